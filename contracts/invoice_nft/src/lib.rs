@@ -1,7 +1,27 @@
 #![no_std]
-use soroban_sdk::{contract, contractimpl, contracttype, Address, Env, Symbol, Vec, BytesN, Val, symbol_short};
+use soroban_sdk::{contract, contractimpl, contracttype, token, xdr::ToXdr, Address, Bytes, Env, IntoVal, Map, Symbol, Vec, BytesN, Val, TryFromVal, symbol_short};
 
 mod tests;
+use tests::Error;
+#[cfg(any(test, feature = "testutils"))]
+pub mod testutils;
+
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum InvoiceStatus {
+    Active,  // Open and freely transferable
+    Repaid,  // Settled by the debtor
+    Expired, // Past its due date without being repaid (derived, never stored)
+    Locked,  // Held as collateral by the lending pool
+    Seized,  // Collateral taken after the backed loan was liquidated
+}
+
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SigScheme {
+    Ed25519,   // Backend keys are 32-byte ed25519 public keys
+    Secp256k1, // Backend keys are 65-byte uncompressed secp256k1 public keys, signing the SHA-256 of the payload
+}
 
 #[contracttype]
 #[derive(Clone)]
@@ -10,16 +30,68 @@ pub struct Invoice {
     pub owner: Address,
     pub amount: i128,
     pub due_date: u64,
-    pub is_repaid: bool,
+    pub status: InvoiceStatus,
+    pub paid_amount: i128, // Total paid so far; the invoice is repaid once this reaches amount
+    pub issuer: Address,   // Original minter, paid the transfer fee on secondary sales
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InvoiceItem {
+    pub amount: i128,
+    pub due_date: u64,
+    pub risk_score: u32,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Tranches {
+    pub senior_holder: Address, // Paid first, up to senior_amount
+    pub junior_holder: Address, // Receives whatever is paid beyond senior_amount
+    pub senior_amount: i128,
 }
 
 #[contracttype]
 pub enum DataKey {
     Invoice(u64), // Maps ID -> Invoice
     TokenId,      // Tracks the next available ID
-    BackendPubkey, // Backend public key for signature verification
+    BackendPubkeys, // Maps key index -> authorized backend public key
+    NextBackendIndex, // Tracks the next available backend key index
+    Admin,        // Contract administrator
+    Paused,       // Contract pause state
+    TokenAddress, // Token used to settle invoice repayments
+    TransfersEnabled, // Whether repay moves tokens or only records repayment
+    RepaymentSink, // Optional recipient of repayments instead of the invoice owner
+    LendingPool,  // Lending pool allowed to seize invoices it holds as collateral
+    MinInvoiceAmount, // Smallest invoice amount that can be minted
+    MaxInvoiceAmount, // Largest invoice amount that can be minted, 0 = unlimited
+    ExternalRef(BytesN<32>), // Maps backend's external receivable reference -> invoice ID
+    TransferFeeBps, // Share of face value a buyer pays the original issuer on transfer, in basis points
+    MintNonce(Address), // Maps user -> nonce their next mint authorization must be signed over
+    SigScheme,    // Signature scheme backend messages are verified under
+    Secp256k1Pubkeys, // Maps key index -> authorized secp256k1 backend public key
+    MaxMintsPerWindow, // Most mints one backend key may authorize per window, 0 = unlimited
+    MintWindow,   // Length of a mint rate limit window, in seconds
+    MintCount(u32, u64), // Maps (backend key index, window number) -> mints authorized in that window
+    Minter(Address), // Marks addresses allowed to mint without a backend signature
+    Tranches(u64), // Maps invoice ID -> senior and junior holders of its repayment
 }
 
+// Instance storage TTL target, in ledgers (approx 30 days)
+const TTL_EXTENSION: u32 = 535_680;
+
+// Maximum number of ids accepted by batch lookups, to bound gas
+const MAX_BATCH_SIZE: u32 = 50;
+
+// Short message for each error code, so clients can read the mapping from the contract instead of keeping their own
+const ERROR_MESSAGES: [(Error, &str); 5] = [
+    (Error::InvoiceNotFound, "invoice_not_found"),
+    (Error::InvoiceExpired, "invoice_expired"),
+    (Error::InvalidSignature, "invalid_signature"),
+    (Error::AlreadyRepaid, "already_repaid"),
+    (Error::Unauthorized, "unauthorized"),
+];
+
 #[contract]
 pub struct InvoiceContract;
 
@@ -28,57 +100,518 @@ impl InvoiceContract {
     // Helper function to extend storage TTL
     fn extend_storage_ttl(env: &Env) {
         // Extend TTL to 535,680 ledgers (approx 30 days)
-        env.storage().instance().extend_ttl(535_680, 535_680);
+        env.storage().instance().extend_ttl(TTL_EXTENSION, TTL_EXTENSION);
+    }
+
+    // BUMP TTL: Anyone (e.g. a keeper) can keep the contract instance from being archived
+    pub fn bump_ttl(env: Env) {
+        Self::extend_storage_ttl(&env);
+        env.events().publish((Symbol::new(&env, "ttl_bumped"),), TTL_EXTENSION);
+    }
+
+    // GET TTL INFO: Number of ledgers each TTL extension targets
+    pub fn get_ttl_info(_env: Env) -> u32 {
+        TTL_EXTENSION
+    }
+
+    // ERROR MESSAGE: Short symbol describing an error code, unknown_error for codes the contract doesn't define
+    pub fn error_message(env: Env, code: u32) -> Symbol {
+        let message = ERROR_MESSAGES.iter()
+            .find(|(error, _)| *error as u32 == code)
+            .map_or("unknown_error", |(_, message)| *message);
+        Symbol::new(&env, message)
+    }
+
+    // INITIALIZE: Set the contract administrator and the settlement token
+    pub fn init(env: Env, admin: Address, token_address: Address) {
+        if env.storage().instance().has(&DataKey::Admin) {
+            panic!("Already initialized");
+        }
+        env.storage().instance().set(&DataKey::Admin, &admin);
+        env.storage().instance().set(&DataKey::TokenAddress, &token_address);
+        env.storage().instance().set(&DataKey::TransfersEnabled, &true);
+        env.storage().instance().set(&DataKey::Paused, &false);
+        Self::extend_storage_ttl(&env);
+    }
+
+    // SET TRANSFERS ENABLED: Opt in or out of moving tokens on repay (admin only)
+    pub fn set_transfers_enabled(env: Env, enabled: bool) {
+        Self::require_admin(&env);
+        let old = Self::transfers_enabled(env.clone());
+        env.storage().instance().set(&DataKey::TransfersEnabled, &enabled);
+        Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "transfers_enabled", old, enabled);
+    }
+
+    // GET TRANSFERS ENABLED: Check whether repay moves tokens
+    pub fn transfers_enabled(env: Env) -> bool {
+        env.storage().instance().get(&DataKey::TransfersEnabled).unwrap_or(false)
+    }
+
+    // SET TRANSFER FEE: Share of an invoice's face value the buyer pays its original issuer on transfer (admin only)
+    pub fn set_transfer_fee(env: Env, fee_bps: u32) {
+        Self::require_admin(&env);
+        if fee_bps > 10_000 {
+            panic!("Invalid transfer fee");
+        }
+        let old = Self::get_transfer_fee(env.clone());
+        env.storage().instance().set(&DataKey::TransferFeeBps, &fee_bps);
+        Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "transfer_fee_bps", old, fee_bps);
+    }
+
+    // GET TRANSFER FEE: Returns the transfer fee in basis points
+    pub fn get_transfer_fee(env: Env) -> u32 {
+        env.storage().instance().get(&DataKey::TransferFeeBps).unwrap_or(0)
+    }
+
+    // SET REPAYMENT SINK: Route repayments to a fixed account instead of the invoice owner (admin only)
+    pub fn set_repayment_sink(env: Env, sink: Address) {
+        Self::require_admin(&env);
+        let old: Option<Address> = env.storage().instance().get(&DataKey::RepaymentSink);
+        env.storage().instance().set(&DataKey::RepaymentSink, &sink);
+        Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "repayment_sink", old, Some(sink));
+    }
+
+    // Helper function to announce an admin setting change with its old and new values, topics ("config_changed", name)
+    fn config_changed<T>(env: &Env, name: &str, old: T, new: T)
+    where
+        Val: TryFromVal<Env, T>,
+    {
+        env.events().publish((Symbol::new(env, "config_changed"), Symbol::new(env, name)), (old, new));
     }
 
     // Helper function to check admin authorization
     fn require_admin(env: &Env) {
-        let admin: Address = env.storage().instance().get(&DataKey::BackendPubkey)
-            .expect("Backend pubkey not set");
+        let admin: Address = env.storage().instance().get(&DataKey::Admin).expect("Not initialized");
         admin.require_auth();
     }
 
-    // SET BACKEND PUBKEY: Initialize backend public key for signature verification
-    pub fn set_backend_pubkey(env: Env, pubkey: BytesN<32>) {
-        // For simplicity, we'll allow anyone to set this initially
-        // In production, this should be admin-only
-        env.storage().instance().set(&DataKey::BackendPubkey, &pubkey);
+    // Helper function to check if contract is paused
+    fn check_paused(env: &Env) {
+        if env.storage().instance().get(&DataKey::Paused).unwrap_or(false) {
+            panic!("CONTRACT_PAUSED");
+        }
+    }
+
+    // PAUSE CONTROL: Set contract pause state (admin only)
+    pub fn set_paused(env: Env, paused: bool) {
+        Self::require_admin(&env);
+        env.storage().instance().set(&DataKey::Paused, &paused);
         Self::extend_storage_ttl(&env);
+        env.events().publish((symbol_short!("pause_set"), paused), env.ledger().sequence());
     }
 
-    // Helper function to verify backend signature
-    fn verify_signature(env: &Env, user: &Address, amount: i128, risk_score: u32, signature: &BytesN<64>) -> bool {
-        let backend_pubkey: BytesN<32> = env.storage().instance().get(&DataKey::BackendPubkey)
-            .expect("Backend pubkey not set");
-        
-        // Create message payload: (user_address, invoice_amount, risk_score)
-        let mut payload = Vec::new(&env);
+    // GET PAUSE STATE: Check if contract is paused
+    pub fn is_paused(env: Env) -> bool {
+        env.storage().instance().get(&DataKey::Paused).unwrap_or(false)
+    }
+
+    // SET INVOICE AMOUNT LIMITS: Restrict minting to a regulated size band (admin only, max 0 = unlimited)
+    pub fn set_invoice_amount_limits(env: Env, min_amount: i128, max_amount: i128) {
+        Self::require_admin(&env);
+        if min_amount < 0 || max_amount < 0 || (max_amount > 0 && min_amount > max_amount) {
+            panic!("Invalid amount limits");
+        }
+        let old = (Self::get_min_invoice_amount(env.clone()), Self::get_max_invoice_amount(env.clone()));
+        env.storage().instance().set(&DataKey::MinInvoiceAmount, &min_amount);
+        env.storage().instance().set(&DataKey::MaxInvoiceAmount, &max_amount);
+        Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "invoice_amount_limits", old, (min_amount, max_amount));
+    }
+
+    // GET MIN INVOICE AMOUNT: Returns the smallest mintable amount
+    pub fn get_min_invoice_amount(env: Env) -> i128 {
+        env.storage().instance().get(&DataKey::MinInvoiceAmount).unwrap_or(0)
+    }
+
+    // GET MAX INVOICE AMOUNT: Returns the largest mintable amount, 0 = unlimited
+    pub fn get_max_invoice_amount(env: Env) -> i128 {
+        env.storage().instance().get(&DataKey::MaxInvoiceAmount).unwrap_or(0)
+    }
+
+    // ADD BACKEND: Authorize a financing partner's signing key, returns its index (admin only)
+    pub fn add_backend(env: Env, pubkey: BytesN<32>) -> u32 {
+        Self::require_admin(&env);
+        Self::check_nonzero(&pubkey, "Invalid backend key");
+
+        let index: u32 = env.storage().instance().get(&DataKey::NextBackendIndex).unwrap_or(0);
+        let mut backends = Self::get_backends(env.clone());
+        backends.set(index, pubkey);
+
+        env.storage().instance().set(&DataKey::BackendPubkeys, &backends);
+        env.storage().instance().set(&DataKey::NextBackendIndex, &(index + 1));
+        Self::extend_storage_ttl(&env);
+
+        env.events().publish((symbol_short!("backend"), symbol_short!("added")), index);
+        index
+    }
+
+    // ADD SECP256K1 BACKEND: Authorize a secp256k1 signing key, sharing the backend index space (admin only)
+    pub fn add_secp256k1_backend(env: Env, pubkey: BytesN<65>) -> u32 {
+        Self::require_admin(&env);
+        Self::check_nonzero(&pubkey, "Invalid backend key");
+
+        let index: u32 = env.storage().instance().get(&DataKey::NextBackendIndex).unwrap_or(0);
+        let mut backends = Self::get_secp256k1_backends(env.clone());
+        backends.set(index, pubkey);
+
+        env.storage().instance().set(&DataKey::Secp256k1Pubkeys, &backends);
+        env.storage().instance().set(&DataKey::NextBackendIndex, &(index + 1));
+        Self::extend_storage_ttl(&env);
+
+        env.events().publish((symbol_short!("backend"), symbol_short!("added")), index);
+        index
+    }
+
+    // REMOVE BACKEND: Revoke a signing key so it can no longer authorize mints (admin only)
+    pub fn remove_backend(env: Env, index: u32) {
+        Self::require_admin(&env);
+
+        let mut backends = Self::get_backends(env.clone());
+        let mut secp256k1_backends = Self::get_secp256k1_backends(env.clone());
+        if !backends.contains_key(index) && !secp256k1_backends.contains_key(index) {
+            panic!("Backend not found");
+        }
+        backends.remove(index);
+        secp256k1_backends.remove(index);
+
+        env.storage().instance().set(&DataKey::BackendPubkeys, &backends);
+        env.storage().instance().set(&DataKey::Secp256k1Pubkeys, &secp256k1_backends);
+        Self::extend_storage_ttl(&env);
+
+        env.events().publish((symbol_short!("backend"), symbol_short!("removed")), index);
+    }
+
+    // GET BACKENDS: All authorized backend keys by index
+    pub fn get_backends(env: Env) -> Map<u32, BytesN<32>> {
+        env.storage().instance().get(&DataKey::BackendPubkeys).unwrap_or(Map::new(&env))
+    }
+
+    // GET SECP256K1 BACKENDS: All authorized secp256k1 backend keys by index
+    pub fn get_secp256k1_backends(env: Env) -> Map<u32, BytesN<65>> {
+        env.storage().instance().get(&DataKey::Secp256k1Pubkeys).unwrap_or(Map::new(&env))
+    }
+
+    // ADD MINTER: Let an account or contract (e.g. a trusted issuer) mint directly, without backend signatures (admin only)
+    pub fn add_minter(env: Env, minter: Address) {
+        Self::require_admin(&env);
+        env.storage().instance().set(&DataKey::Minter(minter.clone()), &true);
+        Self::extend_storage_ttl(&env);
+        env.events().publish((symbol_short!("minter"), symbol_short!("added")), minter);
+    }
+
+    // REMOVE MINTER: Revoke an address's minting role (admin only)
+    pub fn remove_minter(env: Env, minter: Address) {
+        Self::require_admin(&env);
+        env.storage().instance().remove(&DataKey::Minter(minter.clone()));
+        Self::extend_storage_ttl(&env);
+        env.events().publish((symbol_short!("minter"), symbol_short!("removed")), minter);
+    }
+
+    // IS MINTER: Check whether an address holds the minting role
+    pub fn is_minter(env: Env, minter: Address) -> bool {
+        env.storage().instance().has(&DataKey::Minter(minter))
+    }
+
+    // SET MINT RATE LIMIT: Cap the mints any one backend key can authorize per window of seconds (admin only, 0 = unlimited)
+    pub fn set_mint_rate_limit(env: Env, max_mints: u32, window: u64) {
+        Self::require_admin(&env);
+        if max_mints > 0 && window == 0 {
+            panic!("Invalid mint window");
+        }
+        let old = Self::get_mint_rate_limit(env.clone());
+        env.storage().instance().set(&DataKey::MaxMintsPerWindow, &max_mints);
+        env.storage().instance().set(&DataKey::MintWindow, &window);
+        Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "mint_rate_limit", old, (max_mints, window));
+    }
+
+    // GET MINT RATE LIMIT: The (max mints, window seconds) pair, (0, 0) when unlimited
+    pub fn get_mint_rate_limit(env: Env) -> (u32, u64) {
+        (
+            env.storage().instance().get(&DataKey::MaxMintsPerWindow).unwrap_or(0),
+            env.storage().instance().get(&DataKey::MintWindow).unwrap_or(0),
+        )
+    }
+
+    // GET MINT COUNT: Mints a backend key has authorized in the current window
+    pub fn get_mint_count(env: Env, key_index: u32) -> u32 {
+        match Self::current_mint_window(&env) {
+            Some(window) => env.storage().temporary().get(&DataKey::MintCount(key_index, window)).unwrap_or(0),
+            None => 0,
+        }
+    }
+
+    // Helper function for the number of the current rate limit window, None when mints are unlimited
+    fn current_mint_window(env: &Env) -> Option<u64> {
+        let (max_mints, window) = Self::get_mint_rate_limit(env.clone());
+        if max_mints == 0 {
+            return None;
+        }
+        Some(env.ledger().timestamp() / window)
+    }
+
+    // Helper function to count a mint against its backend key, rejecting it once the window's cap is reached
+    fn count_backend_mint(env: &Env, key_index: u32) {
+        let window = match Self::current_mint_window(env) {
+            Some(window) => window,
+            None => return,
+        };
+        if Self::mint_limit_reached(env, key_index) {
+            panic!("Backend mint limit reached");
+        }
+        let count = Self::get_mint_count(env.clone(), key_index);
+
+        // Counts only matter for their own window, so they can expire rather than pile up in instance storage
+        let key = DataKey::MintCount(key_index, window);
+        env.storage().temporary().set(&key, &(count + 1));
+        env.storage().temporary().extend_ttl(&key, TTL_EXTENSION, TTL_EXTENSION);
+    }
+
+    // Helper function to check whether a backend key has used up the current window's mints
+    fn mint_limit_reached(env: &Env, key_index: u32) -> bool {
+        let (max_mints, _) = Self::get_mint_rate_limit(env.clone());
+        Self::current_mint_window(env).is_some() && Self::get_mint_count(env.clone(), key_index) >= max_mints
+    }
+
+    // SET SIG SCHEME: Choose the signature scheme backend messages are verified under (admin only)
+    pub fn set_sig_scheme(env: Env, scheme: SigScheme) {
+        Self::require_admin(&env);
+        let old = Self::get_sig_scheme(env.clone());
+        env.storage().instance().set(&DataKey::SigScheme, &scheme);
+        Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "sig_scheme", old, scheme);
+    }
+
+    // GET SIG SCHEME: The configured signature scheme, ed25519 unless changed
+    pub fn get_sig_scheme(env: Env) -> SigScheme {
+        env.storage().instance().get(&DataKey::SigScheme).unwrap_or(SigScheme::Ed25519)
+    }
+
+    // Helper function to build the message the backend signs: (user_address, invoice_amount, due_date, risk_score, nonce[, external_ref])
+    fn signature_payload(env: &Env, user: &Address, amount: i128, due_date: u64, risk_score: u32, nonce: u64, external_ref: &Option<BytesN<32>>) -> Bytes {
+        let mut payload: Vec<Val> = Vec::new(env);
         payload.push_back(user.to_val());
-        payload.push_back(amount.to_val());
-        payload.push_back(risk_score.to_val());
-        
-        let message = payload.to_val();
-        env.crypto().ed25519_verify(&backend_pubkey, &message, signature)
+        payload.push_back(amount.into_val(env));
+        payload.push_back(due_date.into_val(env));
+        payload.push_back(risk_score.into_val(env));
+        payload.push_back(nonce.into_val(env));
+        if let Some(external_ref) = external_ref {
+            payload.push_back(external_ref.to_val());
+        }
+        payload.to_xdr(env)
+    }
+
+    // Helper function to build the message the backend signs to authorize a batch mint: ("batch", user_address, nonce, items)
+    fn batch_payload(env: &Env, user: &Address, nonce: u64, items: &Vec<InvoiceItem>) -> Bytes {
+        let mut payload: Vec<Val> = Vec::new(env);
+        payload.push_back(symbol_short!("batch").to_val());
+        payload.push_back(user.to_val());
+        payload.push_back(nonce.into_val(env));
+        payload.push_back(items.to_val());
+        payload.to_xdr(env)
+    }
+
+    // Helper function to build the message the backend signs to revoke a mint authorization: ("revoke", user_address, nonce)
+    fn revoke_payload(env: &Env, user: &Address, nonce: u64) -> Bytes {
+        let mut payload: Vec<Val> = Vec::new(env);
+        payload.push_back(symbol_short!("revoke").to_val());
+        payload.push_back(user.to_val());
+        payload.push_back(nonce.into_val(env));
+        payload.to_xdr(env)
+    }
+
+    // Helper function to build the message the backend signs to attest a payment: (invoice_id, paid_before, amount)
+    // Including the amount already paid means each attestation can only be applied once
+    fn payment_payload(env: &Env, id: u64, paid_before: i128, amount: i128) -> Bytes {
+        let mut payload: Vec<Val> = Vec::new(env);
+        payload.push_back(id.into_val(env));
+        payload.push_back(paid_before.into_val(env));
+        payload.push_back(amount.into_val(env));
+        payload.to_xdr(env)
+    }
+
+    // Helper function to reject all-zero keys and signatures, which only ever come from unset or placeholder values
+    fn check_nonzero<const N: usize>(bytes: &BytesN<N>, error: &str) {
+        if bytes.iter().all(|byte| byte == 0) {
+            panic!("{}", error);
+        }
+    }
+
+    // Helper function to verify a signature from the backend registered at key_index
+    // ed25519_verify traps the invocation with a crypto error if the signature does not match
+    #[allow(clippy::too_many_arguments)]
+    fn verify_signature(env: &Env, user: &Address, amount: i128, due_date: u64, risk_score: u32, external_ref: &Option<BytesN<32>>, key_index: u32, signature: &BytesN<64>) {
+        let nonce = Self::get_mint_nonce(env.clone(), user.clone());
+        let message = Self::signature_payload(env, user, amount, due_date, risk_score, nonce, external_ref);
+        Self::verify_backend_message(env, &message, key_index, signature);
+    }
+
+    // Helper function to verify any backend-signed message against the key registered at key_index, under the configured scheme
+    fn verify_backend_message(env: &Env, message: &Bytes, key_index: u32, signature: &BytesN<64>) {
+        if let Some(error) = Self::backend_message_error(env, message, key_index, signature) {
+            panic!("{}", error);
+        }
+    }
+
+    // Helper function to find why a backend-signed message would be rejected, None if it verifies
+    // The key and placeholder checks run first; an ed25519 mismatch then traps inside ed25519_verify by design,
+    // as the host offers no non-trapping ed25519 check
+    fn backend_message_error(env: &Env, message: &Bytes, key_index: u32, signature: &BytesN<64>) -> Option<&'static str> {
+        // Fail with a clear error before the crypto host function traps on an obvious placeholder
+        if signature.iter().all(|byte| byte == 0) {
+            return Some("Empty signature");
+        }
+        match Self::get_sig_scheme(env.clone()) {
+            SigScheme::Ed25519 => {
+                let Some(backend_pubkey) = Self::get_backends(env.clone()).get(key_index) else {
+                    return Some("Unknown backend key");
+                };
+                env.crypto().ed25519_verify(&backend_pubkey, message, signature);
+                None
+            }
+            SigScheme::Secp256k1 => {
+                let Some(backend_pubkey) = Self::get_secp256k1_backends(env.clone()).get(key_index) else {
+                    return Some("Unknown backend key");
+                };
+                // The signature carries no recovery id, so accept it if either candidate key is the backend's
+                let digest = env.crypto().sha256(message);
+                let signed_by_backend = (0..2).any(|recovery_id| {
+                    env.crypto().secp256k1_recover(&digest, signature, recovery_id) == backend_pubkey
+                });
+                if signed_by_backend { None } else { Some("Invalid signature") }
+            }
+        }
     }
 
     // 1. MINT: Create a new Invoice NFT with signature verification
-    pub fn mint(env: Env, owner: Address, amount: i128, due_date: u64, risk_score: u32, signature: BytesN<64>) -> u64 {
+    #[allow(clippy::too_many_arguments)]
+    pub fn mint(env: Env, owner: Address, amount: i128, due_date: u64, risk_score: u32, external_ref: Option<BytesN<32>>, key_index: u32, signature: BytesN<64>) -> u64 {
+        Self::check_paused(&env);
         owner.require_auth(); // Ensure the caller is who they say they are
 
+        Self::check_invoice_terms(&env, amount, due_date);
+
+        // A retried mint for the same real-world receivable must not create a second invoice
+        if let Some(external_ref) = &external_ref {
+            if env.storage().instance().has(&DataKey::ExternalRef(external_ref.clone())) {
+                panic!("Duplicate external reference");
+            }
+        }
+
+        // Verify backend signature, then consume the nonce so it can't be replayed
+        Self::verify_signature(&env, &owner, amount, due_date, risk_score, &external_ref, key_index, &signature);
+        Self::bump_mint_nonce(&env, &owner);
+        Self::count_backend_mint(&env, key_index);
+
+        let current_id = Self::store_new_invoice(&env, &owner, amount, due_date);
+        if let Some(external_ref) = external_ref {
+            env.storage().instance().set(&DataKey::ExternalRef(external_ref), &current_id);
+        }
+        Self::extend_storage_ttl(&env);
+
+        current_id
+    }
+
+    // VERIFY MINT SIGNATURE: Dry-run mint's checks on a backend signature without minting or consuming the nonce
+    // Returns false wherever mint would reject, pause and the backend's rate limit included, except an
+    // ed25519 signature mismatch, which traps as it does in mint since the host's ed25519 check cannot return
+    #[allow(clippy::too_many_arguments)]
+    pub fn verify_mint_signature(env: Env, owner: Address, amount: i128, due_date: u64, risk_score: u32, external_ref: Option<BytesN<32>>, key_index: u32, signature: BytesN<64>) -> bool {
+        if Self::is_paused(env.clone()) || Self::invoice_terms_error(&env, amount, due_date).is_some() {
+            return false;
+        }
+        if let Some(external_ref) = &external_ref {
+            if env.storage().instance().has(&DataKey::ExternalRef(external_ref.clone())) {
+                return false;
+            }
+        }
+        if Self::mint_limit_reached(&env, key_index) {
+            return false;
+        }
+        let nonce = Self::get_mint_nonce(env.clone(), owner.clone());
+        let message = Self::signature_payload(&env, &owner, amount, due_date, risk_score, nonce, &external_ref);
+        Self::backend_message_error(&env, &message, key_index, &signature).is_none()
+    }
+
+    // MINT AS MINTER: Create an invoice for an owner on a minter's own authority, in place of a backend signature
+    pub fn mint_as_minter(env: Env, minter: Address, owner: Address, amount: i128, due_date: u64, risk_score: u32) -> u64 {
+        Self::check_paused(&env);
+        minter.require_auth();
+        if !Self::is_minter(env.clone(), minter.clone()) {
+            panic!("Not a minter");
+        }
+        Self::check_invoice_terms(&env, amount, due_date);
+
+        let current_id = Self::store_new_invoice(&env, &owner, amount, due_date);
+        Self::extend_storage_ttl(&env);
+
+        // Record which minter vouched for the invoice, since no backend signature did
+        env.events().publish((symbol_short!("minted_by"), minter), (current_id, risk_score));
+        current_id
+    }
+
+    // MINT BATCH: Create one invoice per line item, all under a single backend signature over the whole batch
+    pub fn mint_batch(env: Env, owner: Address, items: Vec<InvoiceItem>, key_index: u32, signature: BytesN<64>) -> Vec<u64> {
+        Self::check_paused(&env);
+        owner.require_auth();
+
+        if items.is_empty() || items.len() > MAX_BATCH_SIZE {
+            panic!("Invalid batch size");
+        }
+        for item in items.iter() {
+            Self::check_invoice_terms(&env, item.amount, item.due_date);
+        }
+
+        // One signature and one nonce cover every item, so no item can be altered, dropped or replayed
+        let nonce = Self::get_mint_nonce(env.clone(), owner.clone());
+        let message = Self::batch_payload(&env, &owner, nonce, &items);
+        Self::verify_backend_message(&env, &message, key_index, &signature);
+        Self::bump_mint_nonce(&env, &owner);
+
+        let mut ids = Vec::new(&env);
+        for item in items.iter() {
+            Self::count_backend_mint(&env, key_index);
+            ids.push_back(Self::store_new_invoice(&env, &owner, item.amount, item.due_date));
+        }
+        Self::extend_storage_ttl(&env);
+        ids
+    }
+
+    // Helper function to reject expired invoices and amounts outside the configured size band
+    fn check_invoice_terms(env: &Env, amount: i128, due_date: u64) {
+        if let Some(error) = Self::invoice_terms_error(env, amount, due_date) {
+            panic!("{}", error);
+        }
+    }
+
+    // Helper function to find why an invoice's terms would be rejected, None if they are acceptable
+    fn invoice_terms_error(env: &Env, amount: i128, due_date: u64) -> Option<&'static str> {
         // Check if invoice is expired
         let current_timestamp = env.ledger().timestamp();
         if due_date <= current_timestamp {
-            panic!("INVOICE_EXPIRED");
+            return Some("INVOICE_EXPIRED");
         }
 
-        // Verify backend signature
-        if !Self::verify_signature(&env, &owner, amount, risk_score, &signature) {
-            panic!("INVALID_SIGNATURE");
+        // Only finance invoices within the configured size band
+        if amount <= 0 {
+            return Some("Invalid invoice amount");
+        }
+        let max_amount = Self::get_max_invoice_amount(env.clone());
+        if amount < Self::get_min_invoice_amount(env.clone()) || (max_amount > 0 && amount > max_amount) {
+            return Some("Invoice amount out of range");
         }
+        None
+    }
 
+    // Helper function to save a freshly minted invoice under the next id and announce it
+    fn store_new_invoice(env: &Env, owner: &Address, amount: i128, due_date: u64) -> u64 {
         // Get the current ID count
-        let mut current_id = env.storage().instance().get(&DataKey::TokenId).unwrap_or(0u64);
-        current_id += 1;
+        let current_id = env.storage().instance().get(&DataKey::TokenId).unwrap_or(0u64)
+            .checked_add(1)
+            .expect("Counter overflow");
 
         // Create the invoice object
         let invoice = Invoice {
@@ -86,37 +619,339 @@ impl InvoiceContract {
             owner: owner.clone(),
             amount,
             due_date,
-            is_repaid: false,
+            status: InvoiceStatus::Active,
+            paid_amount: 0,
+            issuer: owner.clone(),
         };
 
         // Save to storage
         env.storage().instance().set(&DataKey::Invoice(current_id), &invoice);
         env.storage().instance().set(&DataKey::TokenId, &current_id);
-        Self::extend_storage_ttl(&env);
 
         // Emit an event (so our API can see it later)
-        env.events().publish((symbol_short!("mint"), owner), current_id);
+        env.events().publish((symbol_short!("mint"), owner.clone()), current_id);
 
         current_id
     }
 
+    // GET MINT NONCE: Nonce the user's next mint authorization must be signed over
+    pub fn get_mint_nonce(env: Env, user: Address) -> u64 {
+        env.storage().instance().get(&DataKey::MintNonce(user)).unwrap_or(0)
+    }
+
+    // Helper function to move a user on to their next mint nonce
+    fn bump_mint_nonce(env: &Env, user: &Address) {
+        let nonce = Self::get_mint_nonce(env.clone(), user.clone());
+        env.storage().instance().set(&DataKey::MintNonce(user.clone()), &(nonce + 1));
+    }
+
+    // REVOKE MINT: Invalidate a user's outstanding mint authorization, on the backend's signature
+    pub fn revoke_mint(env: Env, user: Address, nonce: u64, key_index: u32, signature: BytesN<64>) {
+        if nonce != Self::get_mint_nonce(env.clone(), user.clone()) {
+            panic!("Invalid nonce");
+        }
+
+        let message = Self::revoke_payload(&env, &user, nonce);
+        Self::verify_backend_message(&env, &message, key_index, &signature);
+
+        Self::bump_mint_nonce(&env, &user);
+        Self::extend_storage_ttl(&env);
+
+        env.events().publish((symbol_short!("revoked"), user), nonce);
+    }
+
+    // INVOICE ID FOR REF: Look up the invoice minted for an external reference
+    pub fn invoice_id_for_ref(env: Env, external_ref: BytesN<32>) -> Option<u64> {
+        env.storage().instance().get(&DataKey::ExternalRef(external_ref))
+    }
+
     // 2. GET: Read invoice details
     pub fn get_invoice(env: Env, id: u64) -> Option<Invoice> {
         env.storage().instance().get(&DataKey::Invoice(id))
     }
 
-    // 3. REPAY: Mark the invoice as paid
-    pub fn repay(env: Env, id: u64) {
+    // OWNER OF: Just the current owner of an invoice, like ERC-721 ownerOf
+    pub fn owner_of(env: Env, id: u64) -> Option<Address> {
+        Self::get_invoice(env, id).map(|invoice| invoice.owner)
+    }
+
+    // GET DUE DATE: Just an invoice's maturity, for callers that don't decode the full Invoice
+    pub fn get_due_date(env: Env, id: u64) -> u64 {
+        let invoice: Invoice = env.storage().instance().get(&DataKey::Invoice(id)).expect("Invoice not found");
+        invoice.due_date
+    }
+
+    // GET AMOUNT: Just an invoice's face amount, for callers that don't decode the full Invoice
+    pub fn get_amount(env: Env, id: u64) -> i128 {
+        let invoice: Invoice = env.storage().instance().get(&DataKey::Invoice(id)).expect("Invoice not found");
+        invoice.amount
+    }
+
+    // SET LENDING POOL: Link the lending pool that may seize collateral (admin only)
+    pub fn set_lending_pool(env: Env, lending_pool: Address) {
+        Self::require_admin(&env);
+        let old = Self::get_lending_pool(env.clone());
+        env.storage().instance().set(&DataKey::LendingPool, &lending_pool);
+        Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "lending_pool", old, Some(lending_pool));
+    }
+
+    // GET LENDING POOL: The linked lending pool, if any
+    pub fn get_lending_pool(env: Env) -> Option<Address> {
+        env.storage().instance().get(&DataKey::LendingPool)
+    }
+
+    // GET INVOICE STATUS: Current status, reporting unpaid active invoices past their due date as Expired
+    pub fn get_invoice_status(env: Env, id: u64) -> InvoiceStatus {
+        let invoice: Invoice = env.storage().instance().get(&DataKey::Invoice(id)).expect("Invoice not found");
+        if invoice.status == InvoiceStatus::Active && env.ledger().timestamp() > invoice.due_date {
+            return InvoiceStatus::Expired;
+        }
+        invoice.status
+    }
+
+    // IS LOCKED: Whether the invoice currently backs a loan and so can't be transferred
+    pub fn is_locked(env: Env, id: u64) -> bool {
+        let invoice: Invoice = env.storage().instance().get(&DataKey::Invoice(id)).expect("Invoice not found");
+        invoice.status == InvoiceStatus::Locked
+    }
+
+    // Helper function to check the linked lending pool authorized the call
+    fn require_lending_pool(env: &Env) {
+        let lending_pool: Address = env.storage().instance().get(&DataKey::LendingPool)
+            .expect("Lending pool not set");
+        lending_pool.require_auth();
+    }
+
+    // LOCK INVOICE: Hold an active invoice as loan collateral for its owner (linked lending pool only)
+    pub fn lock_invoice(env: Env, id: u64, owner: Address) {
+        Self::require_lending_pool(&env);
+
+        let invoice: Invoice = env.storage().instance().get(&DataKey::Invoice(id)).expect("Invoice not found");
+        if invoice.owner != owner {
+            panic!("Not invoice owner");
+        }
+        if Self::get_invoice_status(env.clone(), id) != InvoiceStatus::Active {
+            panic!("Invoice not available");
+        }
+        if env.storage().instance().has(&DataKey::Tranches(id)) {
+            panic!("Invoice tranched");
+        }
+        Self::set_status(&env, id, InvoiceStatus::Locked);
+        env.events().publish((symbol_short!("locked"),), id);
+    }
+
+    // UNLOCK INVOICE: Release collateral once its loan is repaid (linked lending pool only)
+    pub fn unlock_invoice(env: Env, id: u64) {
+        Self::require_lending_pool(&env);
+
+        let invoice: Invoice = env.storage().instance().get(&DataKey::Invoice(id)).expect("Invoice not found");
+        if invoice.status != InvoiceStatus::Locked {
+            panic!("Invoice not locked");
+        }
+        Self::set_status(&env, id, InvoiceStatus::Active);
+        env.events().publish((symbol_short!("unlocked"),), id);
+    }
+
+    // MARK SEIZED: Flag a locked invoice whose loan was liquidated (linked lending pool only)
+    pub fn mark_seized(env: Env, id: u64) {
+        Self::require_lending_pool(&env);
+
+        let invoice: Invoice = env.storage().instance().get(&DataKey::Invoice(id)).expect("Invoice not found");
+        match invoice.status {
+            InvoiceStatus::Locked => {}
+            InvoiceStatus::Seized => panic!("Invoice already seized"),
+            _ => panic!("Invoice not locked"),
+        }
+        Self::set_status(&env, id, InvoiceStatus::Seized);
+        env.events().publish((symbol_short!("seized"), invoice.owner), id);
+    }
+
+    // REASSIGN INVOICE: Move a locked invoice to the borrower its loan was assigned to (linked lending pool only)
+    pub fn reassign_invoice(env: Env, id: u64, new_owner: Address) {
+        Self::require_lending_pool(&env);
+
         let mut invoice: Invoice = env.storage().instance().get(&DataKey::Invoice(id)).expect("Invoice not found");
-        
-        invoice.owner.require_auth(); // Only the owner can repay
+        if invoice.status != InvoiceStatus::Locked {
+            panic!("Invoice not locked");
+        }
+        let previous = invoice.owner.clone();
+        invoice.owner = new_owner.clone();
+        env.storage().instance().set(&DataKey::Invoice(id), &invoice);
+        Self::extend_storage_ttl(&env);
+
+        env.events().publish((symbol_short!("transfer"), previous, new_owner), id);
+    }
+
+    // Helper function to persist a status transition
+    fn set_status(env: &Env, id: u64, status: InvoiceStatus) {
+        let mut invoice: Invoice = env.storage().instance().get(&DataKey::Invoice(id)).expect("Invoice not found");
+        invoice.status = status;
+        env.storage().instance().set(&DataKey::Invoice(id), &invoice);
+        Self::extend_storage_ttl(env);
+    }
+
+    // TRANSFER: Hand an invoice to a new owner, unless it is locked or closed
+    pub fn transfer(env: Env, from: Address, to: Address, id: u64) {
+        Self::check_paused(&env);
+        from.require_auth();
+
+        let mut invoice: Invoice = env.storage().instance().get(&DataKey::Invoice(id)).expect("Invoice not found");
+        if invoice.owner != from {
+            panic!("Not invoice owner");
+        }
+        if env.storage().instance().has(&DataKey::Tranches(id)) {
+            panic!("Invoice tranched");
+        }
+
+        match invoice.status {
+            InvoiceStatus::Active => {}
+            InvoiceStatus::Locked => panic!("Invoice locked"),
+            _ => panic!("Invoice not transferable"),
+        }
+
+        // The buyer pays the original issuer a cut, unless the issuer is buying it back
+        let fee = invoice.amount * Self::get_transfer_fee(env.clone()) as i128 / 10_000;
+        if fee > 0 && to != invoice.issuer {
+            to.require_auth();
+            let token_addr: Address = env.storage().instance().get(&DataKey::TokenAddress).expect("Not initialized");
+            token::Client::new(&env, &token_addr).transfer(&to, &invoice.issuer, &fee);
+            env.events().publish((symbol_short!("xfer_fee"), invoice.issuer.clone()), (id, fee));
+        }
+
+        invoice.owner = to.clone();
+        env.storage().instance().set(&DataKey::Invoice(id), &invoice);
+        Self::extend_storage_ttl(&env);
+
+        env.events().publish((symbol_short!("transfer"), from, to), id);
+    }
+
+    // SPLIT TRANCHES: Assign an invoice's repayment to a senior holder, paid first up to senior_amount, and a junior holder
+    // taking the rest (owner only). A tranched invoice can no longer be transferred or pledged as collateral
+    pub fn split_tranches(env: Env, id: u64, senior_holder: Address, junior_holder: Address, senior_amount: i128) {
+        Self::check_paused(&env);
+        let invoice: Invoice = env.storage().instance().get(&DataKey::Invoice(id)).expect("Invoice not found");
+        invoice.owner.require_auth();
+
+        if Self::get_invoice_status(env.clone(), id) != InvoiceStatus::Active {
+            panic!("Invoice not available");
+        }
+        if env.storage().instance().has(&DataKey::Tranches(id)) {
+            panic!("Invoice already tranched");
+        }
+        if senior_amount <= 0 || senior_amount > invoice.amount {
+            panic!("Invalid senior amount");
+        }
+
+        let tranches = Tranches { senior_holder, junior_holder, senior_amount };
+        env.storage().instance().set(&DataKey::Tranches(id), &tranches);
+        Self::extend_storage_ttl(&env);
+
+        env.events().publish((symbol_short!("tranched"), invoice.owner), (id, tranches));
+    }
+
+    // GET TRANCHES: An invoice's senior and junior holders, None if it hasn't been split
+    pub fn get_tranches(env: Env, id: u64) -> Option<Tranches> {
+        env.storage().instance().get(&DataKey::Tranches(id))
+    }
+
+    // BATCH GET: Read several invoices at once, None for ids that don't exist
+    pub fn get_invoices(env: Env, ids: Vec<u64>) -> Vec<Option<Invoice>> {
+        if ids.len() > MAX_BATCH_SIZE {
+            panic!("Batch too large");
+        }
+        let mut invoices = Vec::new(&env);
+        for id in ids.iter() {
+            invoices.push_back(env.storage().instance().get(&DataKey::Invoice(id)));
+        }
+        invoices
+    }
+
+    // 3. REPAY: Settle the invoice and mark it as paid
+    pub fn repay(env: Env, payer: Address, id: u64) {
+        Self::check_paused(&env);
+        let mut invoice: Invoice = env.storage().instance().get(&DataKey::Invoice(id)).expect("Invoice not found");
+
+        match invoice.status {
+            InvoiceStatus::Repaid => panic!("Invoice already repaid"),
+            InvoiceStatus::Seized => panic!("Invoice seized"),
+            InvoiceStatus::Locked => panic!("Invoice locked"),
+            _ => {}
+        }
+
+        if Self::transfers_enabled(env.clone()) {
+            payer.require_auth();
+
+            let token_addr: Address = env.storage().instance().get(&DataKey::TokenAddress).expect("Not initialized");
+            let token_client = token::Client::new(&env, &token_addr);
+            let outstanding = invoice.amount - invoice.paid_amount;
+            match Self::get_tranches(env.clone(), id) {
+                // Tranche holders own the cash flows, so they are paid directly: senior first, then the residual
+                Some(tranches) => {
+                    let senior_due = (tranches.senior_amount - invoice.paid_amount).clamp(0, outstanding);
+                    if senior_due > 0 {
+                        token_client.transfer(&payer, &tranches.senior_holder, &senior_due);
+                    }
+                    if outstanding > senior_due {
+                        token_client.transfer(&payer, &tranches.junior_holder, &(outstanding - senior_due));
+                    }
+                }
+                None => {
+                    let recipient: Address = env.storage().instance().get(&DataKey::RepaymentSink)
+                        .unwrap_or(invoice.owner.clone());
+                    token_client.transfer(&payer, &recipient, &outstanding);
+                }
+            }
+        } else {
+            // Without on-chain settlement only the owner can attest the invoice was paid
+            invoice.owner.require_auth();
+        }
 
-        // (In a real app, we would transfer USDC here. For MVP, we just flip the switch.)
-        invoice.is_repaid = true;
+        invoice.paid_amount = invoice.amount;
+        invoice.status = InvoiceStatus::Repaid;
 
         env.storage().instance().set(&DataKey::Invoice(id), &invoice);
         Self::extend_storage_ttl(&env);
         
         env.events().publish((symbol_short!("repay"), invoice.owner), id);
     }
+
+    // RECORD PAYMENT: Apply a backend-attested partial payment from the debtor
+    pub fn record_payment(env: Env, id: u64, amount: i128, key_index: u32, signature: BytesN<64>) {
+        Self::check_paused(&env);
+        let mut invoice: Invoice = env.storage().instance().get(&DataKey::Invoice(id)).expect("Invoice not found");
+
+        match invoice.status {
+            InvoiceStatus::Repaid => panic!("Invoice already repaid"),
+            InvoiceStatus::Seized => panic!("Invoice seized"),
+            InvoiceStatus::Locked => panic!("Invoice locked"),
+            _ => {}
+        }
+
+        if amount <= 0 {
+            panic!("Invalid payment amount");
+        }
+
+        let message = Self::payment_payload(&env, id, invoice.paid_amount, amount);
+        Self::verify_backend_message(&env, &message, key_index, &signature);
+
+        invoice.paid_amount += amount;
+        if invoice.paid_amount >= invoice.amount {
+            invoice.status = InvoiceStatus::Repaid;
+        }
+
+        env.storage().instance().set(&DataKey::Invoice(id), &invoice);
+        Self::extend_storage_ttl(&env);
+
+        env.events().publish((symbol_short!("payment"), invoice.owner), (id, amount));
+    }
+
+    // GET OUTSTANDING: Amount still owed on an invoice
+    pub fn get_outstanding(env: Env, id: u64) -> i128 {
+        let invoice: Invoice = env.storage().instance().get(&DataKey::Invoice(id)).expect("Invoice not found");
+        if invoice.paid_amount >= invoice.amount {
+            return 0;
+        }
+        invoice.amount - invoice.paid_amount
+    }
 }
\ No newline at end of file
@@ -1,5 +1,4 @@
 use soroban_sdk::contracterror;
-use crate::InvoiceContract;
 
 #[contracterror]
 #[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
@@ -13,95 +12,1095 @@ pub enum Error {
 }
 
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests {
-    use super::*;
-    use soroban_sdk::{testutils::Address as TestAddress, testutils::Bytes as TestBytes, Bytes};
-    use soroban_sdk::contractclient::InvoiceContractClient;
+    extern crate std;
+
+    use crate::{DataKey, Invoice, InvoiceContract, InvoiceContractClient, InvoiceItem, InvoiceStatus, SigScheme, Tranches};
+    use ed25519_dalek::{Signer, SigningKey};
+    use k256::ecdsa::SigningKey as Secp256k1SigningKey;
+    use soroban_sdk::{testutils::{storage::Instance as _, Address as _, Events, Ledger}, token, vec, Address, BytesN, Env, IntoVal, Symbol, TryFromVal, Vec};
+
+    // Registers a deterministic backend keypair, returning it with its key index
+    fn add_backend_key(env: &Env, client: &InvoiceContractClient, seed: u8) -> (SigningKey, u32) {
+        let signing_key = SigningKey::from_bytes(&[seed; 32]);
+        let index = client.add_backend(&BytesN::from_array(env, &signing_key.verifying_key().to_bytes()));
+        (signing_key, index)
+    }
+
+    // Initializes the contract with a single backend key for signing mints
+    fn setup_backend(env: &Env, client: &InvoiceContractClient, seed: u8) -> (SigningKey, u32) {
+        client.init(&Address::generate(env), &Address::generate(env));
+        add_backend_key(env, client, seed)
+    }
+
+    // Signs the same payload the contract verifies in mint, over the owner's current nonce
+    fn sign_mint(env: &Env, client: &InvoiceContractClient, signing_key: &SigningKey, owner: &Address, amount: i128, due_date: u64, risk_score: u32) -> BytesN<64> {
+        let nonce = client.get_mint_nonce(owner);
+        let payload = InvoiceContract::signature_payload(env, owner, amount, due_date, risk_score, nonce, &None);
+        let message: std::vec::Vec<u8> = payload.iter().collect();
+        BytesN::from_array(env, &signing_key.sign(&message).to_bytes())
+    }
+
+    // Signs a mint payload that carries an external receivable reference
+    #[allow(clippy::too_many_arguments)]
+    fn sign_mint_with_ref(env: &Env, client: &InvoiceContractClient, signing_key: &SigningKey, owner: &Address, amount: i128, due_date: u64, risk_score: u32, external_ref: &BytesN<32>) -> BytesN<64> {
+        let nonce = client.get_mint_nonce(owner);
+        let payload = InvoiceContract::signature_payload(env, owner, amount, due_date, risk_score, nonce, &Some(external_ref.clone()));
+        let message: std::vec::Vec<u8> = payload.iter().collect();
+        BytesN::from_array(env, &signing_key.sign(&message).to_bytes())
+    }
+
+    // Signs a mint payload with a secp256k1 backend key, over the payload's SHA-256 digest
+    fn sign_mint_secp256k1(env: &Env, client: &InvoiceContractClient, signing_key: &Secp256k1SigningKey, owner: &Address, amount: i128, due_date: u64, risk_score: u32) -> BytesN<64> {
+        let nonce = client.get_mint_nonce(owner);
+        let payload = InvoiceContract::signature_payload(env, owner, amount, due_date, risk_score, nonce, &None);
+        let digest = env.crypto().sha256(&payload).to_array();
+        let (signature, _) = signing_key.sign_prehash_recoverable(&digest).unwrap();
+        BytesN::from_array(env, &signature.to_bytes().into())
+    }
+
+    // Registers a deterministic secp256k1 backend key by its uncompressed public key
+    fn add_secp256k1_backend_key(env: &Env, client: &InvoiceContractClient, seed: u8) -> (Secp256k1SigningKey, u32) {
+        let signing_key = Secp256k1SigningKey::from_slice(&[seed; 32]).unwrap();
+        let pubkey: [u8; 65] = signing_key.verifying_key().to_encoded_point(false).as_bytes().try_into().unwrap();
+        let index = client.add_secp256k1_backend(&BytesN::from_array(env, &pubkey));
+        (signing_key, index)
+    }
+
+    // Signs a payment attestation the way record_payment verifies it
+    fn sign_payment(env: &Env, signing_key: &SigningKey, id: u64, paid_before: i128, amount: i128) -> BytesN<64> {
+        let payload = InvoiceContract::payment_payload(env, id, paid_before, amount);
+        let message: std::vec::Vec<u8> = payload.iter().collect();
+        BytesN::from_array(env, &signing_key.sign(&message).to_bytes())
+    }
 
     #[test]
     fn test_mint_invoice_success() {
         let env = Env::default();
+        env.mock_all_auths();
         let contract_id = env.register_contract(None, InvoiceContract);
         let client = InvoiceContractClient::new(&env, &contract_id);
 
         let owner = Address::generate(&env);
-        let backend_pubkey = [1u8; 32];
-        client.set_backend_pubkey(&backend_pubkey);
+        let (backend_key, key_index) = setup_backend(&env, &client, 1);
 
-        // Create a valid signature (mock)
-        let signature = [2u8; 64];
-        
+        // Sign with the real backend key so mint exercises ed25519 verification
         let due_date = env.ledger().timestamp() + 86400; // Tomorrow
-        let invoice_id = client.mint(&owner, &1000, &due_date, &750, &signature);
+        let signature = sign_mint(&env, &client, &backend_key, &owner, 1000, due_date, 750);
+        let invoice_id = client.mint(&owner, &1000, &due_date, &750, &None, &key_index, &signature);
 
         let invoice = client.get_invoice(&invoice_id).unwrap();
         assert_eq!(invoice.owner, owner);
         assert_eq!(invoice.amount, 1000);
         assert_eq!(invoice.due_date, due_date);
-        assert!(!invoice.is_repaid);
+        assert_eq!(invoice.status, InvoiceStatus::Active);
     }
 
     #[test]
     #[should_panic(expected = "INVOICE_EXPIRED")]
     fn test_mint_expired_invoice() {
         let env = Env::default();
+        env.mock_all_auths();
         let contract_id = env.register_contract(None, InvoiceContract);
         let client = InvoiceContractClient::new(&env, &contract_id);
 
         let owner = Address::generate(&env);
-        let backend_pubkey = [1u8; 32];
-        client.set_backend_pubkey(&backend_pubkey);
+        let (backend_key, key_index) = setup_backend(&env, &client, 1);
 
-        let signature = [2u8; 64];
+        env.ledger().with_mut(|li| li.timestamp = 2 * 86400);
         let past_date = env.ledger().timestamp() - 86400; // Yesterday
+        let signature = sign_mint(&env, &client, &backend_key, &owner, 1000, past_date, 750);
 
-        client.mint(&owner, &1000, &past_date, &750, &signature);
+        client.mint(&owner, &1000, &past_date, &750, &None, &key_index, &signature);
     }
 
     #[test]
-    #[should_panic(expected = "INVALID_SIGNATURE")]
     fn test_mint_invalid_signature() {
         let env = Env::default();
+        env.mock_all_auths();
         let contract_id = env.register_contract(None, InvoiceContract);
         let client = InvoiceContractClient::new(&env, &contract_id);
 
         let owner = Address::generate(&env);
-        let backend_pubkey = [1u8; 32];
-        client.set_backend_pubkey(&backend_pubkey);
+        let (_, key_index) = setup_backend(&env, &client, 1);
+        let due_date = env.ledger().timestamp() + 86400;
+
+        // Garbage bytes
+        let invalid_signature = BytesN::from_array(&env, &[99u8; 64]);
+        assert!(client.try_mint(&owner, &1000, &due_date, &750, &None, &key_index, &invalid_signature).is_err());
 
-        let invalid_signature = [99u8; 64]; // Invalid signature
+        // Signed by a key other than the registered backend
+        let other_key = SigningKey::from_bytes(&[2u8; 32]);
+        let foreign_signature = sign_mint(&env, &client, &other_key, &owner, 1000, due_date, 750);
+        assert!(client.try_mint(&owner, &1000, &due_date, &750, &None, &key_index, &foreign_signature).is_err());
+    }
+
+    #[test]
+    fn test_mint_tampered_payload() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InvoiceContract);
+        let client = InvoiceContractClient::new(&env, &contract_id);
+
+        let owner = Address::generate(&env);
+        let (backend_key, key_index) = setup_backend(&env, &client, 1);
         let due_date = env.ledger().timestamp() + 86400;
 
-        client.mint(&owner, &1000, &due_date, &750, &invalid_signature);
+        // The backend approved 1000, the user tries to mint 5000
+        let signature = sign_mint(&env, &client, &backend_key, &owner, 1000, due_date, 750);
+        assert!(client.try_mint(&owner, &5000, &due_date, &750, &None, &key_index, &signature).is_err());
+
+        // Someone else cannot reuse the owner's approval
+        let attacker = Address::generate(&env);
+        assert!(client.try_mint(&attacker, &1000, &due_date, &750, &None, &key_index, &signature).is_err());
+
+        // Nor can the owner push the approved maturity out
+        let later_due = due_date + 30 * 86400;
+        assert!(client.try_mint(&owner, &1000, &later_due, &750, &None, &key_index, &signature).is_err());
     }
 
     #[test]
     fn test_repay_invoice() {
         let env = Env::default();
+        env.mock_all_auths();
         let contract_id = env.register_contract(None, InvoiceContract);
         let client = InvoiceContractClient::new(&env, &contract_id);
 
         let owner = Address::generate(&env);
-        let backend_pubkey = [1u8; 32];
-        client.set_backend_pubkey(&backend_pubkey);
-
-        let signature = [2u8; 64];
+        let (backend_key, key_index) = setup_backend(&env, &client, 1);
         let due_date = env.ledger().timestamp() + 86400;
-        let invoice_id = client.mint(&owner, &1000, &due_date, &750, &signature);
 
-        client.repay(&invoice_id);
+        let signature = sign_mint(&env, &client, &backend_key, &owner, 1000, due_date, 750);
+        let invoice_id = client.mint(&owner, &1000, &due_date, &750, &None, &key_index, &signature);
+
+        // Owner-attested repayment, no settlement token involved
+        client.set_transfers_enabled(&false);
+        client.repay(&owner, &invoice_id);
 
         let invoice = client.get_invoice(&invoice_id).unwrap();
-        assert!(invoice.is_repaid);
+        assert_eq!(invoice.status, InvoiceStatus::Repaid);
     }
 
     #[test]
     #[should_panic(expected = "Invoice not found")]
     fn test_repay_nonexistent_invoice() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InvoiceContract);
+        let client = InvoiceContractClient::new(&env, &contract_id);
+
+        client.repay(&Address::generate(&env), &999);
+    }
+
+    #[test]
+    #[should_panic(expected = "CONTRACT_PAUSED")]
+    fn test_mint_when_paused() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InvoiceContract);
+        let client = InvoiceContractClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        client.init(&admin, &Address::generate(&env));
+        client.set_paused(&true);
+        assert!(client.is_paused());
+
+        let owner = Address::generate(&env);
+        let signature = BytesN::from_array(&env, &[2u8; 64]);
+        let due_date = env.ledger().timestamp() + 86400;
+        client.mint(&owner, &1000, &due_date, &750, &None, &0, &signature);
+    }
+
+    #[test]
+    fn test_bump_ttl() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InvoiceContract);
+        let client = InvoiceContractClient::new(&env, &contract_id);
+
+        let ttl_before = env.as_contract(&contract_id, || env.storage().instance().get_ttl());
+        assert!(ttl_before < client.get_ttl_info());
+
+        client.bump_ttl();
+
+        let ttl_after = env.as_contract(&contract_id, || env.storage().instance().get_ttl());
+        assert_eq!(ttl_after, client.get_ttl_info());
+
+        let (_, topics, data) = env.events().all().last().unwrap();
+        assert_eq!(topics, (Symbol::new(&env, "ttl_bumped"),).into_val(&env));
+        assert_eq!(u32::try_from_val(&env, &data).unwrap(), client.get_ttl_info());
+    }
+
+    // Stores an invoice directly so repayment can be tested independently of minting
+    fn seed_invoice(env: &Env, contract_id: &Address, owner: &Address, amount: i128) -> u64 {
+        let invoice = Invoice {
+            id: 1,
+            owner: owner.clone(),
+            amount,
+            due_date: env.ledger().timestamp() + 86400,
+            status: InvoiceStatus::Active,
+            paid_amount: 0,
+            issuer: owner.clone(),
+        };
+        env.as_contract(contract_id, || {
+            env.storage().instance().set(&DataKey::Invoice(1), &invoice);
+        });
+        1
+    }
+
+    #[test]
+    fn test_repay_transfers_tokens_to_owner() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InvoiceContract);
+        let client = InvoiceContractClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+        assert!(client.transfers_enabled());
+
+        let owner = Address::generate(&env);
+        let payer = Address::generate(&env);
+        token::StellarAssetClient::new(&env, &token_address).mint(&payer, &1500);
+        let invoice_id = seed_invoice(&env, &contract_id, &owner, 1000);
+
+        client.repay(&payer, &invoice_id);
+
+        let token_client = token::Client::new(&env, &token_address);
+        assert_eq!(token_client.balance(&owner), 1000);
+        assert_eq!(token_client.balance(&payer), 500);
+        assert_eq!(client.get_invoice(&invoice_id).unwrap().status, InvoiceStatus::Repaid);
+    }
+
+    #[test]
+    fn test_repay_transfers_tokens_to_sink() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InvoiceContract);
+        let client = InvoiceContractClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+        let sink = Address::generate(&env);
+        client.set_repayment_sink(&sink);
+
+        let owner = Address::generate(&env);
+        let payer = Address::generate(&env);
+        token::StellarAssetClient::new(&env, &token_address).mint(&payer, &1000);
+        let invoice_id = seed_invoice(&env, &contract_id, &owner, 1000);
+
+        client.repay(&payer, &invoice_id);
+
+        let token_client = token::Client::new(&env, &token_address);
+        assert_eq!(token_client.balance(&sink), 1000);
+        assert_eq!(token_client.balance(&owner), 0);
+        assert_eq!(client.get_invoice(&invoice_id).unwrap().status, InvoiceStatus::Repaid);
+    }
+
+    #[test]
+    fn test_repay_without_transfers() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InvoiceContract);
+        let client = InvoiceContractClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+        client.set_transfers_enabled(&false);
+
+        // The payer holds no tokens, so this only succeeds because nothing moves
+        let owner = Address::generate(&env);
+        let payer = Address::generate(&env);
+        let invoice_id = seed_invoice(&env, &contract_id, &owner, 1000);
+
+        client.repay(&payer, &invoice_id);
+
+        assert_eq!(token::Client::new(&env, &token_address).balance(&owner), 0);
+        assert_eq!(client.get_invoice(&invoice_id).unwrap().status, InvoiceStatus::Repaid);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invoice already repaid")]
+    fn test_repay_twice() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InvoiceContract);
+        let client = InvoiceContractClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+
+        let owner = Address::generate(&env);
+        let payer = Address::generate(&env);
+        token::StellarAssetClient::new(&env, &token_address).mint(&payer, &2000);
+        let invoice_id = seed_invoice(&env, &contract_id, &owner, 1000);
+
+        client.repay(&payer, &invoice_id);
+        client.repay(&payer, &invoice_id);
+    }
+
+    #[test]
+    fn test_get_invoices_batch() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InvoiceContract);
+        let client = InvoiceContractClient::new(&env, &contract_id);
+
+        let owner = Address::generate(&env);
+        let (backend_key, key_index) = setup_backend(&env, &client, 1);
+        let due_date = env.ledger().timestamp() + 86400;
+        let first = client.mint(&owner, &1000, &due_date, &750, &None, &key_index, &sign_mint(&env, &client, &backend_key, &owner, 1000, due_date, 750));
+        let second = client.mint(&owner, &2000, &due_date, &750, &None, &key_index, &sign_mint(&env, &client, &backend_key, &owner, 2000, due_date, 750));
+
+        let invoices = client.get_invoices(&vec![&env, second, 999, first]);
+
+        assert_eq!(invoices.len(), 3);
+        assert_eq!(invoices.get(0).unwrap().unwrap().amount, 2000);
+        assert!(invoices.get(1).unwrap().is_none());
+        assert_eq!(invoices.get(2).unwrap().unwrap().amount, 1000);
+    }
+
+    #[test]
+    #[should_panic(expected = "Batch too large")]
+    fn test_get_invoices_batch_too_large() {
+        let env = Env::default();
+        let contract_id = env.register_contract(None, InvoiceContract);
+        let client = InvoiceContractClient::new(&env, &contract_id);
+
+        let mut ids = Vec::new(&env);
+        for id in 0..51u64 {
+            ids.push_back(id);
+        }
+        client.get_invoices(&ids);
+    }
+
+    #[test]
+    fn test_multiple_backend_issuers() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InvoiceContract);
+        let client = InvoiceContractClient::new(&env, &contract_id);
+
+        let (first_key, first_index) = setup_backend(&env, &client, 1);
+        let (second_key, second_index) = add_backend_key(&env, &client, 2);
+        assert_ne!(first_index, second_index);
+        assert_eq!(client.get_backends().len(), 2);
+
+        let owner = Address::generate(&env);
+        let due_date = env.ledger().timestamp() + 86400;
+
+        // Each issuer mints with its own key
+        let first_sig = sign_mint(&env, &client, &first_key, &owner, 1000, due_date, 750);
+        client.mint(&owner, &1000, &due_date, &750, &None, &first_index, &first_sig);
+        let second_sig = sign_mint(&env, &client, &second_key, &owner, 2000, due_date, 600);
+        client.mint(&owner, &2000, &due_date, &600, &None, &second_index, &second_sig);
+
+        // A signature is only valid against the index of the key that made it
+        let first_sig = sign_mint(&env, &client, &first_key, &owner, 3000, due_date, 750);
+        assert!(client.try_mint(&owner, &3000, &due_date, &750, &None, &second_index, &first_sig).is_err());
+
+        // Once removed, the second issuer can no longer authorize mints
+        client.remove_backend(&second_index);
+        assert_eq!(client.get_backends().len(), 1);
+        let second_sig = sign_mint(&env, &client, &second_key, &owner, 4000, due_date, 600);
+        assert!(client.try_mint(&owner, &4000, &due_date, &600, &None, &second_index, &second_sig).is_err());
+
+        // The first issuer is unaffected
+        let first_sig = sign_mint(&env, &client, &first_key, &owner, 5000, due_date, 750);
+        client.mint(&owner, &5000, &due_date, &750, &None, &first_index, &first_sig);
+    }
+
+    #[test]
+    #[should_panic(expected = "Backend not found")]
+    fn test_remove_unknown_backend() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InvoiceContract);
+        let client = InvoiceContractClient::new(&env, &contract_id);
+
+        client.init(&Address::generate(&env), &Address::generate(&env));
+        client.remove_backend(&7);
+    }
+
+    #[test]
+    fn test_mark_seized_by_lending_pool() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InvoiceContract);
+        let client = InvoiceContractClient::new(&env, &contract_id);
+
+        client.init(&Address::generate(&env), &Address::generate(&env));
+        let lending_pool = Address::generate(&env);
+        client.set_lending_pool(&lending_pool);
+        assert_eq!(client.get_lending_pool(), Some(lending_pool.clone()));
+
+        let owner = Address::generate(&env);
+        let invoice_id = seed_invoice(&env, &contract_id, &owner, 1000);
+
+        // Only an invoice locked as collateral can be seized
+        assert!(client.try_mark_seized(&invoice_id).is_err());
+        client.lock_invoice(&invoice_id, &owner);
+        client.mark_seized(&invoice_id);
+        assert_eq!(env.auths()[0].0, lending_pool);
+
+        assert_eq!(client.get_invoice_status(&invoice_id), InvoiceStatus::Seized);
+
+        // A seized invoice can no longer be settled
+        assert!(client.try_repay(&owner, &invoice_id).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "Lending pool not set")]
+    fn test_mark_seized_without_lending_pool() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InvoiceContract);
+        let client = InvoiceContractClient::new(&env, &contract_id);
+
+        client.init(&Address::generate(&env), &Address::generate(&env));
+        let invoice_id = seed_invoice(&env, &contract_id, &Address::generate(&env), 1000);
+        client.mark_seized(&invoice_id);
+    }
+
+    #[test]
+    fn test_invoice_status_transitions() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InvoiceContract);
+        let client = InvoiceContractClient::new(&env, &contract_id);
+
+        client.init(&Address::generate(&env), &Address::generate(&env));
+        client.set_transfers_enabled(&false);
+        client.set_lending_pool(&Address::generate(&env));
+        let owner = Address::generate(&env);
+        let invoice_id = seed_invoice(&env, &contract_id, &owner, 1000);
+        assert_eq!(client.get_invoice_status(&invoice_id), InvoiceStatus::Active);
+
+        // Locking as collateral and releasing it again
+        client.lock_invoice(&invoice_id, &owner);
+        assert_eq!(client.get_invoice_status(&invoice_id), InvoiceStatus::Locked);
+        assert!(client.is_locked(&invoice_id));
+        assert!(client.try_repay(&owner, &invoice_id).is_err());
+        client.unlock_invoice(&invoice_id);
+        assert_eq!(client.get_invoice_status(&invoice_id), InvoiceStatus::Active);
+        assert!(!client.is_locked(&invoice_id));
+
+        // Only the owner's invoice can be pledged on their behalf
+        assert!(client.try_lock_invoice(&invoice_id, &Address::generate(&env)).is_err());
+        assert_eq!(client.get_invoice_status(&invoice_id), InvoiceStatus::Active);
+
+        // Repayment closes the invoice
+        client.repay(&owner, &invoice_id);
+        assert_eq!(client.get_invoice_status(&invoice_id), InvoiceStatus::Repaid);
+        assert!(client.try_lock_invoice(&invoice_id, &owner).is_err());
+    }
+
+    #[test]
+    fn test_invoice_expires_after_due_date() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InvoiceContract);
+        let client = InvoiceContractClient::new(&env, &contract_id);
+
+        client.init(&Address::generate(&env), &Address::generate(&env));
+        client.set_lending_pool(&Address::generate(&env));
+        let owner = Address::generate(&env);
+        let invoice_id = seed_invoice(&env, &contract_id, &owner, 1000);
+        let due_date = client.get_invoice(&invoice_id).unwrap().due_date;
+
+        env.ledger().with_mut(|li| li.timestamp = due_date + 1);
+        assert_eq!(client.get_invoice_status(&invoice_id), InvoiceStatus::Expired);
+
+        // An expired invoice can't be pledged as collateral
+        assert!(client.try_lock_invoice(&invoice_id, &owner).is_err());
+    }
+
+    #[test]
+    fn test_transfer_invoice() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InvoiceContract);
+        let client = InvoiceContractClient::new(&env, &contract_id);
+
+        client.init(&Address::generate(&env), &Address::generate(&env));
+        let owner = Address::generate(&env);
+        let buyer = Address::generate(&env);
+        let invoice_id = seed_invoice(&env, &contract_id, &owner, 1000);
+
+        client.transfer(&owner, &buyer, &invoice_id);
+        assert_eq!(client.get_invoice(&invoice_id).unwrap().owner, buyer);
+
+        // The previous owner no longer controls it
+        assert!(client.try_transfer(&owner, &owner, &invoice_id).is_err());
+    }
+
+    #[test]
+    fn test_transfer_fee_to_issuer() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InvoiceContract);
+        let client = InvoiceContractClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+        client.set_transfer_fee(&200);
+        assert_eq!(client.get_transfer_fee(), 200);
+
+        let issuer = Address::generate(&env);
+        let first_buyer = Address::generate(&env);
+        let second_buyer = Address::generate(&env);
+        let token_admin = token::StellarAssetClient::new(&env, &token_address);
+        token_admin.mint(&first_buyer, &100);
+        token_admin.mint(&second_buyer, &100);
+        let invoice_id = seed_invoice(&env, &contract_id, &issuer, 1000);
+
+        // 2% of the 1000 face value on each sale, always to the original issuer
+        client.transfer(&issuer, &first_buyer, &invoice_id);
+        client.transfer(&first_buyer, &second_buyer, &invoice_id);
+
+        let token_client = token::Client::new(&env, &token_address);
+        assert_eq!(token_client.balance(&issuer), 40);
+        assert_eq!(token_client.balance(&first_buyer), 80);
+        assert_eq!(token_client.balance(&second_buyer), 80);
+
+        let invoice = client.get_invoice(&invoice_id).unwrap();
+        assert_eq!(invoice.owner, second_buyer);
+        assert_eq!(invoice.issuer, issuer);
+        assert!(client.try_set_transfer_fee(&10_001).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "Invoice locked")]
+    fn test_transfer_locked_invoice() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InvoiceContract);
+        let client = InvoiceContractClient::new(&env, &contract_id);
+
+        client.init(&Address::generate(&env), &Address::generate(&env));
+        client.set_lending_pool(&Address::generate(&env));
+        let owner = Address::generate(&env);
+        let invoice_id = seed_invoice(&env, &contract_id, &owner, 1000);
+
+        client.lock_invoice(&invoice_id, &owner);
+        client.transfer(&owner, &Address::generate(&env), &invoice_id);
+    }
+
+    #[test]
+    fn test_token_id_overflow() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InvoiceContract);
+        let client = InvoiceContractClient::new(&env, &contract_id);
+
+        let owner = Address::generate(&env);
+        let (backend_key, key_index) = setup_backend(&env, &client, 1);
+        env.as_contract(&contract_id, || {
+            env.storage().instance().set(&DataKey::TokenId, &(u64::MAX - 1));
+        });
+
+        let due_date = env.ledger().timestamp() + 86400;
+        let signature = sign_mint(&env, &client, &backend_key, &owner, 1000, due_date, 750);
+        assert_eq!(client.mint(&owner, &1000, &due_date, &750, &None, &key_index, &signature), u64::MAX);
+
+        // The counter refuses to wrap back onto existing ids, even with a fresh authorization
+        let signature = sign_mint(&env, &client, &backend_key, &owner, 1000, due_date, 750);
+        assert!(client.try_mint(&owner, &1000, &due_date, &750, &None, &key_index, &signature).is_err());
+        assert_eq!(client.get_invoice(&u64::MAX).unwrap().owner, owner);
+    }
+
+    #[test]
+    fn test_invoice_amount_band() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InvoiceContract);
+        let client = InvoiceContractClient::new(&env, &contract_id);
+
+        let owner = Address::generate(&env);
+        let (backend_key, key_index) = setup_backend(&env, &client, 1);
+        client.set_invoice_amount_limits(&500, &5000);
+        assert_eq!(client.get_min_invoice_amount(), 500);
+        assert_eq!(client.get_max_invoice_amount(), 5000);
+
+        let due_date = env.ledger().timestamp() + 86400;
+        for amount in [500, 5000] {
+            let signature = sign_mint(&env, &client, &backend_key, &owner, amount, due_date, 750);
+            client.mint(&owner, &amount, &due_date, &750, &None, &key_index, &signature);
+        }
+
+        // Validly signed, but outside the band
+        for amount in [499, 5001] {
+            let signature = sign_mint(&env, &client, &backend_key, &owner, amount, due_date, 750);
+            assert!(client.try_mint(&owner, &amount, &due_date, &750, &None, &key_index, &signature).is_err());
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid invoice amount")]
+    fn test_mint_zero_amount() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InvoiceContract);
+        let client = InvoiceContractClient::new(&env, &contract_id);
+
+        // No band configured, zero is still rejected
+        let owner = Address::generate(&env);
+        let (backend_key, key_index) = setup_backend(&env, &client, 1);
+        let due_date = env.ledger().timestamp() + 86400;
+        let signature = sign_mint(&env, &client, &backend_key, &owner, 0, due_date, 750);
+        client.mint(&owner, &0, &due_date, &750, &None, &key_index, &signature);
+    }
+
+    #[test]
+    fn test_partial_payments_settle_invoice() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InvoiceContract);
+        let client = InvoiceContractClient::new(&env, &contract_id);
+
+        let (backend_key, key_index) = setup_backend(&env, &client, 1);
+        let invoice_id = seed_invoice(&env, &contract_id, &Address::generate(&env), 1000);
+
+        let first = sign_payment(&env, &backend_key, invoice_id, 0, 400);
+        client.record_payment(&invoice_id, &400, &key_index, &first);
+        assert_eq!(client.get_outstanding(&invoice_id), 600);
+        assert_eq!(client.get_invoice(&invoice_id).unwrap().paid_amount, 400);
+        assert_eq!(client.get_invoice_status(&invoice_id), InvoiceStatus::Active);
+
+        // The same attestation can't be applied twice
+        assert!(client.try_record_payment(&invoice_id, &400, &key_index, &first).is_err());
+
+        let second = sign_payment(&env, &backend_key, invoice_id, 400, 600);
+        client.record_payment(&invoice_id, &600, &key_index, &second);
+        assert_eq!(client.get_outstanding(&invoice_id), 0);
+        assert_eq!(client.get_invoice_status(&invoice_id), InvoiceStatus::Repaid);
+    }
+
+    #[test]
+    fn test_repay_charges_only_outstanding() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InvoiceContract);
+        let client = InvoiceContractClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+        let (backend_key, key_index) = add_backend_key(&env, &client, 1);
+
+        let owner = Address::generate(&env);
+        let payer = Address::generate(&env);
+        token::StellarAssetClient::new(&env, &token_address).mint(&payer, &1000);
+        let invoice_id = seed_invoice(&env, &contract_id, &owner, 1000);
+
+        let signature = sign_payment(&env, &backend_key, invoice_id, 0, 250);
+        client.record_payment(&invoice_id, &250, &key_index, &signature);
+        client.repay(&payer, &invoice_id);
+
+        let token_client = token::Client::new(&env, &token_address);
+        assert_eq!(token_client.balance(&owner), 750);
+        assert_eq!(token_client.balance(&payer), 250);
+        assert_eq!(client.get_outstanding(&invoice_id), 0);
+    }
+
+    #[test]
+    fn test_duplicate_external_ref_rejected() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InvoiceContract);
+        let client = InvoiceContractClient::new(&env, &contract_id);
+
+        let (backend_key, key_index) = setup_backend(&env, &client, 1);
+        let owner = Address::generate(&env);
+        let due_date = env.ledger().timestamp() + 86400;
+        let external_ref = BytesN::from_array(&env, &[7; 32]);
+        assert_eq!(client.invoice_id_for_ref(&external_ref), None);
+
+        let signature = sign_mint_with_ref(&env, &client, &backend_key, &owner, 1000, due_date, 750, &external_ref);
+        let invoice_id = client.mint(&owner, &1000, &due_date, &750, &Some(external_ref.clone()), &key_index, &signature);
+        assert_eq!(client.invoice_id_for_ref(&external_ref), Some(invoice_id));
+
+        // A retried mint for the same receivable is rejected, even when freshly signed
+        assert!(client.try_mint(&owner, &1000, &due_date, &750, &Some(external_ref.clone()), &key_index, &signature).is_err());
+        let retry = sign_mint_with_ref(&env, &client, &backend_key, &owner, 2000, due_date, 750, &external_ref);
+        assert!(client.try_mint(&owner, &2000, &due_date, &750, &Some(external_ref.clone()), &key_index, &retry).is_err());
+
+        // The reference is bound by the signature, so it can't be swapped for another one
+        let other_ref = BytesN::from_array(&env, &[8; 32]);
+        assert!(client.try_mint(&owner, &1000, &due_date, &750, &Some(other_ref.clone()), &key_index, &signature).is_err());
+        assert_eq!(client.invoice_id_for_ref(&other_ref), None);
+    }
+
+    #[test]
+    fn test_revoked_mint_rejected() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InvoiceContract);
+        let client = InvoiceContractClient::new(&env, &contract_id);
+
+        let (backend_key, key_index) = setup_backend(&env, &client, 1);
+        let owner = Address::generate(&env);
+        let due_date = env.ledger().timestamp() + 86400;
+        assert_eq!(client.get_mint_nonce(&owner), 0);
+
+        // The backend authorizes a mint, then the deal falls through
+        let signature = sign_mint(&env, &client, &backend_key, &owner, 1000, due_date, 750);
+        let payload = InvoiceContract::revoke_payload(&env, &owner, 0);
+        let message: std::vec::Vec<u8> = payload.iter().collect();
+        let revocation = BytesN::from_array(&env, &backend_key.sign(&message).to_bytes());
+        client.revoke_mint(&owner, &0, &key_index, &revocation);
+        assert_eq!(client.get_mint_nonce(&owner), 1);
+
+        assert!(client.try_mint(&owner, &1000, &due_date, &750, &None, &key_index, &signature).is_err());
+
+        // The revocation itself can't be replayed, and new authorizations still work
+        assert!(client.try_revoke_mint(&owner, &0, &key_index, &revocation).is_err());
+        let fresh = sign_mint(&env, &client, &backend_key, &owner, 1000, due_date, 750);
+        client.mint(&owner, &1000, &due_date, &750, &None, &key_index, &fresh);
+        assert_eq!(client.get_mint_nonce(&owner), 2);
+    }
+
+    #[test]
+    fn test_mint_under_each_sig_scheme() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InvoiceContract);
+        let client = InvoiceContractClient::new(&env, &contract_id);
+
+        let (ed25519_key, ed25519_index) = setup_backend(&env, &client, 1);
+        let (secp256k1_key, secp256k1_index) = add_secp256k1_backend_key(&env, &client, 2);
+        let owner = Address::generate(&env);
+        let due_date = env.ledger().timestamp() + 86400;
+        assert_eq!(client.get_sig_scheme(), SigScheme::Ed25519);
+
+        let signature = sign_mint(&env, &client, &ed25519_key, &owner, 1000, due_date, 750);
+        client.mint(&owner, &1000, &due_date, &750, &None, &ed25519_index, &signature);
+
+        client.set_sig_scheme(&SigScheme::Secp256k1);
+        assert_eq!(client.get_sig_scheme(), SigScheme::Secp256k1);
+        let signature = sign_mint_secp256k1(&env, &client, &secp256k1_key, &owner, 1000, due_date, 750);
+        let invoice_id = client.mint(&owner, &1000, &due_date, &750, &None, &secp256k1_index, &signature);
+        assert_eq!(client.get_invoice(&invoice_id).unwrap().owner, owner);
+    }
+
+    #[test]
+    fn test_sig_scheme_mismatch_rejected() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InvoiceContract);
+        let client = InvoiceContractClient::new(&env, &contract_id);
+
+        let (ed25519_key, ed25519_index) = setup_backend(&env, &client, 1);
+        let (secp256k1_key, secp256k1_index) = add_secp256k1_backend_key(&env, &client, 2);
+        let owner = Address::generate(&env);
+        let due_date = env.ledger().timestamp() + 86400;
+
+        // A secp256k1 signature doesn't pass ed25519 verification, whichever key it is checked against
+        let secp256k1_signature = sign_mint_secp256k1(&env, &client, &secp256k1_key, &owner, 1000, due_date, 750);
+        assert!(client.try_mint(&owner, &1000, &due_date, &750, &None, &ed25519_index, &secp256k1_signature).is_err());
+        assert!(client.try_mint(&owner, &1000, &due_date, &750, &None, &secp256k1_index, &secp256k1_signature).is_err());
+
+        // And once secp256k1 is configured, ed25519 signatures stop working
+        client.set_sig_scheme(&SigScheme::Secp256k1);
+        let ed25519_signature = sign_mint(&env, &client, &ed25519_key, &owner, 1000, due_date, 750);
+        assert!(client.try_mint(&owner, &1000, &due_date, &750, &None, &ed25519_index, &ed25519_signature).is_err());
+        assert!(client.try_mint(&owner, &1000, &due_date, &750, &None, &secp256k1_index, &ed25519_signature).is_err());
+
+        // A secp256k1 signature by a different key is rejected too
+        let (other_key, _) = add_secp256k1_backend_key(&env, &client, 3);
+        let forged = sign_mint_secp256k1(&env, &client, &other_key, &owner, 1000, due_date, 750);
+        assert!(client.try_mint(&owner, &1000, &due_date, &750, &None, &secp256k1_index, &forged).is_err());
+        assert_eq!(client.get_mint_nonce(&owner), 0);
+    }
+
+    #[test]
+    fn test_mint_rate_limit_per_backend_key() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InvoiceContract);
+        let client = InvoiceContractClient::new(&env, &contract_id);
+
+        let (backend_key, key_index) = setup_backend(&env, &client, 1);
+        let (other_key, other_index) = add_backend_key(&env, &client, 2);
+        let hour = 3600;
+        client.set_mint_rate_limit(&2, &hour);
+        assert_eq!(client.get_mint_rate_limit(), (2, hour));
+
+        let owner = Address::generate(&env);
+        let due_date = env.ledger().timestamp() + 86400;
+        for _ in 0..2 {
+            let signature = sign_mint(&env, &client, &backend_key, &owner, 1000, due_date, 750);
+            client.mint(&owner, &1000, &due_date, &750, &None, &key_index, &signature);
+        }
+        assert_eq!(client.get_mint_count(&key_index), 2);
+
+        // The key is capped for the rest of the hour, while other keys are unaffected
+        let signature = sign_mint(&env, &client, &backend_key, &owner, 1000, due_date, 750);
+        assert!(client.try_mint(&owner, &1000, &due_date, &750, &None, &key_index, &signature).is_err());
+        let other_signature = sign_mint(&env, &client, &other_key, &owner, 1000, due_date, 750);
+        client.mint(&owner, &1000, &due_date, &750, &None, &other_index, &other_signature);
+        assert_eq!(client.get_mint_count(&other_index), 1);
+
+        // Once the window rolls over the key can authorize mints again
+        env.ledger().with_mut(|li| li.timestamp += hour);
+        assert_eq!(client.get_mint_count(&key_index), 0);
+        let signature = sign_mint(&env, &client, &backend_key, &owner, 1000, due_date, 750);
+        client.mint(&owner, &1000, &due_date, &750, &None, &key_index, &signature);
+        assert_eq!(client.get_mint_count(&key_index), 1);
+    }
+
+    #[test]
+    fn test_owner_of() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InvoiceContract);
+        let client = InvoiceContractClient::new(&env, &contract_id);
+
+        let (backend_key, key_index) = setup_backend(&env, &client, 1);
+        let owner = Address::generate(&env);
+        let due_date = env.ledger().timestamp() + 86400;
+        let signature = sign_mint(&env, &client, &backend_key, &owner, 1000, due_date, 750);
+        let invoice_id = client.mint(&owner, &1000, &due_date, &750, &None, &key_index, &signature);
+
+        assert_eq!(client.owner_of(&invoice_id), Some(owner.clone()));
+        assert_eq!(client.owner_of(&(invoice_id + 1)), None);
+
+        // Ownership follows transfers
+        let buyer = Address::generate(&env);
+        client.transfer(&owner, &buyer, &invoice_id);
+        assert_eq!(client.owner_of(&invoice_id), Some(buyer));
+    }
+
+    #[test]
+    fn test_all_zero_key_and_signature_rejected() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InvoiceContract);
+        let client = InvoiceContractClient::new(&env, &contract_id);
+
+        let (_, key_index) = setup_backend(&env, &client, 1);
+        assert!(client.try_add_backend(&BytesN::from_array(&env, &[0u8; 32])).is_err());
+        assert!(client.try_add_secp256k1_backend(&BytesN::from_array(&env, &[0u8; 65])).is_err());
+        assert_eq!(client.get_backends().len(), 1);
+
+        let owner = Address::generate(&env);
+        let due_date = env.ledger().timestamp() + 86400;
+        let empty_signature = BytesN::from_array(&env, &[0u8; 64]);
+        assert!(client.try_mint(&owner, &1000, &due_date, &750, &None, &key_index, &empty_signature).is_err());
+        assert_eq!(client.get_mint_nonce(&owner), 0);
+    }
+
+    #[test]
+    fn test_mint_batch() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InvoiceContract);
+        let client = InvoiceContractClient::new(&env, &contract_id);
+
+        let (backend_key, key_index) = setup_backend(&env, &client, 1);
+        let owner = Address::generate(&env);
+        let now = env.ledger().timestamp();
+        let items = vec![
+            &env,
+            InvoiceItem { amount: 1000, due_date: now + 86400, risk_score: 750 },
+            InvoiceItem { amount: 2500, due_date: now + 2 * 86400, risk_score: 700 },
+            InvoiceItem { amount: 400, due_date: now + 3 * 86400, risk_score: 720 },
+        ];
+        let payload = InvoiceContract::batch_payload(&env, &owner, 0, &items);
+        let message: std::vec::Vec<u8> = payload.iter().collect();
+        let signature = BytesN::from_array(&env, &backend_key.sign(&message).to_bytes());
+
+        // A tampered line item no longer matches what the backend signed
+        let mut tampered = items.clone();
+        tampered.set(1, InvoiceItem { amount: 25_000, due_date: now + 2 * 86400, risk_score: 700 });
+        assert!(client.try_mint_batch(&owner, &tampered, &key_index, &signature).is_err());
+
+        let ids = client.mint_batch(&owner, &items, &key_index, &signature);
+        assert_eq!(ids, vec![&env, 1, 2, 3]);
+        for (id, item) in ids.iter().zip(items.iter()) {
+            let invoice = client.get_invoice(&id).unwrap();
+            assert_eq!(invoice.owner, owner);
+            assert_eq!(invoice.amount, item.amount);
+            assert_eq!(invoice.due_date, item.due_date);
+        }
+
+        // The signature was consumed with the owner's nonce
+        assert_eq!(client.get_mint_nonce(&owner), 1);
+        assert!(client.try_mint_batch(&owner, &items, &key_index, &signature).is_err());
+    }
+
+    #[test]
+    fn test_mint_as_minter() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InvoiceContract);
+        let client = InvoiceContractClient::new(&env, &contract_id);
+
+        client.init(&Address::generate(&env), &Address::generate(&env));
+        let minter = Address::generate(&env);
+        let owner = Address::generate(&env);
+        let due_date = env.ledger().timestamp() + 86400;
+
+        // Without the role, and with no signature to fall back on, the mint is rejected
+        assert!(client.try_mint_as_minter(&minter, &owner, &1000, &due_date, &750).is_err());
+
+        client.add_minter(&minter);
+        assert!(client.is_minter(&minter));
+        let invoice_id = client.mint_as_minter(&minter, &owner, &1000, &due_date, &750);
+        assert_eq!(env.auths()[0].0, minter);
+        let invoice = client.get_invoice(&invoice_id).unwrap();
+        assert_eq!(invoice.owner, owner);
+        assert_eq!(invoice.amount, 1000);
+
+        client.remove_minter(&minter);
+        assert!(client.try_mint_as_minter(&minter, &owner, &1000, &due_date, &750).is_err());
+    }
+
+    #[test]
+    fn test_verify_mint_signature() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InvoiceContract);
+        let client = InvoiceContractClient::new(&env, &contract_id);
+
+        let owner = Address::generate(&env);
+        let (backend_key, key_index) = setup_backend(&env, &client, 1);
+        let due_date = env.ledger().timestamp() + 86400;
+
+        // A valid signature checks out without consuming the nonce, so mint still accepts it
+        let signature = sign_mint(&env, &client, &backend_key, &owner, 1000, due_date, 750);
+        assert!(client.verify_mint_signature(&owner, &1000, &due_date, &750, &None, &key_index, &signature));
+        assert_eq!(client.get_mint_nonce(&owner), 0);
+        assert!(!client.verify_mint_signature(&owner, &1000, &0, &750, &None, &key_index, &signature));
+        assert!(!client.verify_mint_signature(&owner, &1000, &due_date, &750, &None, &99, &signature));
+        assert!(!client.verify_mint_signature(&owner, &1000, &due_date, &750, &None, &key_index, &BytesN::from_array(&env, &[0u8; 64])));
+
+        // Pausing or exhausting the backend's window rejects a signature mint would otherwise accept
+        client.set_paused(&true);
+        assert!(!client.verify_mint_signature(&owner, &1000, &due_date, &750, &None, &key_index, &signature));
+        client.set_paused(&false);
+        client.set_mint_rate_limit(&1, &86400);
+        let other = Address::generate(&env);
+        let other_signature = sign_mint(&env, &client, &backend_key, &other, 1000, due_date, 750);
+        client.mint(&other, &1000, &due_date, &750, &None, &key_index, &other_signature);
+        assert!(!client.verify_mint_signature(&owner, &1000, &due_date, &750, &None, &key_index, &signature));
+        client.set_mint_rate_limit(&0, &0);
+
+        // A tampered ed25519 payload traps by design, the same way it fails mint
+        assert!(client.try_verify_mint_signature(&owner, &5000, &due_date, &750, &None, &key_index, &signature).is_err());
+        assert!(client.try_mint(&owner, &5000, &due_date, &750, &None, &key_index, &signature).is_err());
+        client.mint(&owner, &1000, &due_date, &750, &None, &key_index, &signature);
+
+        // Under secp256k1 a mismatch returns false instead
+        client.set_sig_scheme(&SigScheme::Secp256k1);
+        let (secp256k1_key, secp256k1_index) = add_secp256k1_backend_key(&env, &client, 2);
+        let signature = sign_mint_secp256k1(&env, &client, &secp256k1_key, &owner, 1000, due_date, 750);
+        assert!(client.verify_mint_signature(&owner, &1000, &due_date, &750, &None, &secp256k1_index, &signature));
+        assert!(!client.verify_mint_signature(&owner, &5000, &due_date, &750, &None, &secp256k1_index, &signature));
+        client.mint(&owner, &1000, &due_date, &750, &None, &secp256k1_index, &signature);
+        assert!(!client.verify_mint_signature(&owner, &1000, &due_date, &750, &None, &secp256k1_index, &signature));
+    }
+
+    #[test]
+    fn test_tranched_repayment_pays_senior_first() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InvoiceContract);
+        let client = InvoiceContractClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+        let (backend_key, key_index) = add_backend_key(&env, &client, 1);
+
+        let owner = Address::generate(&env);
+        let payer = Address::generate(&env);
+        let senior = Address::generate(&env);
+        let junior = Address::generate(&env);
+        token::StellarAssetClient::new(&env, &token_address).mint(&payer, &2000);
+        let token_client = token::Client::new(&env, &token_address);
+
+        let invoice_id = seed_invoice(&env, &contract_id, &owner, 1000);
+        assert!(client.try_split_tranches(&invoice_id, &senior, &junior, &1001).is_err());
+        client.split_tranches(&invoice_id, &senior, &junior, &800);
+        assert_eq!(client.get_tranches(&invoice_id), Some(Tranches { senior_holder: senior.clone(), junior_holder: junior.clone(), senior_amount: 800 }));
+        assert!(client.try_split_tranches(&invoice_id, &junior, &senior, &800).is_err());
+        assert!(client.try_transfer(&owner, &Address::generate(&env), &invoice_id).is_err());
+
+        // 500 already attested counts toward the senior tranche, so repay tops it up to 800 before the junior sees anything
+        let signature = sign_payment(&env, &backend_key, invoice_id, 0, 500);
+        client.record_payment(&invoice_id, &500, &key_index, &signature);
+        client.repay(&payer, &invoice_id);
+        assert_eq!(token_client.balance(&senior), 300);
+        assert_eq!(token_client.balance(&junior), 200);
+        assert_eq!(token_client.balance(&owner), 0);
+        assert_eq!(client.get_invoice(&invoice_id).unwrap().status, InvoiceStatus::Repaid);
+    }
+
+    #[test]
+    fn test_admin_setters_emit_config_changed() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, InvoiceContract);
+        let client = InvoiceContractClient::new(&env, &contract_id);
+        client.init(&Address::generate(&env), &Address::generate(&env));
+
+        client.set_transfer_fee(&250);
+        let (_, topics, data) = env.events().all().last().unwrap();
+        assert_eq!(topics, (Symbol::new(&env, "config_changed"), Symbol::new(&env, "transfer_fee_bps")).into_val(&env));
+        assert_eq!(<(u32, u32)>::try_from_val(&env, &data).unwrap(), (0, 250));
+
+        client.set_transfers_enabled(&false);
+        let (_, topics, data) = env.events().all().last().unwrap();
+        assert_eq!(topics, (Symbol::new(&env, "config_changed"), Symbol::new(&env, "transfers_enabled")).into_val(&env));
+        assert_eq!(<(bool, bool)>::try_from_val(&env, &data).unwrap(), (true, false));
+
+        client.set_invoice_amount_limits(&100, &10_000);
+        let (_, topics, data) = env.events().all().last().unwrap();
+        assert_eq!(topics, (Symbol::new(&env, "config_changed"), Symbol::new(&env, "invoice_amount_limits")).into_val(&env));
+        assert_eq!(<((i128, i128), (i128, i128))>::try_from_val(&env, &data).unwrap(), ((0, 0), (100, 10_000)));
+
+        client.set_mint_rate_limit(&5, &3600);
+        let (_, topics, data) = env.events().all().last().unwrap();
+        assert_eq!(topics, (Symbol::new(&env, "config_changed"), Symbol::new(&env, "mint_rate_limit")).into_val(&env));
+        assert_eq!(<((u32, u64), (u32, u64))>::try_from_val(&env, &data).unwrap(), ((0, 0), (5, 3600)));
+
+        client.set_sig_scheme(&SigScheme::Secp256k1);
+        let (_, topics, data) = env.events().all().last().unwrap();
+        assert_eq!(topics, (Symbol::new(&env, "config_changed"), Symbol::new(&env, "sig_scheme")).into_val(&env));
+        assert_eq!(<(SigScheme, SigScheme)>::try_from_val(&env, &data).unwrap(), (SigScheme::Ed25519, SigScheme::Secp256k1));
+
+        // Address settings report None until first set
+        let pool = Address::generate(&env);
+        client.set_lending_pool(&pool);
+        let (_, topics, data) = env.events().all().last().unwrap();
+        assert_eq!(topics, (Symbol::new(&env, "config_changed"), Symbol::new(&env, "lending_pool")).into_val(&env));
+        assert_eq!(<(Option<Address>, Option<Address>)>::try_from_val(&env, &data).unwrap(), (None, Some(pool.clone())));
+
+        let sink = Address::generate(&env);
+        client.set_repayment_sink(&sink);
+        let (_, topics, data) = env.events().all().last().unwrap();
+        assert_eq!(topics, (Symbol::new(&env, "config_changed"), Symbol::new(&env, "repayment_sink")).into_val(&env));
+        assert_eq!(<(Option<Address>, Option<Address>)>::try_from_val(&env, &data).unwrap(), (None, Some(sink)));
+    }
+
+    #[test]
+    fn test_error_message_codes() {
         let env = Env::default();
         let contract_id = env.register_contract(None, InvoiceContract);
         let client = InvoiceContractClient::new(&env, &contract_id);
 
-        client.repay(&999);
+        // Codes are part of the public interface, so check the raw numbers clients see
+        assert_eq!(client.error_message(&1), Symbol::new(&env, "invoice_not_found"));
+        assert_eq!(client.error_message(&3), Symbol::new(&env, "invalid_signature"));
+        assert_eq!(client.error_message(&5), Symbol::new(&env, "unauthorized"));
+        assert_eq!(client.error_message(&0), Symbol::new(&env, "unknown_error"));
+        assert_eq!(client.error_message(&6), Symbol::new(&env, "unknown_error"));
     }
 }
@@ -0,0 +1,8 @@
+// Helpers for contracts and tests that need to produce what the invoice contract verifies
+use crate::InvoiceContract;
+use soroban_sdk::{Address, Bytes, Env};
+
+// The exact payload a backend signs to authorize a mint, for the owner's given nonce
+pub fn mint_payload(env: &Env, owner: &Address, amount: i128, due_date: u64, risk_score: u32, nonce: u64) -> Bytes {
+    InvoiceContract::signature_payload(env, owner, amount, due_date, risk_score, nonce, &None)
+}
@@ -1,11 +1,42 @@
 #![no_std]
-use soroban_sdk::{contract, contractimpl, contracttype, token, Address, Env, Symbol, BytesN, symbol_short};
+use soroban_sdk::{contract, contractclient, contractimpl, contracttype, token, xdr::ToXdr, Address, Bytes, Env, Map, Symbol, BytesN, Val, Vec, IntoVal, TryFromVal, symbol_short};
 
 mod tests;
+use tests::Error;
+
+pub mod shares;
+
+#[cfg(any(test, feature = "testutils"))]
+pub mod testutils;
 
 #[contracttype]
 #[derive(Clone)]
 pub struct Loan {
+    pub id: u64,
+    pub borrower: Address,
+    pub invoice_id: u64,
+    pub invoice_amount: i128, // Face value of the collateral invoice
+    pub principal: i128,
+    pub interest: i128,
+    pub remaining_principal: i128, // Principal not yet paid back
+    pub unpaid_interest: i128,     // Interest accrued up to last_payment_time but not yet paid
+    pub last_payment_time: u64,    // Interest accrues on remaining_principal from here
+    pub last_interest_payment: u64, // When accrued interest was last paid off in full
+    pub collateral_claimed: i128,  // Share of invoice_amount already handed to partial liquidators
+    pub collateral_locked: bool,   // Whether the invoice is locked in the linked InvoiceContract for this loan
+    pub start_time: u64,
+    pub due_date: u64,
+    pub is_disbursed: bool,
+    pub is_repaid: bool,
+    pub is_defaulted: bool,
+    pub guarantor: Option<Address>, // Liable for the shortfall if the borrower defaults
+    pub terms_hash: BytesN<32>,     // Hash of the off-chain loan agreement, fixed at creation
+}
+
+// Loan layout written to instance storage by deployments before loans moved to persistent storage
+#[contracttype]
+#[derive(Clone)]
+pub struct LegacyLoan {
     pub id: u64,
     pub borrower: Address,
     pub invoice_id: u64,
@@ -17,6 +48,160 @@ pub struct Loan {
     pub is_defaulted: bool,
 }
 
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BorrowerExposure {
+    pub active_loans: u32,
+    pub outstanding_principal: i128,
+    pub accrued_interest: i128,
+    pub overdue_loans: u32,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RepaymentReceipt {
+    pub principal_paid: i128,
+    pub interest_paid: i128,
+    pub fee_paid: i128, // Early repayment penalty, 0 once the minimum hold period has passed
+    pub total_paid: i128,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RateStep {
+    pub start_offset: u64, // Seconds after the loan starts that this rate takes effect
+    pub rate_bps: u32,     // Annual rate in basis points
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ScheduleEntry {
+    pub due_date: u64,
+    pub principal: i128,
+    pub interest: i128,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LoanSummary {
+    pub id: u64,
+    pub borrower: Address,
+    pub principal: i128,
+    pub interest_paid: i128, // All interest collected over the loan's life
+    pub closed_at: u64,
+}
+
+#[contracttype]
+#[derive(Clone)]
+pub struct LoanView {
+    pub loan: Loan,
+    pub interest_due: i128,     // Whole units of interest owed right now
+    pub carried_fraction: i128, // Sub-unit interest carried between payments, in FRACTION_SCALE parts of a unit
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OwedSnapshot {
+    pub amount: i128,   // Remaining principal plus interest owed when taken
+    pub timestamp: u64, // Ledger time the snapshot was taken
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WiringReport {
+    pub pool_knows_invoice: bool,    // Pool's linked InvoiceContract is the one checked
+    pub invoice_knows_pool: bool,    // InvoiceContract's linked lending pool is this pool
+    pub backend_key_shared: bool,    // Pool's backend key is among the InvoiceContract's backends
+    pub pool_active: bool,           // Pool is not paused
+    pub invoice_active: bool,        // InvoiceContract is not paused
+    pub healthy: bool,               // Every check above passed
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PoolSnapshot {
+    pub admin: Address,
+    pub token: Address,
+    pub invoice_contract: Option<Address>,
+    pub last_loan_id: u64,         // Value of the LoanId counter
+    pub active_loans: u32,         // Number of open loans
+    pub total_outstanding: i128,   // Principal currently lent out
+    pub total_deposits: i128,      // LP deposits not yet withdrawn
+    pub treasury_balance: i128,
+    pub lp_yield: i128,
+    pub lifetime_interest: i128,
+    pub bad_debt: i128,
+    pub config: LendingConfig,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LendingConfig {
+    pub interest_rate_bps: u32,
+    pub compounding_frequency: u32,
+    pub grace_period: u64,
+    pub interest_grace_period: u64,
+    pub pool_cap: i128,
+    pub liquidation_threshold_bps: u32,
+    pub max_ltv_bps: u32,
+    pub min_health_factor_bps: u32,
+    pub min_partial_repayment: i128,
+    pub deterministic_loan_ids: bool,
+    pub protocol_share_bps: u32,
+    pub snapshot_window: u64,
+    pub release_ratio_bps: u32,
+    pub withdraw_cooldown: u64,
+    pub withdraw_queue: bool,
+    pub liquidator_whitelist: bool,
+    pub borrow_fee_bps: u32,
+    pub fee_token: Option<Address>,
+    pub min_hold_period: u64,
+    pub early_repayment_penalty_bps: u32,
+    pub max_total_loans: u32,
+    pub interest_recovery_bps: u32,
+    pub auto_approve_limit: i128,
+    pub early_repay_discount_bps: u32,
+    pub min_liquidation_amount: i128,
+    pub penalty_rate_bps: u32,
+    pub day_count_convention: DayCountConvention,
+    pub pause_flags: PauseFlags,
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PauseFlags {
+    pub deposits: bool,     // LP deposits and withdrawals
+    pub borrows: bool,      // Loan creation and disbursement
+    pub repayments: bool,   // Repayments and collateral release
+    pub liquidations: bool, // Full and partial liquidations, and default sweeps
+}
+
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Permissions {
+    pub can_deposit: bool,   // Not blocked and deposits not paused
+    pub can_borrow: bool,    // Not blocked and borrows not paused
+    pub can_liquidate: bool, // Not blocked, liquidations not paused, and on the whitelist if it is enabled
+    pub is_admin: bool,
+    pub is_blocked: bool,
+}
+
+// Groups of entry points that can be paused independently
+enum Operation {
+    Deposits,
+    Borrows,
+    Repayments,
+    Liquidations,
+}
+
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DayCountConvention {
+    Actual365,    // Rates quoted over a 365-day year
+    Actual360,    // Banker's year of 360 days
+    ActualActual, // Average year of 365.25 days, spreading leap days evenly
+}
+
 #[contracttype]
 pub enum LoanStatus {
     Active,
@@ -28,12 +213,165 @@ pub enum LoanStatus {
 pub enum DataKey {
     Admin,
     TokenAddress, // The address of the USDC token
-    Paused,       // Contract pause state
+    Paused,       // Per-operation pause flags
     Loan(u64),    // Maps ID -> Loan
     LoanId,       // Tracks the next available loan ID
     BackendPubkey, // Backend public key for signature verification
+    TotalOutstanding, // Sum of principal on open loans
+    BorrowerLoans(Address), // Maps borrower -> IDs of every loan they have taken or been assigned
+    InvoiceContract, // Linked InvoiceContract notified when collateral is seized
+    CollateralClaims(u64), // Maps loan ID -> collateral each liquidator has earned through partial liquidations
+    TreasuryBalance, // Interest accrued to the protocol treasury
+    LpYield,      // Interest accrued to liquidity providers
+    NativeToken,  // Whether the pool lends native XLM through its Stellar Asset Contract
+    LpDeposit(Address), // Maps LP -> amount deposited and not yet withdrawn
+    OwedSnapshot(u64), // Maps loan ID -> owed amount cached for an upcoming repay_loan
+    RateSchedule(u64), // Maps loan ID -> stepped rates replacing the flat pool rate
+    LastDeposit(Address), // Maps LP -> timestamp of their most recent deposit
+    ActiveLoans,  // IDs of loans that are neither repaid nor defaulted
+    BadDebt,      // Defaulted principal neither liquidators nor guarantors covered
+    OverdueNotified(u64), // Marks loans whose loan_overdue event has already been emitted
+    AccruedFraction(u64), // Maps loan ID -> sub-unit interest carried past the last payment
+    InterestAdjustment(u64), // Maps loan ID -> admin correction or prepaid extension interest not yet settled
+    TotalDeposits, // Sum of LP deposits not yet withdrawn
+    DepositSeconds, // Time-weighted deposits (amount x seconds) up to DepositCheckpoint
+    DepositCheckpoint, // Ledger time DepositSeconds was last brought up to date
+    WithdrawQueue, // LPs with a withdrawal waiting for liquidity, oldest first
+    QueuedWithdrawal(Address), // Maps LP -> amount queued, or fulfilled and awaiting claim
+    ReservedLiquidity, // Pool balance set aside for fulfilled withdrawals not yet claimed
+    LifetimeInterest, // Every unit of interest ever collected, never reduced by payouts
+    Reputation(Address), // Maps borrower -> loans repaid on time minus loans defaulted
+    TreasuryDust, // Swept sub-unit interest not yet adding up to a whole unit, in FRACTION_SCALE parts
+    CollectedFees(Address), // Maps fee token -> borrower fees collected in it and not yet withdrawn
+    DefaultedAt(u64), // Maps loan ID -> ledger time it defaulted, after which interest stops accruing
+    InterestPaid(u64), // Maps loan ID -> interest collected on it so far
+    LoanArchive(u64), // Maps loan ID -> summary kept once a repaid loan's full record is removed
+    PendingApproval(u64), // Marks loans above the auto-approve limit that await admin approval
+    SeenBorrower(Address), // Marks addresses that have taken at least one loan
+    UniqueBorrowers, // Number of distinct addresses that have taken a loan
+    BorrowFee(u64), // Maps loan ID -> origination fee charged when it was disbursed
+    LpShares(Address), // Maps LP -> pool shares held
+    TotalShares,  // Pool shares held by all LPs
+    YieldWithdrawn, // LP yield already paid out through withdrawals
+    InvoiceLoan(u64), // Maps invoice ID -> the loan it financed, kept after that loan is archived
+}
+
+// Admin-tunable pool settings, kept apart from DataKey to stay within the contracttype variant limit
+#[contracttype]
+pub enum ConfigKey {
+    PoolCap,      // Maximum pool size (balance + outstanding), 0 = unlimited
+    LiquidationThresholdBps, // Health factor below which a loan can be liquidated, 0 = disabled
+    CompoundingFrequency, // Interest compounding periods per year, 0 = simple interest
+    MinPartialRepayment, // Smallest accepted partial repayment, unless it closes the loan
+    DeterministicLoanIds, // Derive loan ids from a hash instead of the LoanId counter
+    GracePeriod, // Seconds after the due date before an overdue loan can be liquidated
+    EventNamespace, // Optional leading topic on every event, to tell deployments apart
+    ProtocolShareBps, // Portion of collected interest routed to the treasury, in basis points
+    SnapshotWindow, // Seconds an owed snapshot stays valid for repay_loan
+    ReleaseRatioBps, // Outstanding share of principal at or below which collateral can be released
+    InterestGracePeriod, // Seconds interest may go unpaid before a loan can be liquidated, 0 = disabled
+    WithdrawCooldown, // Seconds an LP must wait after depositing before withdrawing
+    DayCountConvention, // Year length interest accrues over
+    Oracle,       // Price source valuing collateral, face amount is used when unset
+    ExchangeRate(Address, Address), // Maps (repay token, loan token) -> loan token units per repay token, in EXCHANGE_RATE_SCALE
+    SettlementDesk, // Counterparty that swaps repayments in other tokens into the loan token at the configured rate
+    WithdrawQueueEnabled, // Queue withdrawals that exceed idle liquidity instead of rejecting them
+    MaxLtvBps,    // Largest principal a loan may have relative to its invoice amount, in basis points, 0 = uncapped
+    MinHealthFactor, // Health factor (bps) a partial repayment must leave a loan at, 0 = disabled
+    LiquidatorWhitelist, // Whether only listed liquidators may liquidate
+    Liquidator(Address), // Marks addresses on the liquidator whitelist
+    Blocked(Address), // Marks addresses barred from depositing, borrowing and liquidating
+    BorrowFeeBps, // Origination fee charged on disbursement, in basis points of principal
+    FeeToken,     // Token borrower fees are collected in, the lending token when unset
+    MinHoldPeriod, // Seconds after a loan starts before it can be repaid without penalty, 0 = disabled
+    EarlyRepaymentPenaltyBps, // Penalty on principal repaid within the minimum hold period, in basis points
+    MaxTotalLoans, // Most loans that may be open at once, 0 = unlimited
+    LiquidationInterestRecoveryBps, // Share of accrued interest a partial liquidator pays on the principal they cover, in basis points
+    AutoApproveLimit, // Largest principal a loan may have and still be active on creation, 0 = no approval needed
+    EarlyRepayDiscountBps, // Share of interest waived when a loan is repaid in full before its due date, in basis points
+    MinLiquidationAmount, // Smallest accepted partial liquidation, unless it covers the rest of the loan
+    PenaltyRateBps, // Annual rate charged in place of a loan's own once it is past due, 0 = keep the loan's rate
+}
+
+// Functions of the InvoiceContract that the pool calls into
+#[contractclient(name = "InvoiceClient")]
+pub trait InvoiceInterface {
+    fn set_paused(env: Env, paused: bool);
+    fn lock_invoice(env: Env, id: u64, owner: Address);
+    fn unlock_invoice(env: Env, id: u64);
+    fn mark_seized(env: Env, id: u64);
+    fn reassign_invoice(env: Env, id: u64, new_owner: Address);
+    fn get_lending_pool(env: Env) -> Option<Address>;
+    fn get_backends(env: Env) -> Map<u32, BytesN<32>>;
+    fn is_paused(env: Env) -> bool;
+    fn owner_of(env: Env, id: u64) -> Option<Address>;
+    fn get_due_date(env: Env, id: u64) -> u64;
+    fn get_amount(env: Env, id: u64) -> i128;
+    #[allow(clippy::too_many_arguments)]
+    fn mint(env: Env, owner: Address, amount: i128, due_date: u64, risk_score: u32, external_ref: Option<BytesN<32>>, key_index: u32, signature: BytesN<64>) -> u64;
 }
 
+// Price source the pool consults for the market value of collateral
+#[contractclient(name = "OracleClient")]
+pub trait OracleInterface {
+    fn price_of(env: Env, invoice_id: u64) -> i128;
+}
+
+// Instance storage TTL target, in ledgers (approx 30 days)
+const TTL_EXTENSION: u32 = 535_680;
+
+// Fixed-point scale used for compounded interest math
+const RATE_SCALE: i128 = 1_000_000_000_000;
+
+// Fixed-point scale of admin-set exchange rates, so 1_000_000_000 is 1:1
+const EXCHANGE_RATE_SCALE: i128 = 1_000_000_000;
+
+// Highest supported compounding frequency (daily)
+const MAX_COMPOUNDING_FREQUENCY: u32 = 365;
+
+// Loan interest rate, 5% expressed in basis points
+const APY_BPS: u64 = 500;
+
+// Seconds in the 365-day year rates are quoted over
+const YEAR_IN_SECONDS: u64 = 31_536_000;
+
+// Seconds in a 360-day banker's year
+const YEAR_360_IN_SECONDS: u64 = 31_104_000;
+
+// Seconds in an average 365.25-day year
+const YEAR_ACTUAL_IN_SECONDS: u64 = 31_557_600;
+
+// Parts of a token unit that sub-unit interest is tracked in between payments
+const FRACTION_SCALE: i128 = 1_000_000;
+
+// Maximum number of steps in a loan's rate schedule, to bound gas
+const MAX_RATE_STEPS: u32 = 10;
+
+// Maximum number of loans a single default sweep may process, to bound gas
+const MAX_SWEEP_BATCH: u32 = 50;
+
+// Maximum number of entries in a computed repayment schedule, to bound gas
+const MAX_SCHEDULE_ENTRIES: u32 = 100;
+
+// Maximum number of ids returned by one page of get_active_loans
+const MAX_PAGE_SIZE: u32 = 50;
+
+// Native XLM amounts are in stroops (1 XLM = 10^7 stroops), like every Stellar Asset Contract
+const NATIVE_DECIMALS: u32 = 7;
+
+// Short message for each error code, so clients can read the mapping from the contract instead of keeping their own
+const ERROR_MESSAGES: [(Error, &str); 9] = [
+    (Error::NotInitialized, "not_initialized"),
+    (Error::ContractPaused, "contract_paused"),
+    (Error::InsufficientLiquidity, "insufficient_liquidity"),
+    (Error::LoanNotFound, "loan_not_found"),
+    (Error::LoanAlreadyRepaid, "loan_already_repaid"),
+    (Error::LoanDefaulted, "loan_defaulted"),
+    (Error::InsufficientBalance, "insufficient_balance"),
+    (Error::CannotLiquidateHealthyLoan, "loan_healthy"),
+    (Error::Unauthorized, "unauthorized"),
+];
+
 #[contract]
 pub struct LendingPool;
 
@@ -47,213 +385,2874 @@ impl LendingPool {
         }
         env.storage().instance().set(&DataKey::Admin, &admin);
         env.storage().instance().set(&DataKey::TokenAddress, &token_address);
-        env.storage().instance().set(&DataKey::Paused, &false);
+        env.storage().instance().set(&DataKey::Paused, &Self::all_flags(false));
+    }
+
+    // UPGRADE: Replace the contract's code with previously uploaded Wasm, keeping its storage (admin only)
+    pub fn upgrade(env: Env, new_wasm_hash: BytesN<32>) {
+        Self::require_admin(&env);
+        env.deployer().update_current_contract_wasm(new_wasm_hash.clone());
+        Self::publish_event(&env, (symbol_short!("upgraded"),), new_wasm_hash);
+    }
+
+    // EXPORT STATE: Bundle admin, token, counters and config, to compare pool state across upgrades (admin only)
+    pub fn export_state(env: Env) -> PoolSnapshot {
+        Self::require_admin(&env);
+        PoolSnapshot {
+            admin: env.storage().instance().get(&DataKey::Admin).expect("Not initialized"),
+            token: env.storage().instance().get(&DataKey::TokenAddress).expect("Not initialized"),
+            invoice_contract: Self::get_invoice_contract(env.clone()),
+            last_loan_id: env.storage().instance().get(&DataKey::LoanId).unwrap_or(0),
+            active_loans: Self::active_loans(&env).len(),
+            total_outstanding: Self::get_total_outstanding(&env),
+            total_deposits: env.storage().instance().get(&DataKey::TotalDeposits).unwrap_or(0),
+            treasury_balance: Self::get_treasury_balance(env.clone()),
+            lp_yield: Self::get_lp_yield(env.clone()),
+            lifetime_interest: Self::get_lifetime_interest(env.clone()),
+            bad_debt: Self::get_bad_debt(env.clone()),
+            config: Self::get_config(env),
+        }
+    }
+
+    // INITIALIZE NATIVE: Lend native XLM, using the network's native Stellar Asset Contract as the pool token
+    pub fn init_native(env: Env, admin: Address) {
+        let native_token = Self::native_token_address(&env);
+        if token::Client::new(&env, &native_token).decimals() != NATIVE_DECIMALS {
+            panic!("Invalid native token");
+        }
+        Self::init(env.clone(), admin, native_token);
+        env.storage().instance().set(&DataKey::NativeToken, &true);
+    }
+
+    // GET NATIVE: Check whether the pool lends native XLM
+    pub fn is_native(env: Env) -> bool {
+        env.storage().instance().get(&DataKey::NativeToken).unwrap_or(false)
+    }
+
+    // Helper function to derive the native asset's contract address (XDR of Asset::Native is a zero discriminant)
+    fn native_token_address(env: &Env) -> Address {
+        let native_asset = Bytes::from_array(env, &[0u8; 4]);
+        env.deployer().with_stellar_asset(native_asset).deployed_address()
+    }
+
+    // SET EVENT NAMESPACE: Prefix every event with a deployment-specific topic (admin only)
+    pub fn set_event_namespace(env: Env, namespace: Symbol) {
+        Self::require_admin(&env);
+        let old = Self::get_event_namespace(env.clone());
+        env.storage().instance().set(&ConfigKey::EventNamespace, &namespace);
+        Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "event_namespace", old, Some(namespace));
+    }
+
+    // GET EVENT NAMESPACE: The configured namespace, None for the standard un-prefixed events
+    pub fn get_event_namespace(env: Env) -> Option<Symbol> {
+        env.storage().instance().get(&ConfigKey::EventNamespace)
+    }
+
+    // Helper function to publish an event, leading with the namespace topic when one is set
+    fn publish_event(env: &Env, topics: impl IntoVal<Env, Vec<Val>>, data: impl IntoVal<Env, Val>) {
+        let mut topics: Vec<Val> = topics.into_val(env);
+        if let Some(namespace) = Self::get_event_namespace(env.clone()) {
+            topics.push_front(namespace.to_val());
+        }
+        env.events().publish(topics, data);
+    }
+
+    // Helper function to publish a loan lifecycle event; topics are always (name, loan_id, party)
+    // so an indexer can rebuild one loan's history by filtering on the loan id, and a borrower can
+    // discover loans opened for them (e.g. by a relayer) by subscribing to loan_created with their address
+    fn publish_loan_event(env: &Env, name: &str, loan_id: u64, party: Address, data: impl IntoVal<Env, Val>) {
+        Self::publish_event(env, (Symbol::new(env, name), loan_id, party), data);
+    }
+
+    // Helper function to leave an audit trail of an admin setting change: topics (config_changed, name), data (old, new)
+    fn config_changed<T>(env: &Env, name: &str, old: T, new: T)
+    where
+        Val: TryFromVal<Env, T>,
+    {
+        Self::publish_event(env, (Symbol::new(env, "config_changed"), Symbol::new(env, name)), (old, new));
     }
 
-    // Helper function to check if contract is paused
-    fn check_paused(env: &Env) {
-        if env.storage().instance().get(&DataKey::Paused).unwrap_or(false) {
+    // Helper function to check if an operation is paused
+    fn check_paused(env: &Env, operation: Operation) {
+        let flags = Self::get_pause_flags(env.clone());
+        let paused = match operation {
+            Operation::Deposits => flags.deposits,
+            Operation::Borrows => flags.borrows,
+            Operation::Repayments => flags.repayments,
+            Operation::Liquidations => flags.liquidations,
+        };
+        if paused {
             panic!("CONTRACT_PAUSED");
         }
     }
 
+    // Helper function for pause flags with every operation set the same way
+    fn all_flags(paused: bool) -> PauseFlags {
+        PauseFlags { deposits: paused, borrows: paused, repayments: paused, liquidations: paused }
+    }
+
     // Helper function to check admin authorization
     fn require_admin(env: &Env) {
         let admin: Address = env.storage().instance().get(&DataKey::Admin).expect("Not initialized");
         admin.require_auth();
     }
 
-    // PAUSE CONTROL: Set contract pause state (admin only)
+    // PAUSE CONTROL: Pause or resume every operation at once (admin only)
     pub fn set_paused(env: Env, paused: bool) {
+        Self::set_pause_flags(env, Self::all_flags(paused));
+    }
+
+    // SET PAUSE FLAGS: Pause operations independently, e.g. stop borrowing but keep repayments open (admin only)
+    pub fn set_pause_flags(env: Env, flags: PauseFlags) {
         Self::require_admin(&env);
-        env.storage().instance().set(&DataKey::Paused, &paused);
-        env.events().publish((symbol_short!("pause_set"), paused), env.ledger().sequence());
+        let old = Self::get_pause_flags(env.clone());
+        env.storage().instance().set(&DataKey::Paused, &flags);
+        Self::publish_event(&env, (symbol_short!("pause_set"), Self::is_paused(env.clone())), env.ledger().sequence());
+        Self::config_changed(&env, "pause_flags", old, flags);
+    }
+
+    // GET PAUSE FLAGS: Returns which operations are paused, reading the single bool older deployments stored as all-or-nothing
+    pub fn get_pause_flags(env: Env) -> PauseFlags {
+        let stored: Option<Val> = env.storage().instance().get(&DataKey::Paused);
+        match stored {
+            None => Self::all_flags(false),
+            Some(value) => match bool::try_from_val(&env, &value) {
+                Ok(paused) => Self::all_flags(paused),
+                Err(_) => PauseFlags::try_from_val(&env, &value).expect("Invalid pause flags"),
+            },
+        }
     }
 
-    // GET PAUSE STATE: Check if contract is paused
+    // PAUSE ALL: Halt both the pool and the invoice contract in one call (shared admin only)
+    pub fn pause_all(env: Env, invoice_contract: Address) {
+        Self::set_paused(env.clone(), true);
+        InvoiceClient::new(&env, &invoice_contract).set_paused(&true);
+    }
+
+    // GET PAUSE STATE: Check if any operation is paused
     pub fn is_paused(env: Env) -> bool {
-        env.storage().instance().get(&DataKey::Paused).unwrap_or(false)
+        let flags = Self::get_pause_flags(env);
+        flags.deposits || flags.borrows || flags.repayments || flags.liquidations
     }
 
     // 2. DEPOSIT: LPs add capital to the pool
     pub fn deposit(env: Env, from: Address, amount: i128) {
-        Self::check_paused(&env);
+        Self::check_paused(&env, Operation::Deposits);
         from.require_auth();
+        Self::check_not_blocked(&env, &from);
 
         let token_addr: Address = env.storage().instance().get(&DataKey::TokenAddress).expect("Not initialized");
         let client = token::Client::new(&env, &token_addr);
 
+        if amount <= 0 {
+            panic!("Invalid deposit amount");
+        }
+
+        // Reject deposits that would push the pool past its cap. Events from a reverted
+        // call are discarded, so no cap_reached event can accompany the rejection.
+        let cap = Self::get_pool_cap(env.clone());
+        if cap > 0 {
+            let pool_size = client.balance(&env.current_contract_address()) + Self::get_total_outstanding(&env);
+            if pool_size + amount > cap {
+                panic!("Pool cap reached");
+            }
+        }
+
         // Transfer from User -> Contract
         client.transfer(&from, &env.current_contract_address(), &amount);
-        
-        // (In a real app, we would mint "Pool Share Tokens" here)
-        env.events().publish((symbol_short!("deposit"), from), amount);
+
+        // Shares are priced off what LPs already own, so yield earned before this deposit stays with earlier LPs
+        let total_shares = Self::get_total_shares(env.clone());
+        let minted = shares::shares_for_deposit(amount, total_shares, Self::lp_pool_value(&env));
+        let held = Self::get_lp_shares(env.clone(), from.clone());
+        env.storage().instance().set(&DataKey::LpShares(from.clone()), &(held + minted));
+        env.storage().instance().set(&DataKey::TotalShares, &(total_shares + minted));
+
+        Self::checkpoint_deposits(&env, amount);
+        let deposited = Self::get_lp_deposit(env.clone(), from.clone());
+        env.storage().instance().set(&DataKey::LpDeposit(from.clone()), &(deposited + amount));
+        env.storage().instance().set(&DataKey::LastDeposit(from.clone()), &env.ledger().timestamp());
+        Self::extend_storage_ttl(&env);
+
+        Self::publish_event(&env, (symbol_short!("deposit"), from), amount);
     }
 
-    // 3. BORROW: Borrow against an invoice (Simplified)
-    pub fn borrow(env: Env, borrower: Address, amount: i128) {
-        Self::check_paused(&env);
-        borrower.require_auth();
+    // WITHDRAW: LPs redeem shares for up to what they are worth, deposits plus earned yield, as far as idle liquidity allows
+    pub fn withdraw(env: Env, to: Address, amount: i128) {
+        Self::check_paused(&env, Operation::Deposits);
+        to.require_auth();
+
+        if amount <= 0 {
+            panic!("Invalid withdrawal amount");
+        }
+
+        let value = Self::get_lp_value(env.clone(), to.clone());
+        if amount > value {
+            panic!("Insufficient deposit");
+        }
+
+        if Self::time_until_withdrawable(env.clone(), to.clone()) > 0 {
+            panic!("Withdrawal cooldown active");
+        }
 
-        // 1. Check if the pool has enough funds
         let token_addr: Address = env.storage().instance().get(&DataKey::TokenAddress).expect("Not initialized");
         let client = token::Client::new(&env, &token_addr);
-        
-        let pool_balance = client.balance(&env.current_contract_address());
-        if amount > pool_balance {
+        // While others are waiting, a new withdrawal joins the back of the queue rather than jumping it
+        let short = Self::available_liquidity(&env) < amount;
+        if short && !Self::is_withdraw_queue_enabled(env.clone()) {
             panic!("Insufficient pool liquidity");
         }
+        let queue_instead = short || !Self::withdraw_queue(&env).is_empty();
 
-        // 2. Transfer funds Contract -> Borrower
-        client.transfer(&env.current_contract_address(), &borrower, &amount);
+        // Shares are burned at today's price either way, so a queued withdrawal stops earning while it waits
+        let total_shares = Self::get_total_shares(env.clone());
+        let burned = shares::shares_for_withdrawal(amount, total_shares, Self::lp_pool_value(&env));
+        let held = Self::get_lp_shares(env.clone(), to.clone());
+        env.storage().instance().set(&DataKey::LpShares(to.clone()), &(held - burned));
+        env.storage().instance().set(&DataKey::TotalShares, &(total_shares - burned));
 
-        env.events().publish((symbol_short!("borrow"), borrower), amount);
-    }
+        // The LP's deposit is drawn down in proportion, and the rest of the amount is yield they earned
+        let deposited = Self::get_lp_deposit(env.clone(), to.clone());
+        let principal = (amount * deposited / value).min(deposited).min(amount);
+        Self::checkpoint_deposits(&env, -principal);
+        env.storage().instance().set(&DataKey::LpDeposit(to.clone()), &(deposited - principal));
+        let yield_withdrawn = Self::yield_withdrawn(&env) + amount - principal;
+        env.storage().instance().set(&DataKey::YieldWithdrawn, &yield_withdrawn);
 
-    // Helper function to calculate interest (5% APY)
-    fn calculate_interest(principal: i128, start_time: u64, end_time: u64) -> i128 {
-        const YEAR_IN_SECONDS: u64 = 31_536_000; // 365.25 days
-        const APY_BPS: u64 = 500; // 5% expressed in basis points
-        
-        if end_time <= start_time {
-            return 0;
+        // The amount leaves the LP's position either way; queued, it is paid out once repayments refill the pool
+        if queue_instead {
+            if env.storage().instance().has(&DataKey::QueuedWithdrawal(to.clone())) {
+                panic!("Withdrawal already queued");
+            }
+            let mut queue = Self::withdraw_queue(&env);
+            queue.push_back(to.clone());
+            env.storage().instance().set(&DataKey::WithdrawQueue, &queue);
+            env.storage().instance().set(&DataKey::QueuedWithdrawal(to.clone()), &amount);
+            Self::extend_storage_ttl(&env);
+            Self::publish_event(&env, (Symbol::new(&env, "withdraw_queued"), to), amount);
+            return;
         }
-        
-        let duration = end_time - start_time;
-        let interest = principal * APY_BPS as i128 * duration as i128 / (10_000 * YEAR_IN_SECONDS as i128);
-        interest
-    }
 
-    // Helper function to extend storage TTL
-    fn extend_storage_ttl(env: &Env) {
-        // Extend TTL to 535,680 ledgers (approx 30 days)
-        env.storage().instance().extend_ttl(535_680, 535_680);
+        Self::extend_storage_ttl(&env);
+        client.transfer(&env.current_contract_address(), &to, &amount);
+
+        Self::publish_event(&env, (symbol_short!("withdraw"), to), amount);
     }
 
-    // SET BACKEND PUBKEY: Initialize backend public key for signature verification
-    pub fn set_backend_pubkey(env: Env, pubkey: BytesN<32>) {
+    // SET WITHDRAW QUEUE: Queue withdrawals that exceed idle liquidity instead of rejecting them (admin only)
+    pub fn set_withdraw_queue(env: Env, enabled: bool) {
         Self::require_admin(&env);
-        env.storage().instance().set(&DataKey::BackendPubkey, &pubkey);
+        let old = Self::is_withdraw_queue_enabled(env.clone());
+        env.storage().instance().set(&ConfigKey::WithdrawQueueEnabled, &enabled);
         Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "withdraw_queue", old, enabled);
     }
 
-    // CREATE LOAN: Create a new loan record
-    pub fn create_loan(env: Env, borrower: Address, invoice_id: u64, principal: i128, due_date: u64) -> u64 {
-        Self::check_paused(&env);
-        borrower.require_auth();
+    // IS WITHDRAW QUEUE ENABLED: Check whether short-of-liquidity withdrawals are queued
+    pub fn is_withdraw_queue_enabled(env: Env) -> bool {
+        env.storage().instance().get(&ConfigKey::WithdrawQueueEnabled).unwrap_or(false)
+    }
 
-        let current_time = env.ledger().timestamp();
-        let interest = Self::calculate_interest(principal, current_time, due_date);
+    // GET QUEUE POSITION: Withdrawals ahead of an LP's queued one (0 = next), None if nothing is waiting for liquidity
+    pub fn get_queue_position(env: Env, lp: Address) -> Option<u32> {
+        Self::withdraw_queue(&env).first_index_of(lp)
+    }
 
-        let mut loan_id = env.storage().instance().get(&DataKey::LoanId).unwrap_or(0u64);
-        loan_id += 1;
+    // CLAIM QUEUED WITHDRAWAL: Pay out an LP's queued withdrawal once liquidity has reached it
+    pub fn claim_queued_withdrawal(env: Env, lp: Address) -> i128 {
+        Self::check_paused(&env, Operation::Deposits);
+        lp.require_auth();
 
-        let loan = Loan {
-            id: loan_id,
-            borrower: borrower.clone(),
-            invoice_id,
-            principal,
-            interest,
-            start_time: current_time,
-            due_date,
-            is_repaid: false,
-            is_defaulted: false,
-        };
+        Self::fulfill_withdraw_queue(&env);
+        let amount: i128 = env.storage().instance().get(&DataKey::QueuedWithdrawal(lp.clone()))
+            .expect("No queued withdrawal");
+        if Self::withdraw_queue(&env).contains(&lp) {
+            panic!("Withdrawal not yet fulfilled");
+        }
 
-        env.storage().instance().set(&DataKey::Loan(loan_id), &loan);
-        env.storage().instance().set(&DataKey::LoanId, &loan_id);
+        env.storage().instance().remove(&DataKey::QueuedWithdrawal(lp.clone()));
+        let reserved = Self::reserved_liquidity(&env) - amount;
+        env.storage().instance().set(&DataKey::ReservedLiquidity, &reserved);
         Self::extend_storage_ttl(&env);
 
-        env.events().publish((symbol_short!("loan_created"), borrower), loan_id);
-        loan_id
+        let token_addr: Address = env.storage().instance().get(&DataKey::TokenAddress).expect("Not initialized");
+        token::Client::new(&env, &token_addr).transfer(&env.current_contract_address(), &lp, &amount);
+
+        Self::publish_event(&env, (symbol_short!("withdraw"), lp), amount);
+        amount
     }
 
-    // REPAY LOAN: Repay a loan and unlock collateral
-    pub fn repay_loan(env: Env, loan_id: u64) {
-        Self::check_paused(&env);
-        
-        let mut loan: Loan = env.storage().instance().get(&DataKey::Loan(loan_id))
-            .expect("Loan not found");
-        
-        if loan.is_repaid {
-            panic!("Loan already repaid");
+    // Helper function to read the LPs waiting on liquidity, oldest first
+    fn withdraw_queue(env: &Env) -> Vec<Address> {
+        env.storage().instance().get(&DataKey::WithdrawQueue).unwrap_or(Vec::new(env))
+    }
+
+    // Helper function to read the balance set aside for fulfilled, unclaimed withdrawals
+    fn reserved_liquidity(env: &Env) -> i128 {
+        env.storage().instance().get(&DataKey::ReservedLiquidity).unwrap_or(0)
+    }
+
+    // Helper function for the pool balance free to lend or withdraw
+    fn available_liquidity(env: &Env) -> i128 {
+        Self::get_pool_balance(env.clone()) - Self::reserved_liquidity(env)
+    }
+
+    // Helper function to reserve liquidity for queued withdrawals in order, stopping at the first that doesn't fit
+    fn fulfill_withdraw_queue(env: &Env) {
+        let mut queue = Self::withdraw_queue(env);
+        let mut available = Self::available_liquidity(env);
+        let mut reserved = Self::reserved_liquidity(env);
+        let mut fulfilled = 0;
+        while let Some(lp) = queue.first() {
+            if fulfilled >= MAX_PAGE_SIZE {
+                break;
+            }
+            let amount: i128 = env.storage().instance().get(&DataKey::QueuedWithdrawal(lp.clone())).unwrap_or(0);
+            if amount > available {
+                break;
+            }
+            queue.pop_front();
+            available -= amount;
+            reserved += amount;
+            fulfilled += 1;
+            Self::publish_event(env, (Symbol::new(env, "withdraw_ready"), lp), amount);
         }
-        
-        if loan.is_defaulted {
-            panic!("Loan defaulted - use liquidation instead");
+        if fulfilled > 0 {
+            env.storage().instance().set(&DataKey::WithdrawQueue, &queue);
+            env.storage().instance().set(&DataKey::ReservedLiquidity, &reserved);
         }
-        
-        loan.borrower.require_auth();
+    }
 
-        let token_addr: Address = env.storage().instance().get(&DataKey::TokenAddress)
-            .expect("Not initialized");
-        let client = token::Client::new(&env, &token_addr);
+    // Helper function to accumulate time-weighted deposits up to now, then apply a change in total deposits
+    fn checkpoint_deposits(env: &Env, change: i128) {
+        let total: i128 = env.storage().instance().get(&DataKey::TotalDeposits).unwrap_or(0);
+        env.storage().instance().set(&DataKey::DepositSeconds, &Self::deposit_seconds(env));
+        env.storage().instance().set(&DataKey::DepositCheckpoint, &env.ledger().timestamp());
+        env.storage().instance().set(&DataKey::TotalDeposits, &(total + change));
+    }
 
-        let current_time = env.ledger().timestamp();
-        let current_interest = Self::calculate_interest(loan.principal, loan.start_time, current_time);
-        let total_repayment = loan.principal + current_interest;
+    // Helper function for time-weighted deposits as of the current ledger time
+    fn deposit_seconds(env: &Env) -> i128 {
+        let accumulated: i128 = env.storage().instance().get(&DataKey::DepositSeconds).unwrap_or(0);
+        let total: i128 = env.storage().instance().get(&DataKey::TotalDeposits).unwrap_or(0);
+        let checkpoint: u64 = env.storage().instance().get(&DataKey::DepositCheckpoint).unwrap_or(0);
+        accumulated + total * env.ledger().timestamp().saturating_sub(checkpoint) as i128
+    }
 
-        // Check borrower's USDC balance
-        let borrower_balance = client.balance(&loan.borrower);
-        if borrower_balance < total_repayment {
-            panic!("Insufficient USDC balance");
+    // GET POOL APY: Realized LP yield annualized over time-weighted deposits, in basis points
+    pub fn get_pool_apy(env: Env) -> u32 {
+        let deposit_seconds = Self::deposit_seconds(&env);
+        if deposit_seconds <= 0 {
+            return 0;
         }
+        let apy = Self::get_lp_yield(env) * 10_000 * YEAR_IN_SECONDS as i128 / deposit_seconds;
+        apy.clamp(0, u32::MAX as i128) as u32
+    }
 
-        // Transfer repayment from borrower to contract
-        client.transfer(&loan.borrower, &env.current_contract_address(), &total_repayment);
+    // GET SOLVENCY RATIO: Idle funds plus collectible principal over everything owed to LPs, in basis points
+    // Below 10_000 the pool can't cover every LP claim; defaulted principal no longer counts as collectible
+    pub fn get_solvency_ratio(env: Env) -> u32 {
+        // Treasury interest belongs to the protocol, not LPs
+        let idle = Self::get_pool_balance(env.clone()) - Self::get_treasury_balance(env.clone());
+        let assets = idle + Self::get_total_outstanding(&env);
 
-        // Update loan status
-        loan.is_repaid = true;
-        env.storage().instance().set(&DataKey::Loan(loan_id), &loan);
+        // Withdrawals leave the LPs' value when requested, but are owed until claimed
+        let mut liabilities = Self::lp_pool_value(&env) + Self::reserved_liquidity(&env);
+        for lp in Self::withdraw_queue(&env).iter() {
+            liabilities += env.storage().instance().get::<_, i128>(&DataKey::QueuedWithdrawal(lp)).unwrap_or(0);
+        }
+
+        if liabilities <= 0 {
+            return u32::MAX;
+        }
+        (assets * 10_000 / liabilities).clamp(0, u32::MAX as i128) as u32
+    }
+
+    // SET WITHDRAW COOLDOWN: Seconds between an LP's deposit and their next withdrawal (admin only, 0 = none)
+    pub fn set_withdraw_cooldown(env: Env, cooldown: u64) {
+        Self::require_admin(&env);
+        let old = Self::get_withdraw_cooldown(env.clone());
+        env.storage().instance().set(&ConfigKey::WithdrawCooldown, &cooldown);
         Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "withdraw_cooldown", old, cooldown);
+    }
 
-        // In a real implementation, we would transfer the NFT back to the borrower
-        // For now, we just emit an event
-        env.events().publish((symbol_short!("loan_repaid"), loan.borrower), loan_id);
+    // GET WITHDRAW COOLDOWN: Returns the withdrawal cooldown in seconds
+    pub fn get_withdraw_cooldown(env: Env) -> u64 {
+        env.storage().instance().get(&ConfigKey::WithdrawCooldown).unwrap_or(0)
     }
 
-    // LIQUIDATE: Liquidate a defaulted loan
-    pub fn liquidate(env: Env, loan_id: u64) {
-        Self::check_paused(&env);
-        
-        let mut loan: Loan = env.storage().instance().get(&DataKey::Loan(loan_id))
+    // TIME UNTIL WITHDRAWABLE: Seconds before an LP may withdraw, 0 if they already can
+    pub fn time_until_withdrawable(env: Env, lp: Address) -> u64 {
+        let last_deposit: u64 = match env.storage().instance().get(&DataKey::LastDeposit(lp)) {
+            Some(timestamp) => timestamp,
+            None => return 0,
+        };
+        let unlocks_at = last_deposit.saturating_add(Self::get_withdraw_cooldown(env.clone()));
+        unlocks_at.saturating_sub(env.ledger().timestamp())
+    }
+
+    // GET LP DEPOSIT: Amount an LP has deposited and not yet withdrawn
+    pub fn get_lp_deposit(env: Env, lp: Address) -> i128 {
+        env.storage().instance().get(&DataKey::LpDeposit(lp)).unwrap_or(0)
+    }
+
+    // GET LP SHARES: Pool shares an LP holds
+    pub fn get_lp_shares(env: Env, lp: Address) -> i128 {
+        env.storage().instance().get(&DataKey::LpShares(lp)).unwrap_or(0)
+    }
+
+    // GET TOTAL SHARES: Pool shares held by all LPs
+    pub fn get_total_shares(env: Env) -> i128 {
+        env.storage().instance().get(&DataKey::TotalShares).unwrap_or(0)
+    }
+
+    // GET LP VALUE: What an LP's shares are worth now, the most they can withdraw
+    pub fn get_lp_value(env: Env, lp: Address) -> i128 {
+        let held = Self::get_lp_shares(env.clone(), lp);
+        shares::amount_for_shares(held, Self::get_total_shares(env.clone()), Self::lp_pool_value(&env))
+    }
+
+    // Helper function for everything LPs own together: deposits plus yield not yet withdrawn
+    fn lp_pool_value(env: &Env) -> i128 {
+        let total_deposits: i128 = env.storage().instance().get(&DataKey::TotalDeposits).unwrap_or(0);
+        total_deposits + Self::get_lp_yield(env.clone()) - Self::yield_withdrawn(env)
+    }
+
+    // Helper function to read the LP yield already paid out through withdrawals
+    fn yield_withdrawn(env: &Env) -> i128 {
+        env.storage().instance().get(&DataKey::YieldWithdrawn).unwrap_or(0)
+    }
+
+    // 3. DISBURSE: Pay out exactly the principal of a created loan, once, to the borrower or a designated recipient
+    pub fn disburse(env: Env, loan_id: u64, disburse_to: Option<Address>) {
+        Self::check_paused(&env, Operation::Borrows);
+
+        let mut loan: Loan = Self::load_loan(&env, loan_id)
             .expect("Loan not found");
-        
-        if loan.is_repaid {
-            panic!("Cannot liquidate repaid loan");
+
+        if loan.is_disbursed {
+            panic!("Loan already disbursed");
         }
-        
-        if loan.is_defaulted {
-            panic!("Loan already liquidated");
+
+        if loan.is_repaid || loan.is_defaulted {
+            panic!("Loan is closed");
         }
 
-        let current_time = env.ledger().timestamp();
-        if current_time <= loan.due_date {
-            panic!("Cannot liquidate healthy loan");
+        if Self::is_pending_approval(env.clone(), loan_id) {
+            panic!("Loan pending approval");
         }
 
-        let liquidator = env.current_contract_address(); // In real implementation, this would be the caller
-        liquidator.require_auth();
+        loan.borrower.require_auth();
 
-        let token_addr: Address = env.storage().instance().get(&DataKey::TokenAddress)
-            .expect("Not initialized");
+        // 1. Check if the pool has enough funds
+        let token_addr: Address = env.storage().instance().get(&DataKey::TokenAddress).expect("Not initialized");
         let client = token::Client::new(&env, &token_addr);
 
-        // Transfer principal from liquidator to contract
-        client.transfer(&liquidator, &env.current_contract_address(), &loan.principal);
+        // Liquidity set aside for fulfilled withdrawals is not available to lend
+        if loan.principal > Self::available_liquidity(&env) {
+            panic!("Insufficient pool liquidity");
+        }
 
-        // Update loan status
-        loan.is_defaulted = true;
-        env.storage().instance().set(&DataKey::Loan(loan_id), &loan);
+        // 2. Charge the origination fee, in the fee token if one is set and otherwise out of the payout
+        let fee = loan.principal * Self::get_borrow_fee(env.clone()) as i128 / 10_000;
+        let mut payout = loan.principal;
+        if fee > 0 {
+            if !Self::collect_in_fee_token(&env, &loan.borrower, fee) {
+                payout -= fee;
+                Self::record_interest(&env, fee);
+            }
+            env.storage().instance().set(&DataKey::BorrowFee(loan_id), &fee);
+            Self::publish_loan_event(&env, "borrow_fee_charged", loan_id, loan.borrower.clone(), fee);
+        }
+
+        // 3. Transfer funds Contract -> Borrower (or e.g. a financier's account); the borrower stays liable either way
+        let recipient = disburse_to.unwrap_or(loan.borrower.clone());
+        client.transfer(&env.current_contract_address(), &recipient, &payout);
+
+        // Principal counts as lent out only once it has actually left the pool
+        loan.is_disbursed = true;
+        Self::save_loan(&env, &loan);
+        Self::adjust_total_outstanding(&env, loan.principal);
         Self::extend_storage_ttl(&env);
 
-        // In a real implementation, we would transfer the NFT to the liquidator
-        env.events().publish((symbol_short!("loan_liquidated"), liquidator), loan_id);
+        Self::publish_loan_event(&env, "loan_disbursed", loan_id, loan.borrower, loan.principal);
     }
 
-    // GET LOAN: Retrieve loan details
-    pub fn get_loan(env: Env, loan_id: u64) -> Option<Loan> {
-        env.storage().instance().get(&DataKey::Loan(loan_id))
+    // CANCEL LOAN: Withdraw a loan that was never disbursed, unlocking its collateral (borrower or admin).
+    // The loan leaves the active set, freeing its loan slot; it never reached total outstanding, which is booked on
+    // disbursement. The LoanId counter and unique borrower count are deliberately not decremented: ids are never
+    // reused, and an address that opened a loan stays counted as a borrower
+    pub fn cancel_loan(env: Env, caller: Address, loan_id: u64) {
+        caller.require_auth();
+
+        let loan: Loan = Self::load_loan(&env, loan_id)
+            .expect("Loan not found");
+        let admin: Address = env.storage().instance().get(&DataKey::Admin).expect("Not initialized");
+        if caller != loan.borrower && caller != admin {
+            panic!("Not authorized to cancel loan");
+        }
+        if loan.is_disbursed {
+            panic!("Loan already disbursed");
+        }
+        if loan.is_repaid || loan.is_defaulted {
+            panic!("Loan is closed");
+        }
+
+        if loan.collateral_locked {
+            let invoice_contract = Self::get_invoice_contract(env.clone()).expect("Invoice contract not set");
+            InvoiceClient::new(&env, &invoice_contract).unlock_invoice(&loan.invoice_id);
+        }
+
+        let key = DataKey::Loan(loan_id);
+        env.storage().persistent().remove(&key);
+        env.storage().instance().remove(&key);
+        env.storage().instance().remove(&DataKey::RateSchedule(loan_id));
+        env.storage().instance().remove(&DataKey::PendingApproval(loan_id));
+        // A cancelled loan never financed its invoice, which is free to back another loan
+        env.storage().persistent().remove(&DataKey::InvoiceLoan(loan.invoice_id));
+        Self::remove_active_loan(&env, loan_id);
+
+        let mut borrower_loans = Self::get_loans_by_borrower(env.clone(), loan.borrower.clone());
+        if let Some(index) = borrower_loans.first_index_of(loan_id) {
+            borrower_loans.remove(index);
+            Self::set_loans_by_borrower(&env, &loan.borrower, &borrower_loans);
+        }
+        Self::extend_storage_ttl(&env);
+
+        Self::publish_loan_event(&env, "loan_cancelled", loan_id, caller, loan.principal);
+    }
+
+    // SET POOL CAP: Limit the total pool size for capped pilots (admin only, 0 = unlimited)
+    pub fn set_pool_cap(env: Env, cap: i128) {
+        Self::require_admin(&env);
+        if cap < 0 {
+            panic!("Invalid pool cap");
+        }
+        let old = Self::get_pool_cap(env.clone());
+        env.storage().instance().set(&ConfigKey::PoolCap, &cap);
+        Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "pool_cap", old, cap);
+    }
+
+    // GET POOL CAP: Returns the configured cap (0 = unlimited)
+    pub fn get_pool_cap(env: Env) -> i128 {
+        env.storage().instance().get(&ConfigKey::PoolCap).unwrap_or(0)
+    }
+
+    // GET REMAINING CAPACITY: How much more can be deposited before hitting the cap
+    pub fn get_remaining_capacity(env: Env) -> i128 {
+        let cap = Self::get_pool_cap(env.clone());
+        if cap == 0 {
+            return i128::MAX;
+        }
+        let pool_size = Self::get_pool_balance(env.clone()) + Self::get_total_outstanding(&env);
+        if pool_size >= cap {
+            0
+        } else {
+            cap - pool_size
+        }
+    }
+
+    // Helper function to read the principal currently lent out
+    fn get_total_outstanding(env: &Env) -> i128 {
+        env.storage().instance().get(&DataKey::TotalOutstanding).unwrap_or(0)
+    }
+
+    // Helper function to adjust the principal currently lent out
+    fn adjust_total_outstanding(env: &Env, delta: i128) {
+        let total = Self::get_total_outstanding(env) + delta;
+        env.storage().instance().set(&DataKey::TotalOutstanding, &total);
+    }
+
+    // Helper function to calculate interest at an annual rate, simple or compounded per the configured frequency
+    fn calculate_interest(env: &Env, principal: i128, start_time: u64, end_time: u64, rate_bps: u64) -> i128 {
+        
+        // Instantaneous loans (and fully repaid principal) accrue nothing, under any compounding mode
+        if end_time <= start_time || principal <= 0 {
+            return 0;
+        }
+
+        let duration = end_time - start_time;
+        let year = Self::year_length(env);
+        let periods_per_year = Self::get_compounding_frequency(env.clone());
+        if periods_per_year == 0 {
+            return principal * rate_bps as i128 * duration as i128 / (10_000 * year as i128);
+        }
+
+        // Compound over whole periods, then accrue simple interest on the partial period
+        let period_length = year / periods_per_year as u64;
+        let full_periods = duration / period_length;
+        let remainder = duration % period_length;
+
+        let rate_per_period = RATE_SCALE * rate_bps as i128 / (10_000 * periods_per_year as i128);
+        let growth = Self::pow_scaled(RATE_SCALE + rate_per_period, full_periods);
+        let compounded = principal * growth / RATE_SCALE;
+        let stub = compounded * rate_bps as i128 * remainder as i128 / (10_000 * year as i128);
+
+        compounded + stub - principal
+    }
+
+    // PROJECT INTEREST: Interest a loan of this principal would accrue over [start, end] at the pool rate and current settings
+    pub fn project_interest(env: Env, principal: i128, start: u64, end: u64) -> i128 {
+        Self::calculate_interest(&env, principal, start, end, APY_BPS)
+    }
+
+    // Helper function to accrue a loan's interest between two times, following its rate schedule if it has one
+    fn loan_interest(env: &Env, loan_id: u64, loan_start: u64, principal: i128, from: u64, to: u64) -> i128 {
+        let schedule: Vec<RateStep> = match env.storage().instance().get(&DataKey::RateSchedule(loan_id)) {
+            Some(schedule) => schedule,
+            None => return Self::calculate_interest(env, principal, from, to, APY_BPS),
+        };
+
+        // Each step's rate applies from its offset until the next step's offset
+        let mut interest = 0;
+        for (i, step) in schedule.iter().enumerate() {
+            let step_start = loan_start.saturating_add(step.start_offset);
+            let step_end = match schedule.get(i as u32 + 1) {
+                Some(next) => loan_start.saturating_add(next.start_offset),
+                None => u64::MAX,
+            };
+            interest += Self::calculate_interest(env, principal, from.max(step_start), to.min(step_end), step.rate_bps as u64);
+        }
+        interest
+    }
+
+    // Helper function to accrue interest owed on a loan between two times: its own rate up to the due date, then the
+    // penalty rate if one is set, so a borrower repaying late but before liquidation pays for the overdue time
+    fn accrued_interest(env: &Env, loan: &Loan, principal: i128, from: u64, to: u64) -> i128 {
+        let penalty_rate_bps = Self::get_penalty_rate(env.clone());
+        if penalty_rate_bps == 0 || to <= loan.due_date {
+            return Self::loan_interest(env, loan.id, loan.start_time, principal, from, to);
+        }
+        let overdue_from = from.max(loan.due_date);
+        Self::loan_interest(env, loan.id, loan.start_time, principal, from, overdue_from)
+            + Self::calculate_interest(env, principal, overdue_from, to, penalty_rate_bps as u64)
+    }
+
+    // SET RATE SCHEDULE: Attach stepped rates to a loan before it is disbursed (admin only)
+    pub fn set_rate_schedule(env: Env, loan_id: u64, schedule: Vec<RateStep>) {
+        Self::require_admin(&env);
+
+        let mut loan: Loan = Self::load_loan(&env, loan_id)
+            .expect("Loan not found");
+        if loan.is_disbursed || loan.is_repaid || loan.is_defaulted {
+            panic!("Loan already disbursed");
+        }
+
+        // Offsets must start at the loan's start and strictly increase
+        if schedule.is_empty() || schedule.len() > MAX_RATE_STEPS {
+            panic!("Invalid rate schedule");
+        }
+        let mut previous_offset: Option<u64> = None;
+        for step in schedule.iter() {
+            let valid = match previous_offset {
+                None => step.start_offset == 0,
+                Some(previous) => step.start_offset > previous,
+            };
+            if !valid {
+                panic!("Invalid rate schedule");
+            }
+            previous_offset = Some(step.start_offset);
+        }
+
+        env.storage().instance().set(&DataKey::RateSchedule(loan_id), &schedule);
+        loan.interest = Self::loan_interest(&env, loan_id, loan.start_time, loan.principal, loan.start_time, loan.due_date);
+        Self::save_loan(&env, &loan);
+        Self::extend_storage_ttl(&env);
+    }
+
+    // GET RATE SCHEDULE: A loan's stepped rates, None for the flat pool rate
+    pub fn get_rate_schedule(env: Env, loan_id: u64) -> Option<Vec<RateStep>> {
+        env.storage().instance().get(&DataKey::RateSchedule(loan_id))
+    }
+
+    // INTEREST PER SECOND: Interest a loan is accruing right now each second, in RATE_SCALE parts of a unit
+    // Compounding loans accrue on the principal plus interest owed so far; closed loans accrue nothing
+    pub fn interest_per_second(env: Env, loan_id: u64) -> i128 {
+        let loan: Loan = Self::load_loan(&env, loan_id)
+            .expect("Loan not found");
+        if loan.is_repaid || loan.is_defaulted {
+            return 0;
+        }
+
+        let current_time = env.ledger().timestamp();
+        let mut balance = loan.remaining_principal;
+        if Self::get_compounding_frequency(env.clone()) > 0 {
+            balance += Self::interest_due(&env, &loan, current_time);
+        }
+        let rate_bps = Self::current_rate_bps(&env, &loan, current_time);
+        balance * RATE_SCALE * rate_bps as i128 / (10_000 * Self::year_length(&env) as i128)
+    }
+
+    // Helper function for the annual rate a loan accrues at a point in time, from its rate schedule or the pool rate
+    fn current_rate_bps(env: &Env, loan: &Loan, at: u64) -> u64 {
+        let penalty_rate_bps = Self::get_penalty_rate(env.clone());
+        if penalty_rate_bps > 0 && at > loan.due_date {
+            return penalty_rate_bps as u64;
+        }
+        let schedule: Vec<RateStep> = match env.storage().instance().get(&DataKey::RateSchedule(loan.id)) {
+            Some(schedule) => schedule,
+            None => return APY_BPS,
+        };
+        let elapsed = at.saturating_sub(loan.start_time);
+        let mut rate_bps = APY_BPS;
+        for step in schedule.iter() {
+            if step.start_offset > elapsed {
+                break;
+            }
+            rate_bps = step.rate_bps as u64;
+        }
+        rate_bps
+    }
+
+    // Helper function to raise a RATE_SCALE fixed-point number to an integer power
+    fn pow_scaled(mut base: i128, mut exp: u64) -> i128 {
+        let mut result = RATE_SCALE;
+        while exp > 0 {
+            if exp & 1 == 1 {
+                result = result * base / RATE_SCALE;
+            }
+            base = base * base / RATE_SCALE;
+            exp >>= 1;
+        }
+        result
+    }
+
+    // SET COMPOUNDING FREQUENCY: Compounding periods per year, 0 = simple interest (admin only)
+    pub fn set_compounding_frequency(env: Env, periods_per_year: u32) {
+        Self::require_admin(&env);
+        if periods_per_year > MAX_COMPOUNDING_FREQUENCY {
+            panic!("Compounding frequency too high");
+        }
+        let old = Self::get_compounding_frequency(env.clone());
+        env.storage().instance().set(&ConfigKey::CompoundingFrequency, &periods_per_year);
+        Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "compounding_frequency", old, periods_per_year);
+    }
+
+    // GET COMPOUNDING FREQUENCY: Returns periods per year (0 = simple interest)
+    pub fn get_compounding_frequency(env: Env) -> u32 {
+        env.storage().instance().get(&ConfigKey::CompoundingFrequency).unwrap_or(0)
+    }
+
+    // SET DAY COUNT CONVENTION: Year length used to accrue interest (admin only)
+    pub fn set_day_count_convention(env: Env, convention: DayCountConvention) {
+        Self::require_admin(&env);
+        let old = Self::get_day_count_convention(env.clone());
+        env.storage().instance().set(&ConfigKey::DayCountConvention, &convention);
+        Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "day_count_convention", old, convention);
+    }
+
+    // GET DAY COUNT CONVENTION: Returns the accrual basis (Actual365 by default)
+    pub fn get_day_count_convention(env: Env) -> DayCountConvention {
+        env.storage().instance().get(&ConfigKey::DayCountConvention).unwrap_or(DayCountConvention::Actual365)
+    }
+
+    // Helper function for the seconds in a year under the configured day count convention
+    fn year_length(env: &Env) -> u64 {
+        match Self::get_day_count_convention(env.clone()) {
+            DayCountConvention::Actual365 => YEAR_IN_SECONDS,
+            DayCountConvention::Actual360 => YEAR_360_IN_SECONDS,
+            DayCountConvention::ActualActual => YEAR_ACTUAL_IN_SECONDS,
+        }
+    }
+
+    // Helper function to extend storage TTL
+    fn extend_storage_ttl(env: &Env) {
+        // Extend TTL to 535,680 ledgers (approx 30 days)
+        env.storage().instance().extend_ttl(TTL_EXTENSION, TTL_EXTENSION);
+    }
+
+    // Helper function to read a loan, migrating a legacy instance-storage copy on first access
+    fn load_loan(env: &Env, loan_id: u64) -> Option<Loan> {
+        let key = DataKey::Loan(loan_id);
+        env.storage().persistent().get(&key).or_else(|| {
+            let legacy: LegacyLoan = env.storage().instance().get(&key)?;
+            Some(Self::adopt_legacy_loan(env, legacy))
+        })
+    }
+
+    // Helper function to convert a legacy loan, persist it and book it like a loan opened today.
+    // Legacy loans were funded through borrow, so they count as disbursed; their face value is unknown
+    // and taken to be the principal.
+    fn adopt_legacy_loan(env: &Env, legacy: LegacyLoan) -> Loan {
+        let open = !legacy.is_repaid && !legacy.is_defaulted;
+        let loan = Loan {
+            id: legacy.id,
+            borrower: legacy.borrower.clone(),
+            invoice_id: legacy.invoice_id,
+            invoice_amount: legacy.principal,
+            principal: legacy.principal,
+            interest: legacy.interest,
+            remaining_principal: if open { legacy.principal } else { 0 },
+            unpaid_interest: 0,
+            last_payment_time: legacy.start_time,
+            last_interest_payment: legacy.start_time,
+            collateral_claimed: 0,
+            collateral_locked: false,
+            start_time: legacy.start_time,
+            due_date: legacy.due_date,
+            is_disbursed: true,
+            is_repaid: legacy.is_repaid,
+            is_defaulted: legacy.is_defaulted,
+            guarantor: None,
+            terms_hash: BytesN::from_array(env, &[0; 32]),
+        };
+        Self::save_loan(env, &loan);
+
+        let mut borrower_loans = Self::get_loans_by_borrower(env.clone(), loan.borrower.clone());
+        if !borrower_loans.contains(loan.id) {
+            borrower_loans.push_back(loan.id);
+            Self::set_loans_by_borrower(env, &loan.borrower, &borrower_loans);
+        }
+        Self::mark_seen_borrower(env, &loan.borrower);
+        if Self::get_invoice_loan(env.clone(), loan.invoice_id).is_none() {
+            Self::set_persistent(env, &DataKey::InvoiceLoan(loan.invoice_id), &loan.id);
+        }
+        if open {
+            let mut active_loans = Self::active_loans(env);
+            active_loans.push_back(loan.id);
+            env.storage().instance().set(&DataKey::ActiveLoans, &active_loans);
+            Self::adjust_total_outstanding(env, loan.principal);
+        }
+        loan
+    }
+
+    // Helper function to write a loan to persistent storage, dropping any stale instance copy
+    fn save_loan(env: &Env, loan: &Loan) {
+        let key = DataKey::Loan(loan.id);
+        env.storage().persistent().set(&key, loan);
+        env.storage().persistent().extend_ttl(&key, TTL_EXTENSION, TTL_EXTENSION);
+        env.storage().instance().remove(&key);
+
+        // Entries kept per loan live as long as the loan does
+        for key in [DataKey::DefaultedAt(loan.id), DataKey::CollateralClaims(loan.id), DataKey::InvoiceLoan(loan.invoice_id)] {
+            if env.storage().persistent().has(&key) {
+                env.storage().persistent().extend_ttl(&key, TTL_EXTENSION, TTL_EXTENSION);
+            }
+        }
+    }
+
+    // Helper function to write a per-loan or per-address entry to persistent storage under a fresh TTL,
+    // keeping the instance entry bounded as borrowers and loans accumulate
+    fn set_persistent<V: IntoVal<Env, Val>>(env: &Env, key: &DataKey, value: &V) {
+        env.storage().persistent().set(key, value);
+        env.storage().persistent().extend_ttl(key, TTL_EXTENSION, TTL_EXTENSION);
+    }
+
+    // MIGRATE LOANS: Move legacy loans in [from_id, to_id] out of instance storage into persistent storage,
+    // and rewrite a legacy pause flag (admin only, re-runnable)
+    pub fn migrate_loans(env: Env, from_id: u64, to_id: u64) -> u32 {
+        Self::require_admin(&env);
+        if to_id < from_id || to_id - from_id >= MAX_PAGE_SIZE as u64 {
+            panic!("Invalid migration range");
+        }
+
+        let mut migrated = 0;
+        for loan_id in from_id..=to_id {
+            let key = DataKey::Loan(loan_id);
+            let legacy: Option<LegacyLoan> = env.storage().instance().get(&key);
+            if let Some(legacy) = legacy {
+                // A persistent copy is always the newer one, so only a missing copy is filled from instance storage
+                if env.storage().persistent().has(&key) {
+                    env.storage().instance().remove(&key);
+                } else {
+                    Self::adopt_legacy_loan(&env, legacy);
+                }
+                migrated += 1;
+            }
+        }
+        let flags = Self::get_pause_flags(env.clone());
+        env.storage().instance().set(&DataKey::Paused, &flags);
+        Self::extend_storage_ttl(&env);
+        migrated
+    }
+
+    // BUMP TTL: Anyone (e.g. a keeper) can keep the contract instance from being archived
+    pub fn bump_ttl(env: Env) {
+        Self::extend_storage_ttl(&env);
+        Self::publish_event(&env, (Symbol::new(&env, "ttl_bumped"),), TTL_EXTENSION);
+    }
+
+    // GET TTL INFO: Number of ledgers each TTL extension targets
+    pub fn get_ttl_info(_env: Env) -> u32 {
+        TTL_EXTENSION
+    }
+
+    // ERROR MESSAGE: Short symbol describing an error code, unknown_error for codes the contract doesn't define
+    pub fn error_message(env: Env, code: u32) -> Symbol {
+        let message = ERROR_MESSAGES.iter()
+            .find(|(error, _)| *error as u32 == code)
+            .map_or("unknown_error", |(_, message)| *message);
+        Symbol::new(&env, message)
+    }
+
+    // SET BACKEND PUBKEY: Initialize backend public key for signature verification
+    pub fn set_backend_pubkey(env: Env, pubkey: BytesN<32>) {
+        Self::require_admin(&env);
+        // An all-zero key is never a real backend key, only an unset or mistyped one
+        if pubkey.to_array() == [0u8; 32] {
+            panic!("Invalid backend key");
+        }
+        let old = env.storage().instance().get::<DataKey, BytesN<32>>(&DataKey::BackendPubkey);
+        env.storage().instance().set(&DataKey::BackendPubkey, &pubkey);
+        Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "backend_pubkey", old, Some(pubkey));
+    }
+
+    // CREATE LOAN: Create a new loan record, bound to the hash of its off-chain agreement
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_loan(env: Env, borrower: Address, invoice_id: u64, invoice_amount: i128, principal: i128, due_date: u64, guarantor: Option<Address>, terms_hash: BytesN<32>) -> u64 {
+        borrower.require_auth();
+        if let Some(guarantor) = &guarantor {
+            guarantor.require_auth();
+        }
+        Self::open_loan(&env, borrower, invoice_id, invoice_amount, principal, due_date, guarantor, terms_hash)
+    }
+
+    // Helper function to record a new loan once its borrower (and any guarantor) has authorized it
+    #[allow(clippy::too_many_arguments)]
+    fn open_loan(env: &Env, borrower: Address, invoice_id: u64, invoice_amount: i128, principal: i128, due_date: u64, guarantor: Option<Address>, terms_hash: BytesN<32>) -> u64 {
+        Self::check_paused(env, Operation::Borrows);
+        Self::check_not_blocked(env, &borrower);
+        if Self::get_remaining_loan_slots(env.clone()) == 0 {
+            panic!("Loan limit reached");
+        }
+
+        // The cap applies to what the collateral is worth, so a discounted oracle price lowers the limit
+        let max_ltv = Self::get_max_ltv(env.clone());
+        if max_ltv > 0 && principal * 10_000 > Self::invoice_value(env, invoice_id, invoice_amount) * max_ltv as i128 {
+            panic!("Loan exceeds LTV cap");
+        }
+
+        let current_time = env.ledger().timestamp();
+        let interest = Self::calculate_interest(env, principal, current_time, due_date, APY_BPS);
+
+        let deterministic = Self::deterministic_loan_ids(env.clone());
+        let loan_id = if deterministic {
+            Self::derive_loan_id(env, &borrower, invoice_id, current_time)
+        } else {
+            env.storage().instance().get(&DataKey::LoanId).unwrap_or(0u64)
+                .checked_add(1)
+                .expect("Counter overflow")
+        };
+
+        if Self::load_loan(env, loan_id).is_some() || env.storage().persistent().has(&DataKey::LoanArchive(loan_id)) {
+            panic!("Loan already exists");
+        }
+        // An invoice finances one loan, even once that loan is repaid and archived
+        if Self::get_invoice_loan(env.clone(), invoice_id).is_some() {
+            panic!("Invoice already financed");
+        }
+
+        // Lock the borrower's own invoice so it can't be transferred or pledged twice, and keep the loan within its
+        // maturity and at its real face amount
+        let invoice_contract = Self::get_invoice_contract(env.clone());
+        if let Some(invoice_contract) = &invoice_contract {
+            let invoice_client = InvoiceClient::new(env, invoice_contract);
+            if invoice_client.owner_of(&invoice_id) != Some(borrower.clone()) {
+                panic!("Not invoice owner");
+            }
+            if invoice_client.get_amount(&invoice_id) != invoice_amount {
+                panic!("Invoice amount mismatch");
+            }
+            let invoice_due_date = invoice_client.get_due_date(&invoice_id);
+            if current_time > invoice_due_date {
+                panic!("Invoice expired");
+            }
+            if due_date > invoice_due_date {
+                panic!("Loan outlives invoice");
+            }
+            invoice_client.lock_invoice(&invoice_id, &borrower);
+        }
+
+        let loan = Loan {
+            id: loan_id,
+            borrower: borrower.clone(),
+            invoice_id,
+            invoice_amount,
+            principal,
+            interest,
+            remaining_principal: principal,
+            unpaid_interest: 0,
+            last_payment_time: current_time,
+            last_interest_payment: current_time,
+            collateral_claimed: 0,
+            collateral_locked: invoice_contract.is_some(),
+            start_time: current_time,
+            due_date,
+            is_disbursed: false,
+            is_repaid: false,
+            is_defaulted: false,
+            guarantor,
+            terms_hash,
+        };
+
+        Self::save_loan(env, &loan);
+        if !deterministic {
+            env.storage().instance().set(&DataKey::LoanId, &loan_id);
+        }
+        // Large loans wait for an admin before they can be drawn
+        let auto_approve_limit = Self::get_auto_approve_limit(env.clone());
+        if auto_approve_limit > 0 && principal > auto_approve_limit {
+            env.storage().instance().set(&DataKey::PendingApproval(loan_id), &true);
+        }
+
+        let mut borrower_loans = Self::get_loans_by_borrower(env.clone(), borrower.clone());
+        borrower_loans.push_back(loan_id);
+        Self::set_loans_by_borrower(env, &borrower, &borrower_loans);
+        Self::set_persistent(env, &DataKey::InvoiceLoan(invoice_id), &loan_id);
+
+        Self::mark_seen_borrower(env, &borrower);
+
+        let mut active_loans = Self::active_loans(env);
+        active_loans.push_back(loan_id);
+        env.storage().instance().set(&DataKey::ActiveLoans, &active_loans);
+        Self::extend_storage_ttl(env);
+
+        Self::publish_loan_event(env, "loan_created", loan_id, borrower, principal);
+        loan_id
+    }
+
+    // SET MAX TOTAL LOANS: Cap how many loans may be open at once, bounding storage growth (admin only, 0 = unlimited)
+    pub fn set_max_total_loans(env: Env, max_loans: u32) {
+        Self::require_admin(&env);
+        let old = Self::get_max_total_loans(env.clone());
+        env.storage().instance().set(&ConfigKey::MaxTotalLoans, &max_loans);
+        Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "max_total_loans", old, max_loans);
+    }
+
+    // GET MAX TOTAL LOANS: Returns the open loan cap, 0 = unlimited
+    pub fn get_max_total_loans(env: Env) -> u32 {
+        env.storage().instance().get(&ConfigKey::MaxTotalLoans).unwrap_or(0)
+    }
+
+    // GET REMAINING LOAN SLOTS: How many more loans can be opened before the cap, u32::MAX when uncapped
+    pub fn get_remaining_loan_slots(env: Env) -> u32 {
+        let max_loans = Self::get_max_total_loans(env.clone());
+        if max_loans == 0 {
+            return u32::MAX;
+        }
+        max_loans.saturating_sub(Self::active_loans(&env).len())
+    }
+
+    // SET AUTO APPROVE LIMIT: Loans with a larger principal are created pending admin approval (admin only, 0 = no limit)
+    pub fn set_auto_approve_limit(env: Env, limit: i128) {
+        Self::require_admin(&env);
+        if limit < 0 {
+            panic!("Invalid auto-approve limit");
+        }
+        let old = Self::get_auto_approve_limit(env.clone());
+        env.storage().instance().set(&ConfigKey::AutoApproveLimit, &limit);
+        Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "auto_approve_limit", old, limit);
+    }
+
+    // GET AUTO APPROVE LIMIT: Returns the largest principal created without approval, 0 = no limit
+    pub fn get_auto_approve_limit(env: Env) -> i128 {
+        env.storage().instance().get(&ConfigKey::AutoApproveLimit).unwrap_or(0)
+    }
+
+    // APPROVE LOAN: Activate a loan created above the auto-approve limit so it can be disbursed (admin only)
+    pub fn approve_loan(env: Env, loan_id: u64) {
+        Self::require_admin(&env);
+        let loan: Loan = Self::load_loan(&env, loan_id)
+            .expect("Loan not found");
+        if !Self::is_pending_approval(env.clone(), loan_id) {
+            panic!("Loan not pending approval");
+        }
+
+        env.storage().instance().remove(&DataKey::PendingApproval(loan_id));
+        Self::extend_storage_ttl(&env);
+        Self::publish_loan_event(&env, "loan_approved", loan_id, loan.borrower, loan.principal);
+    }
+
+    // IS PENDING APPROVAL: Check whether a loan still awaits admin approval before disbursement
+    pub fn is_pending_approval(env: Env, loan_id: u64) -> bool {
+        env.storage().instance().has(&DataKey::PendingApproval(loan_id))
+    }
+
+    // MINT AND BORROW: Mint a backend-signed invoice and borrow against it in one call, so neither happens without the other
+    #[allow(clippy::too_many_arguments)]
+    pub fn mint_and_borrow(env: Env, owner: Address, amount: i128, due_date: u64, risk_score: u32, key_index: u32, signature: BytesN<64>, principal: i128, terms_hash: BytesN<32>) -> u64 {
+        // The owner authorizes this call, which covers the invoice contract's mint beneath it
+        owner.require_auth();
+        let invoice_contract = Self::get_invoice_contract(env.clone()).expect("Invoice contract not set");
+        let invoice_id = InvoiceClient::new(&env, &invoice_contract)
+            .mint(&owner, &amount, &due_date, &risk_score, &None, &key_index, &signature);
+
+        // A panic opening the loan rolls back the mint above with the rest of the transaction
+        Self::open_loan(&env, owner, invoice_id, amount, principal, due_date, None, terms_hash)
+    }
+
+    // SET DETERMINISTIC LOAN IDS: Derive ids from loan inputs instead of a shared counter (admin only)
+    pub fn set_deterministic_loan_ids(env: Env, enabled: bool) {
+        Self::require_admin(&env);
+        let old = Self::deterministic_loan_ids(env.clone());
+        env.storage().instance().set(&ConfigKey::DeterministicLoanIds, &enabled);
+        Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "deterministic_loan_ids", old, enabled);
+    }
+
+    // GET DETERMINISTIC LOAN IDS: Check whether loan ids are hash-derived
+    pub fn deterministic_loan_ids(env: Env) -> bool {
+        env.storage().instance().get(&ConfigKey::DeterministicLoanIds).unwrap_or(false)
+    }
+
+    // Helper function to derive a loan id from the first 8 bytes of sha256(borrower, invoice_id, start_time)
+    fn derive_loan_id(env: &Env, borrower: &Address, invoice_id: u64, start_time: u64) -> u64 {
+        let mut preimage = borrower.clone().to_xdr(env);
+        preimage.append(&invoice_id.to_xdr(env));
+        preimage.append(&start_time.to_xdr(env));
+
+        let hash = env.crypto().sha256(&preimage).to_array();
+        let mut id_bytes = [0u8; 8];
+        id_bytes.copy_from_slice(&hash[..8]);
+        u64::from_be_bytes(id_bytes)
+    }
+
+    // REPAY LOAN: Repay a loan and unlock collateral, returning a breakdown of what was charged
+    pub fn repay_loan(env: Env, loan_id: u64, repay_token: Option<Address>) -> RepaymentReceipt {
+        Self::check_paused(&env, Operation::Repayments);
+        
+        let mut loan = Self::load_for_repayment(&env, loan_id);
+        
+        if loan.is_repaid {
+            panic!("Loan already repaid");
+        }
+        
+        if loan.is_defaulted {
+            panic!("Loan defaulted - use liquidation instead");
+        }
+
+        if !loan.is_disbursed {
+            panic!("Loan not disbursed");
+        }
+        
+        loan.borrower.require_auth();
+
+        let token_addr: Address = env.storage().instance().get(&DataKey::TokenAddress)
+            .expect("Not initialized");
+        let client = token::Client::new(&env, &token_addr);
+
+        // Honor a fresh snapshot so the borrower is charged exactly the figure they were quoted
+        let current_interest = match Self::fresh_snapshot(&env, loan_id) {
+            Some(snapshot) => snapshot.amount - loan.remaining_principal,
+            None => Self::interest_due(&env, &loan, env.ledger().timestamp()),
+        };
+        let current_interest = current_interest - Self::early_repay_discount(&env, &loan, current_interest);
+        let penalty = Self::early_repayment_penalty(&env, &loan);
+        let receipt = RepaymentReceipt {
+            principal_paid: loan.remaining_principal,
+            interest_paid: current_interest,
+            fee_paid: penalty,
+            total_paid: loan.remaining_principal + current_interest + penalty,
+        };
+        let total_repayment = receipt.total_paid;
+
+        match repay_token.filter(|repay_token| *repay_token != token_addr) {
+            None => {
+                // Check borrower's USDC balance
+                let borrower_balance = client.balance(&loan.borrower);
+                if borrower_balance < total_repayment {
+                    panic!("Insufficient USDC balance");
+                }
+
+                // Transfer repayment from borrower to contract
+                client.transfer(&loan.borrower, &env.current_contract_address(), &total_repayment);
+            }
+            Some(repay_token) => {
+                // The pool only ever books the loan token: the borrower's tokens go to the settlement desk, which pays
+                // the pool the loan-token amount out of its allowance
+                let desk = Self::get_settlement_desk(env.clone()).expect("Settlement desk not set");
+                let converted = Self::convert_repayment(&env, &repay_token, &token_addr, total_repayment);
+                let repay_client = token::Client::new(&env, &repay_token);
+                if repay_client.balance(&loan.borrower) < converted {
+                    panic!("Insufficient balance");
+                }
+                repay_client.transfer(&loan.borrower, &desk, &converted);
+                client.transfer_from(&env.current_contract_address(), &desk, &env.current_contract_address(), &total_repayment);
+                Self::publish_loan_event(&env, "repaid_in_token", loan_id, loan.borrower.clone(), (repay_token, converted));
+            }
+        }
+
+        // Update loan status
+        Self::record_interest(&env, receipt.interest_paid + receipt.fee_paid);
+        Self::close_repaid_loan(&env, &mut loan, receipt.interest_paid);
+        Self::fulfill_withdraw_queue(&env);
+
+        receipt
+    }
+
+    // Helper function to price an amount owed in the loan token in units of another token, rounding in the pool's favour
+    fn convert_repayment(env: &Env, repay_token: &Address, loan_token: &Address, amount: i128) -> i128 {
+        let rate = Self::get_exchange_rate(env.clone(), repay_token.clone(), loan_token.clone())
+            .expect("No exchange rate for token");
+        (amount * EXCHANGE_RATE_SCALE + rate - 1) / rate
+    }
+
+    // SET EXCHANGE RATE: Loan token units one repay token is worth, in EXCHANGE_RATE_SCALE (admin only)
+    pub fn set_exchange_rate(env: Env, repay_token: Address, loan_token: Address, rate: i128) {
+        Self::require_admin(&env);
+        if rate <= 0 {
+            panic!("Invalid exchange rate");
+        }
+        let old = Self::get_exchange_rate(env.clone(), repay_token.clone(), loan_token.clone());
+        env.storage().instance().set(&ConfigKey::ExchangeRate(repay_token, loan_token), &rate);
+        Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "exchange_rate", old, Some(rate));
+    }
+
+    // GET EXCHANGE RATE: The configured rate between two tokens, if any
+    pub fn get_exchange_rate(env: Env, repay_token: Address, loan_token: Address) -> Option<i128> {
+        env.storage().instance().get(&ConfigKey::ExchangeRate(repay_token, loan_token))
+    }
+
+    // SET SETTLEMENT DESK: Counterparty that takes repayments in other tokens and pays the pool in the loan token,
+    // through an allowance it grants the pool; None disables foreign-token repayments (admin only)
+    pub fn set_settlement_desk(env: Env, desk: Option<Address>) {
+        Self::require_admin(&env);
+        let old = Self::get_settlement_desk(env.clone());
+        match &desk {
+            Some(desk) => env.storage().instance().set(&ConfigKey::SettlementDesk, desk),
+            None => env.storage().instance().remove(&ConfigKey::SettlementDesk),
+        }
+        Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "settlement_desk", old, desk);
+    }
+
+    // GET SETTLEMENT DESK: The counterparty settling foreign-token repayments, if any
+    pub fn get_settlement_desk(env: Env) -> Option<Address> {
+        env.storage().instance().get(&ConfigKey::SettlementDesk)
+    }
+
+    // SET MIN HOLD PERIOD: Seconds a loan must run before it can be closed without the early repayment penalty (admin only, 0 = none)
+    pub fn set_min_hold_period(env: Env, hold_period: u64) {
+        Self::require_admin(&env);
+        let old = Self::get_min_hold_period(env.clone());
+        env.storage().instance().set(&ConfigKey::MinHoldPeriod, &hold_period);
+        Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "min_hold_period", old, hold_period);
+    }
+
+    // GET MIN HOLD PERIOD: Returns the minimum hold period in seconds
+    pub fn get_min_hold_period(env: Env) -> u64 {
+        env.storage().instance().get(&ConfigKey::MinHoldPeriod).unwrap_or(0)
+    }
+
+    // SET EARLY REPAYMENT PENALTY: Charge on principal repaid within the minimum hold period, in basis points (admin only)
+    pub fn set_early_repayment_penalty(env: Env, penalty_bps: u32) {
+        Self::require_admin(&env);
+        if penalty_bps > 10_000 {
+            panic!("Invalid early repayment penalty");
+        }
+        let old = Self::get_early_repayment_penalty(env.clone());
+        env.storage().instance().set(&ConfigKey::EarlyRepaymentPenaltyBps, &penalty_bps);
+        Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "early_repayment_penalty_bps", old, penalty_bps);
+    }
+
+    // GET EARLY REPAYMENT PENALTY: Returns the early repayment penalty in basis points
+    pub fn get_early_repayment_penalty(env: Env) -> u32 {
+        env.storage().instance().get(&ConfigKey::EarlyRepaymentPenaltyBps).unwrap_or(0)
+    }
+
+    // SET EARLY REPAY DISCOUNT: Share of interest waived on loans repaid in full before their due date (admin only, 0 = none)
+    pub fn set_early_repay_discount(env: Env, discount_bps: u32) {
+        Self::require_admin(&env);
+        if discount_bps > 10_000 {
+            panic!("Invalid early repayment discount");
+        }
+        let old = Self::get_early_repay_discount(env.clone());
+        env.storage().instance().set(&ConfigKey::EarlyRepayDiscountBps, &discount_bps);
+        Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "early_repay_discount_bps", old, discount_bps);
+    }
+
+    // GET EARLY REPAY DISCOUNT: Returns the early repayment discount in basis points
+    pub fn get_early_repay_discount(env: Env) -> u32 {
+        env.storage().instance().get(&ConfigKey::EarlyRepayDiscountBps).unwrap_or(0)
+    }
+
+    // Helper function for the interest waived if a loan were closed now, 0 from the due date on
+    // Capped at 100% of the interest, so the borrower always pays back at least the principal
+    fn early_repay_discount(env: &Env, loan: &Loan, interest: i128) -> i128 {
+        if env.ledger().timestamp() >= loan.due_date || interest <= 0 {
+            return 0;
+        }
+        interest * Self::get_early_repay_discount(env.clone()) as i128 / 10_000
+    }
+
+    // Helper function for the penalty due if a loan were closed now, 0 once it has been held long enough
+    fn early_repayment_penalty(env: &Env, loan: &Loan) -> i128 {
+        let hold_until = loan.start_time.saturating_add(Self::get_min_hold_period(env.clone()));
+        if env.ledger().timestamp() >= hold_until {
+            return 0;
+        }
+        loan.remaining_principal * Self::get_early_repayment_penalty(env.clone()) as i128 / 10_000
+    }
+
+    // REPAY PARTIAL: Pay down part of a loan, interest first and then principal
+    pub fn repay_partial(env: Env, loan_id: u64, amount: i128) {
+        Self::check_paused(&env, Operation::Repayments);
+
+        let mut loan = Self::load_for_repayment(&env, loan_id);
+
+        if loan.is_repaid {
+            panic!("Loan already repaid");
+        }
+
+        if loan.is_defaulted {
+            panic!("Loan defaulted - use liquidation instead");
+        }
+
+        if !loan.is_disbursed {
+            panic!("Loan not disbursed");
+        }
+
+        if amount <= 0 {
+            panic!("Invalid repayment amount");
+        }
+
+        loan.borrower.require_auth();
+
+        let current_time = env.ledger().timestamp();
+        let health_before = Self::health_factor(&env, &loan);
+        let (accrued, fraction) = Self::accrue(&env, &loan, current_time);
+        let interest_due = accrued.max(0);
+        let penalty = Self::early_repayment_penalty(&env, &loan);
+        let total_owed = loan.remaining_principal + interest_due;
+
+        // A payment that covers everything closes the loan, even if it is below the minimum
+        let closes_loan = amount >= total_owed + penalty;
+        if !closes_loan && amount >= total_owed {
+            panic!("Repayment must cover early repayment penalty");
+        }
+        if !closes_loan && amount < Self::get_min_partial_repayment(env.clone()) {
+            panic!("Repayment below minimum");
+        }
+        let payment = if closes_loan { total_owed + penalty } else { amount };
+
+        let token_addr: Address = env.storage().instance().get(&DataKey::TokenAddress)
+            .expect("Not initialized");
+        token::Client::new(&env, &token_addr)
+            .transfer(&loan.borrower, &env.current_contract_address(), &payment);
+
+        if closes_loan {
+            Self::record_interest(&env, interest_due + penalty);
+            Self::close_repaid_loan(&env, &mut loan, interest_due);
+            Self::fulfill_withdraw_queue(&env);
+            return;
+        }
+
+        let principal_paid = if payment > interest_due { payment - interest_due } else { 0 };
+        Self::record_interest(&env, payment - principal_paid);
+        let interest_paid = Self::interest_paid(&env, loan_id) + payment - principal_paid;
+        env.storage().instance().set(&DataKey::InterestPaid(loan_id), &interest_paid);
+        loan.unpaid_interest = interest_due - (payment - principal_paid);
+        loan.remaining_principal -= principal_paid;
+        loan.last_payment_time = current_time;
+        if loan.unpaid_interest == 0 {
+            loan.last_interest_payment = current_time;
+        }
+        Self::save_loan(&env, &loan);
+        env.storage().instance().remove(&DataKey::OwedSnapshot(loan_id));
+        Self::carry_accrual(&env, loan_id, accrued, fraction);
+
+        // Whatever stays outstanding must still be covered. A borrower who can't restore the position in one go may
+        // still pay it down in steps, so a repayment below the minimum is accepted as long as it improves coverage
+        let min_health = Self::get_min_health_factor(env.clone());
+        let health_after = Self::health_factor(&env, &loan);
+        if min_health > 0 && health_after < min_health && health_after <= health_before {
+            panic!("Repayment leaves loan undercollateralized");
+        }
+        Self::adjust_total_outstanding(&env, -principal_paid);
+        Self::fulfill_withdraw_queue(&env);
+        Self::extend_storage_ttl(&env);
+
+        Self::publish_loan_event(&env, "partial_repay", loan_id, loan.borrower, payment);
+    }
+
+    // Helper function to mark a fully paid loan as repaid, archiving a summary in place of its full record
+    fn close_repaid_loan(env: &Env, loan: &mut Loan, final_interest: i128) {
+        let fraction = Self::accrue(env, loan, env.ledger().timestamp()).1;
+        Self::adjust_total_outstanding(env, -loan.remaining_principal);
+        loan.remaining_principal = 0;
+        loan.unpaid_interest = 0;
+        loan.last_payment_time = env.ledger().timestamp();
+        loan.is_repaid = true;
+        env.storage().instance().remove(&DataKey::OwedSnapshot(loan.id));
+        env.storage().instance().remove(&DataKey::InterestAdjustment(loan.id));
+        // The final sub-unit remainder stays behind for sweep_dust to collect
+        if fraction > 0 {
+            env.storage().instance().set(&DataKey::AccruedFraction(loan.id), &fraction);
+        } else {
+            env.storage().instance().remove(&DataKey::AccruedFraction(loan.id));
+        }
+        Self::remove_active_loan(env, loan.id);
+        if loan.last_payment_time <= loan.due_date {
+            Self::adjust_reputation(env, &loan.borrower, 1);
+        }
+        Self::extend_storage_ttl(env);
+
+        if loan.collateral_locked {
+            Self::unlock_collateral(env, loan);
+        }
+
+        // Keep only what history needs, under its own TTL, so closed loans stop weighing on loan storage
+        let summary = LoanSummary {
+            id: loan.id,
+            borrower: loan.borrower.clone(),
+            principal: loan.principal,
+            interest_paid: Self::interest_paid(env, loan.id) + final_interest,
+            closed_at: loan.last_payment_time,
+        };
+        let archive_key = DataKey::LoanArchive(loan.id);
+        env.storage().persistent().set(&archive_key, &summary);
+        env.storage().persistent().extend_ttl(&archive_key, TTL_EXTENSION, TTL_EXTENSION);
+        env.storage().persistent().extend_ttl(&DataKey::InvoiceLoan(loan.invoice_id), TTL_EXTENSION, TTL_EXTENSION);
+        let key = DataKey::Loan(loan.id);
+        env.storage().persistent().remove(&key);
+        env.storage().instance().remove(&key);
+        env.storage().instance().remove(&DataKey::InterestPaid(loan.id));
+        env.storage().instance().remove(&DataKey::BorrowFee(loan.id));
+        env.storage().instance().remove(&DataKey::RateSchedule(loan.id));
+        env.storage().instance().remove(&DataKey::OverdueNotified(loan.id));
+        env.storage().persistent().remove(&DataKey::DefaultedAt(loan.id));
+        env.storage().persistent().remove(&DataKey::CollateralClaims(loan.id));
+
+        // In a real implementation, we would transfer the NFT back to the borrower
+        // For now, we just emit an event
+        Self::publish_loan_event(env, "loan_repaid", loan.id, loan.borrower.clone(), ());
+    }
+
+    // Helper function to read the interest collected on a loan before its final payment
+    fn interest_paid(env: &Env, loan_id: u64) -> i128 {
+        env.storage().instance().get(&DataKey::InterestPaid(loan_id)).unwrap_or(0)
+    }
+
+    // Helper function to load a loan being repaid, telling an archived (repaid) loan apart from an unknown id
+    fn load_for_repayment(env: &Env, loan_id: u64) -> Loan {
+        match Self::load_loan(env, loan_id) {
+            Some(loan) => loan,
+            None if env.storage().persistent().has(&DataKey::LoanArchive(loan_id)) => panic!("Loan already repaid"),
+            None => panic!("Loan not found"),
+        }
+    }
+
+    // GET LOAN SUMMARY: The archived summary of a repaid loan, whose full record is no longer kept
+    pub fn get_loan_summary(env: Env, loan_id: u64) -> Option<LoanSummary> {
+        env.storage().persistent().get(&DataKey::LoanArchive(loan_id))
+    }
+
+    // GET INVOICE LOAN: The loan an invoice financed, whether still open or archived
+    pub fn get_invoice_loan(env: Env, invoice_id: u64) -> Option<u64> {
+        env.storage().persistent().get(&DataKey::InvoiceLoan(invoice_id))
+    }
+
+    // GET REPUTATION: A borrower's loans repaid on time minus loans they defaulted on
+    pub fn get_reputation(env: Env, borrower: Address) -> i64 {
+        env.storage().persistent().get(&DataKey::Reputation(borrower)).unwrap_or(0)
+    }
+
+    // Helper function to move a borrower's reputation up or down
+    fn adjust_reputation(env: &Env, borrower: &Address, delta: i64) {
+        let score = Self::get_reputation(env.clone(), borrower.clone()).saturating_add(delta);
+        Self::set_persistent(env, &DataKey::Reputation(borrower.clone()), &score);
+    }
+
+    // Helper function to split collected interest between the treasury and LP yield
+    fn record_interest(env: &Env, interest: i128) {
+        if interest <= 0 {
+            return;
+        }
+        let protocol_cut = interest * Self::get_protocol_share(env.clone()) as i128 / 10_000;
+        let treasury = Self::get_treasury_balance(env.clone()) + protocol_cut;
+        let lp_yield = Self::get_lp_yield(env.clone()) + (interest - protocol_cut);
+        env.storage().instance().set(&DataKey::TreasuryBalance, &treasury);
+        env.storage().instance().set(&DataKey::LpYield, &lp_yield);
+        let lifetime = Self::get_lifetime_interest(env.clone()) + interest;
+        env.storage().instance().set(&DataKey::LifetimeInterest, &lifetime);
+    }
+
+    // GET LIFETIME INTEREST: Total interest the pool has ever collected, unaffected by treasury payouts
+    pub fn get_lifetime_interest(env: Env) -> i128 {
+        env.storage().instance().get(&DataKey::LifetimeInterest).unwrap_or(0)
+    }
+
+    // SET PROTOCOL SHARE: Portion of interest kept by the protocol treasury, in basis points (admin only)
+    pub fn set_protocol_share(env: Env, share_bps: u32) {
+        Self::require_admin(&env);
+        if share_bps > 10_000 {
+            panic!("Invalid protocol share");
+        }
+        let old = Self::get_protocol_share(env.clone());
+        env.storage().instance().set(&ConfigKey::ProtocolShareBps, &share_bps);
+        Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "protocol_share_bps", old, share_bps);
+    }
+
+    // GET PROTOCOL SHARE: Returns the protocol share in basis points
+    pub fn get_protocol_share(env: Env) -> u32 {
+        env.storage().instance().get(&ConfigKey::ProtocolShareBps).unwrap_or(0)
+    }
+
+    // GET TREASURY BALANCE: Interest accrued to the protocol so far
+    pub fn get_treasury_balance(env: Env) -> i128 {
+        env.storage().instance().get(&DataKey::TreasuryBalance).unwrap_or(0)
+    }
+
+    // WITHDRAW TREASURY: Pay out protocol interest from the treasury (admin only)
+    pub fn withdraw_treasury(env: Env, to: Address, amount: i128) {
+        Self::pay_from_treasury(&env, &to, amount);
+        Self::publish_event(&env, (Symbol::new(&env, "treasury_withdrawn"), to), amount);
+    }
+
+    // WITHDRAW RESERVE: Draw on the treasury to fund operations, never on LP principal (admin only)
+    pub fn withdraw_reserve(env: Env, to: Address, amount: i128) {
+        Self::pay_from_treasury(&env, &to, amount);
+        Self::publish_event(&env, (Symbol::new(&env, "reserve_withdrawn"), to), amount);
+    }
+
+    // Helper function to transfer an admin-approved amount out of the treasury balance
+    fn pay_from_treasury(env: &Env, to: &Address, amount: i128) {
+        Self::require_admin(env);
+        let treasury = Self::get_treasury_balance(env.clone());
+        if amount <= 0 || amount > treasury {
+            panic!("Invalid treasury withdrawal");
+        }
+        if amount > Self::available_liquidity(env) {
+            panic!("Insufficient pool liquidity");
+        }
+
+        env.storage().instance().set(&DataKey::TreasuryBalance, &(treasury - amount));
+        Self::extend_storage_ttl(env);
+        let token_addr: Address = env.storage().instance().get(&DataKey::TokenAddress).expect("Not initialized");
+        token::Client::new(env, &token_addr).transfer(&env.current_contract_address(), to, &amount);
+    }
+
+    // SWEEP DUST: Move sub-unit interest left on closed loans into the treasury and clear it from storage (admin only)
+    pub fn sweep_dust(env: Env, loan_ids: Vec<u64>) -> i128 {
+        Self::require_admin(&env);
+        if loan_ids.len() > MAX_SWEEP_BATCH {
+            panic!("Too many loans");
+        }
+
+        let mut swept = 0;
+        for loan_id in loan_ids.iter() {
+            // Open loans still need their fraction, so only closed ones are swept
+            let closed = match Self::load_loan(&env, loan_id) {
+                Some(loan) => loan.is_repaid || loan.is_defaulted,
+                None => env.storage().persistent().has(&DataKey::LoanArchive(loan_id)),
+            };
+            let key = DataKey::AccruedFraction(loan_id);
+            if !closed || !env.storage().instance().has(&key) {
+                continue;
+            }
+            let fraction: i128 = env.storage().instance().get(&key).unwrap_or(0);
+            env.storage().instance().remove(&key);
+            swept += fraction;
+        }
+
+        // Whole units join the treasury; what is left waits for the next sweep
+        let dust: i128 = env.storage().instance().get(&DataKey::TreasuryDust).unwrap_or(0);
+        let total = dust + swept;
+        let treasury = Self::get_treasury_balance(env.clone()) + total / FRACTION_SCALE;
+        env.storage().instance().set(&DataKey::TreasuryBalance, &treasury);
+        env.storage().instance().set(&DataKey::TreasuryDust, &(total % FRACTION_SCALE));
+        Self::extend_storage_ttl(&env);
+        swept
+    }
+
+    // GET LP YIELD: Interest accrued to liquidity providers so far
+    pub fn get_lp_yield(env: Env) -> i128 {
+        env.storage().instance().get(&DataKey::LpYield).unwrap_or(0)
+    }
+
+    // SET MIN PARTIAL REPAYMENT: Reject dust partial repayments (admin only)
+    pub fn set_min_partial_repayment(env: Env, amount: i128) {
+        Self::require_admin(&env);
+        if amount < 0 {
+            panic!("Invalid minimum repayment");
+        }
+        let old = Self::get_min_partial_repayment(env.clone());
+        env.storage().instance().set(&ConfigKey::MinPartialRepayment, &amount);
+        Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "min_partial_repayment", old, amount);
+    }
+
+    // GET MIN PARTIAL REPAYMENT: Returns the smallest accepted partial repayment
+    pub fn get_min_partial_repayment(env: Env) -> i128 {
+        env.storage().instance().get(&ConfigKey::MinPartialRepayment).unwrap_or(0)
+    }
+
+    // SET MIN LIQUIDATION AMOUNT: Reject dust partial liquidations (admin only)
+    pub fn set_min_liquidation_amount(env: Env, amount: i128) {
+        Self::require_admin(&env);
+        if amount < 0 {
+            panic!("Invalid minimum liquidation");
+        }
+        let old = Self::get_min_liquidation_amount(env.clone());
+        env.storage().instance().set(&ConfigKey::MinLiquidationAmount, &amount);
+        Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "min_liquidation_amount", old, amount);
+    }
+
+    // GET MIN LIQUIDATION AMOUNT: Returns the smallest accepted partial liquidation
+    pub fn get_min_liquidation_amount(env: Env) -> i128 {
+        env.storage().instance().get(&ConfigKey::MinLiquidationAmount).unwrap_or(0)
+    }
+
+    // GET TOTAL OWED: Remaining principal plus interest owed as of now, or as of the default for a defaulted loan
+    pub fn get_total_owed(env: Env, loan_id: u64) -> i128 {
+        if Self::get_loan_summary(env.clone(), loan_id).is_some() {
+            return 0;
+        }
+        let loan: Loan = Self::load_loan(&env, loan_id)
+            .expect("Loan not found");
+        if loan.is_repaid {
+            return 0;
+        }
+        loan.remaining_principal + Self::interest_due(&env, &loan, env.ledger().timestamp())
+    }
+
+    // SNAPSHOT OWED: Cache the current owed amount so a follow-up repay_loan charges exactly that (borrower only)
+    pub fn snapshot_owed(env: Env, loan_id: u64) -> i128 {
+        let loan: Loan = Self::load_loan(&env, loan_id)
+            .expect("Loan not found");
+        if loan.is_repaid || loan.is_defaulted {
+            panic!("Loan is closed");
+        }
+        loan.borrower.require_auth();
+
+        let current_time = env.ledger().timestamp();
+        let snapshot = OwedSnapshot {
+            amount: loan.remaining_principal + Self::interest_due(&env, &loan, current_time),
+            timestamp: current_time,
+        };
+        env.storage().instance().set(&DataKey::OwedSnapshot(loan_id), &snapshot);
+        Self::extend_storage_ttl(&env);
+        snapshot.amount
+    }
+
+    // GET OWED SNAPSHOT: The cached owed amount for a loan, fresh or not
+    pub fn get_owed_snapshot(env: Env, loan_id: u64) -> Option<OwedSnapshot> {
+        env.storage().instance().get(&DataKey::OwedSnapshot(loan_id))
+    }
+
+    // SET SNAPSHOT WINDOW: Seconds an owed snapshot is honored by repay_loan (admin only)
+    pub fn set_snapshot_window(env: Env, window: u64) {
+        Self::require_admin(&env);
+        let old = Self::get_snapshot_window(env.clone());
+        env.storage().instance().set(&ConfigKey::SnapshotWindow, &window);
+        Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "snapshot_window", old, window);
+    }
+
+    // GET SNAPSHOT WINDOW: Returns the snapshot staleness window in seconds
+    pub fn get_snapshot_window(env: Env) -> u64 {
+        env.storage().instance().get(&ConfigKey::SnapshotWindow).unwrap_or(0)
+    }
+
+    // Helper function to fetch a loan's snapshot if it is still within the staleness window
+    fn fresh_snapshot(env: &Env, loan_id: u64) -> Option<OwedSnapshot> {
+        let snapshot = Self::get_owed_snapshot(env.clone(), loan_id)?;
+        if env.ledger().timestamp() - snapshot.timestamp > Self::get_snapshot_window(env.clone()) {
+            return None;
+        }
+        Some(snapshot)
+    }
+
+    // Helper function to compute interest owed at a point in time (carried plus newly accrued)
+    fn interest_due(env: &Env, loan: &Loan, at: u64) -> i128 {
+        Self::accrue(env, loan, at).0.max(0)
+    }
+
+    // Helper function to accrue interest in fixed point, returning whole units owed and the sub-unit remainder
+    // Carrying the remainder forward stops small payments on low-decimal tokens from truncating interest away
+    // The whole units include any admin adjustment, so they go negative while a reduction exceeds accrued interest
+    fn accrue(env: &Env, loan: &Loan, at: u64) -> (i128, i128) {
+        // A defaulted loan's interest is frozen at the time it defaulted
+        let at = match Self::get_defaulted_at(env.clone(), loan.id) {
+            Some(defaulted_at) => at.min(defaulted_at),
+            None => at,
+        };
+        let carried: i128 = env.storage().instance().get(&DataKey::AccruedFraction(loan.id)).unwrap_or(0);
+        let adjustment: i128 = env.storage().instance().get(&DataKey::InterestAdjustment(loan.id)).unwrap_or(0);
+        let scaled_principal = loan.remaining_principal * FRACTION_SCALE;
+        let scaled = carried + Self::accrued_interest(env, loan, scaled_principal, loan.last_payment_time, at);
+        (loan.unpaid_interest + adjustment + scaled / FRACTION_SCALE, scaled % FRACTION_SCALE)
+    }
+
+    // Helper function to store what is left over once interest is settled up to now: the sub-unit
+    // remainder, and any part of an interest reduction that accrued interest hasn't absorbed yet
+    fn carry_accrual(env: &Env, loan_id: u64, accrued: i128, fraction: i128) {
+        if fraction > 0 {
+            env.storage().instance().set(&DataKey::AccruedFraction(loan_id), &fraction);
+        } else {
+            env.storage().instance().remove(&DataKey::AccruedFraction(loan_id));
+        }
+        if accrued < 0 {
+            env.storage().instance().set(&DataKey::InterestAdjustment(loan_id), &accrued);
+        } else {
+            env.storage().instance().remove(&DataKey::InterestAdjustment(loan_id));
+        }
+    }
+
+    // ADJUST LOAN INTEREST: Correct a fixed-rate loan's interest after a pricing error or dispute (admin only)
+    pub fn adjust_loan_interest(env: Env, loan_id: u64, new_interest: i128) {
+        Self::require_admin(&env);
+
+        let mut loan: Loan = Self::load_loan(&env, loan_id)
+            .expect("Loan not found");
+        if loan.is_repaid {
+            panic!("Loan already repaid");
+        }
+        if loan.is_defaulted {
+            panic!("Loan is closed");
+        }
+        if new_interest < 0 {
+            panic!("Invalid interest");
+        }
+        if env.storage().instance().has(&DataKey::RateSchedule(loan_id)) {
+            panic!("Loan has a rate schedule");
+        }
+
+        // The difference is applied on top of accrual, so holding to maturity costs exactly new_interest
+        let old_interest = loan.interest;
+        let adjustment: i128 = env.storage().instance().get(&DataKey::InterestAdjustment(loan_id)).unwrap_or(0);
+        env.storage().instance().set(&DataKey::InterestAdjustment(loan_id), &(adjustment + new_interest - old_interest));
+        env.storage().instance().remove(&DataKey::OwedSnapshot(loan_id));
+        loan.interest = new_interest;
+        Self::save_loan(&env, &loan);
+        Self::extend_storage_ttl(&env);
+
+        Self::publish_loan_event(&env, "interest_adjusted", loan_id, loan.borrower.clone(), (old_interest, new_interest));
+    }
+
+    // LIQUIDATE: Cover the whole remaining principal of a defaulted loan in exchange for its remaining collateral
+    pub fn liquidate(env: Env, liquidator: Address, loan_id: u64) {
+        let mut loan = Self::load_for_liquidation(&env, &liquidator, loan_id);
+        let amount = loan.remaining_principal;
+        Self::settle_liquidation(&env, &liquidator, &mut loan, amount);
+        Self::publish_loan_event(&env, "loan_liquidated", loan_id, liquidator, amount);
+    }
+
+    // Helper function to authorize a liquidator and load a loan they may liquidate
+    fn load_for_liquidation(env: &Env, liquidator: &Address, loan_id: u64) -> Loan {
+        Self::check_paused(env, Operation::Liquidations);
+        liquidator.require_auth();
+        Self::check_not_blocked(env, liquidator);
+        if !Self::is_liquidator(env.clone(), liquidator.clone()) {
+            panic!("Liquidator not whitelisted");
+        }
+
+        let loan: Loan = Self::load_loan(env, loan_id)
+            .expect("Loan not found");
+
+        if loan.is_repaid {
+            panic!("Cannot liquidate repaid loan");
+        }
+
+        // A loan swept into default keeps its unrecovered principal, and its collateral, up for liquidation
+        if loan.is_defaulted && loan.remaining_principal == 0 {
+            panic!("Loan already liquidated");
+        }
+
+        if !loan.is_disbursed {
+            panic!("Loan not disbursed");
+        }
+
+        if !loan.is_defaulted && !Self::check_liquidatable(env, &loan) {
+            panic!("Cannot liquidate healthy loan");
+        }
+        loan
+    }
+
+    // Helper function to settle a liquidator covering amount of a loan's principal: they pay it plus any recovered
+    // interest and are credited a proportional share of the collateral, and covering the rest defaults the loan.
+    // On a loan already swept into default the principal was written off, so the payment recovers bad debt instead
+    fn settle_liquidation(env: &Env, liquidator: &Address, loan: &mut Loan, amount: i128) -> i128 {
+        let written_off = loan.is_defaulted;
+
+        // Lock in interest accrued so far before the principal it accrues on shrinks
+        let current_time = env.ledger().timestamp();
+        let (accrued, fraction) = Self::accrue(env, loan, current_time);
+        loan.unpaid_interest = accrued.max(0);
+        loan.last_payment_time = current_time;
+        Self::carry_accrual(env, loan.id, accrued, fraction);
+
+        // With recovery on, the interest on the covered principal is settled: the liquidator pays the configured
+        // share of it and the rest is written off
+        let recovery_bps = Self::get_interest_recovery(env.clone());
+        let mut recovered = 0;
+        if recovery_bps > 0 {
+            let interest_share = loan.unpaid_interest * amount / loan.remaining_principal;
+            recovered = interest_share * recovery_bps as i128 / 10_000;
+            loan.unpaid_interest -= interest_share;
+        }
+
+        let token_addr: Address = env.storage().instance().get(&DataKey::TokenAddress)
+            .expect("Not initialized");
+        token::Client::new(env, &token_addr).transfer(liquidator, &env.current_contract_address(), &(amount + recovered));
+        if recovered > 0 {
+            Self::record_recovered_interest(env, recovered);
+            Self::publish_loan_event(env, "interest_recovered", loan.id, liquidator.clone(), recovered);
+        }
+
+        // Share of the collateral still unclaimed, so the last liquidator receives the remainder exactly
+        let collateral_share = (loan.invoice_amount - loan.collateral_claimed) * amount / loan.remaining_principal;
+        loan.collateral_claimed += collateral_share;
+        loan.remaining_principal -= amount;
+        if loan.remaining_principal == 0 && !written_off {
+            Self::mark_defaulted(env, loan);
+            Self::remove_active_loan(env, loan.id);
+            Self::adjust_reputation(env, &loan.borrower, -1);
+        }
+
+        Self::save_loan(env, loan);
+        env.storage().instance().remove(&DataKey::OwedSnapshot(loan.id));
+        if written_off {
+            let bad_debt = Self::get_bad_debt(env.clone());
+            env.storage().instance().set(&DataKey::BadDebt, &(bad_debt - amount.min(bad_debt)));
+        } else {
+            Self::adjust_total_outstanding(env, -amount);
+        }
+
+        let claims_key = DataKey::CollateralClaims(loan.id);
+        let mut claims: Map<Address, i128> = env.storage().persistent().get(&claims_key).unwrap_or(Map::new(env));
+        let claimed = claims.get(liquidator.clone()).unwrap_or(0);
+        claims.set(liquidator.clone(), claimed + collateral_share);
+        Self::set_persistent(env, &claims_key, &claims);
+        Self::extend_storage_ttl(env);
+
+        // Once fully covered the loan defaults and its collateral is seized
+        if loan.is_defaulted && loan.remaining_principal == 0 && loan.collateral_locked {
+            Self::seize_collateral(env, loan);
+        }
+
+        collateral_share
+    }
+
+    // SET INTEREST RECOVERY: Share of accrued interest liquidators pay on top of the principal they cover (admin only, 0 = none)
+    pub fn set_interest_recovery(env: Env, recovery_bps: u32) {
+        Self::require_admin(&env);
+        if recovery_bps > 10_000 {
+            panic!("Invalid interest recovery");
+        }
+        let old = Self::get_interest_recovery(env.clone());
+        env.storage().instance().set(&ConfigKey::LiquidationInterestRecoveryBps, &recovery_bps);
+        Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "interest_recovery_bps", old, recovery_bps);
+    }
+
+    // GET INTEREST RECOVERY: Returns the interest recovery share in basis points
+    pub fn get_interest_recovery(env: Env) -> u32 {
+        env.storage().instance().get(&ConfigKey::LiquidationInterestRecoveryBps).unwrap_or(0)
+    }
+
+    // Helper function to book interest recovered in a liquidation: it first writes down bad debt, and the rest goes to the treasury
+    fn record_recovered_interest(env: &Env, recovered: i128) {
+        let bad_debt = Self::get_bad_debt(env.clone());
+        let written_down = recovered.min(bad_debt);
+        env.storage().instance().set(&DataKey::BadDebt, &(bad_debt - written_down));
+        let treasury = Self::get_treasury_balance(env.clone()) + recovered - written_down;
+        env.storage().instance().set(&DataKey::TreasuryBalance, &treasury);
+        let lifetime = Self::get_lifetime_interest(env.clone()) + recovered;
+        env.storage().instance().set(&DataKey::LifetimeInterest, &lifetime);
+    }
+
+    // SET LIQUIDATOR WHITELIST: Restrict liquidations to listed liquidators, or open them to anyone (admin only)
+    pub fn set_liquidator_whitelist(env: Env, enabled: bool) {
+        Self::require_admin(&env);
+        let old = Self::is_liquidator_whitelist_enabled(env.clone());
+        env.storage().instance().set(&ConfigKey::LiquidatorWhitelist, &enabled);
+        Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "liquidator_whitelist", old, enabled);
+    }
+
+    // IS LIQUIDATOR WHITELIST ENABLED: Check whether liquidations are permissioned
+    pub fn is_liquidator_whitelist_enabled(env: Env) -> bool {
+        env.storage().instance().get(&ConfigKey::LiquidatorWhitelist).unwrap_or(false)
+    }
+
+    // ADD LIQUIDATOR: Put an address on the liquidator whitelist (admin only)
+    pub fn add_liquidator(env: Env, liquidator: Address) {
+        Self::require_admin(&env);
+        env.storage().instance().set(&ConfigKey::Liquidator(liquidator.clone()), &true);
+        Self::extend_storage_ttl(&env);
+        Self::publish_event(&env, (Symbol::new(&env, "liquidator_added"), liquidator), ());
+    }
+
+    // REMOVE LIQUIDATOR: Take an address off the liquidator whitelist (admin only)
+    pub fn remove_liquidator(env: Env, liquidator: Address) {
+        Self::require_admin(&env);
+        env.storage().instance().remove(&ConfigKey::Liquidator(liquidator.clone()));
+        Self::extend_storage_ttl(&env);
+        Self::publish_event(&env, (Symbol::new(&env, "liquidator_removed"), liquidator), ());
+    }
+
+    // IS LIQUIDATOR: Whether an address may liquidate, which is anyone while the whitelist is off
+    pub fn is_liquidator(env: Env, liquidator: Address) -> bool {
+        !Self::is_liquidator_whitelist_enabled(env.clone())
+            || env.storage().instance().has(&ConfigKey::Liquidator(liquidator))
+    }
+
+    // SET BLOCKED: Bar an address from depositing, borrowing and liquidating, or lift the bar (admin only)
+    pub fn set_blocked(env: Env, addr: Address, blocked: bool) {
+        Self::require_admin(&env);
+        if blocked {
+            env.storage().instance().set(&ConfigKey::Blocked(addr.clone()), &true);
+        } else {
+            env.storage().instance().remove(&ConfigKey::Blocked(addr.clone()));
+        }
+        Self::extend_storage_ttl(&env);
+        Self::publish_event(&env, (Symbol::new(&env, "address_blocked"), addr), blocked);
+    }
+
+    // IS BLOCKED: Check whether an address is on the blocklist
+    pub fn is_blocked(env: Env, addr: Address) -> bool {
+        env.storage().instance().has(&ConfigKey::Blocked(addr))
+    }
+
+    // Helper function to reject blocked addresses
+    fn check_not_blocked(env: &Env, addr: &Address) {
+        if Self::is_blocked(env.clone(), addr.clone()) {
+            panic!("Address is blocked");
+        }
+    }
+
+    // GET PERMISSIONS: What an address can currently do, combining the blocklist, pause flags, liquidator whitelist and admin role
+    pub fn get_permissions(env: Env, addr: Address) -> Permissions {
+        let admin: Address = env.storage().instance().get(&DataKey::Admin).expect("Not initialized");
+        let flags = Self::get_pause_flags(env.clone());
+        let is_blocked = Self::is_blocked(env.clone(), addr.clone());
+        Permissions {
+            can_deposit: !is_blocked && !flags.deposits,
+            can_borrow: !is_blocked && !flags.borrows,
+            can_liquidate: !is_blocked && !flags.liquidations && Self::is_liquidator(env, addr.clone()),
+            is_admin: addr == admin,
+            is_blocked,
+        }
+    }
+
+    // LIQUIDATE PARTIAL: Cover part of a defaulted loan's principal for a proportional share of its collateral
+    pub fn liquidate_partial(env: Env, liquidator: Address, loan_id: u64, amount: i128) -> i128 {
+        let mut loan = Self::load_for_liquidation(&env, &liquidator, loan_id);
+
+        if amount <= 0 || amount > loan.remaining_principal {
+            panic!("Invalid liquidation amount");
+        }
+        // Dust liquidations would only bloat state, so each one must be worthwhile or finish the loan off
+        if amount < loan.remaining_principal && amount < Self::get_min_liquidation_amount(env.clone()) {
+            panic!("Liquidation below minimum");
+        }
+
+        let collateral_share = Self::settle_liquidation(&env, &liquidator, &mut loan, amount);
+        Self::publish_loan_event(&env, "partial_liquidation", loan_id, liquidator, (amount, collateral_share));
+        collateral_share
+    }
+
+    // SWEEP DEFAULTS: Mark every loan in the batch that is in default as defaulted, returning the ids that changed
+    // (keeper callable). Unrecovered principal is written off, but the loan stays open to liquidators, who recover it
+    // against the collateral
+    pub fn sweep_defaults(env: Env, loan_ids: Vec<u64>) -> Vec<u64> {
+        Self::check_paused(&env, Operation::Liquidations);
+        if loan_ids.len() > MAX_SWEEP_BATCH {
+            panic!("Too many loans");
+        }
+
+        let mut defaulted = Vec::new(&env);
+        for loan_id in loan_ids.iter() {
+            // Ineligible ids are skipped so one stale entry can't revert the whole sweep
+            let mut loan: Loan = match Self::load_loan(&env, loan_id) {
+                Some(loan) => loan,
+                None => continue,
+            };
+            if !loan.is_disbursed || loan.is_repaid || loan.is_defaulted || !Self::is_in_default(&env, &loan) {
+                continue;
+            }
+
+            Self::mark_defaulted(&env, &mut loan);
+            Self::adjust_reputation(&env, &loan.borrower, -1);
+            env.storage().instance().remove(&DataKey::OwedSnapshot(loan_id));
+            Self::adjust_total_outstanding(&env, -loan.remaining_principal);
+            Self::remove_active_loan(&env, loan_id);
+            let shortfall = loan.remaining_principal;
+            loan.remaining_principal -= Self::cover_shortfall(&env, &loan, shortfall);
+            Self::save_loan(&env, &loan);
+
+            Self::publish_loan_event(&env, "loan_defaulted", loan_id, loan.borrower.clone(), shortfall);
+            defaulted.push_back(loan_id);
+        }
+
+        Self::extend_storage_ttl(&env);
+        defaulted
+    }
+
+    // Helper function to flag a loan as defaulted, recording when so its interest stops accruing there
+    fn mark_defaulted(env: &Env, loan: &mut Loan) {
+        loan.is_defaulted = true;
+        Self::set_persistent(env, &DataKey::DefaultedAt(loan.id), &env.ledger().timestamp());
+    }
+
+    // GET DEFAULTED AT: Ledger time a loan defaulted, if it has
+    pub fn get_defaulted_at(env: Env, loan_id: u64) -> Option<u64> {
+        env.storage().persistent().get(&DataKey::DefaultedAt(loan_id))
+    }
+
+    // Helper function to collect a defaulted loan's shortfall from its guarantor, recording whatever remains as bad debt
+    // and returning what was collected. Pulls through the allowance the guarantor granted the pool, capped by their
+    // balance, so it never reverts the default
+    fn cover_shortfall(env: &Env, loan: &Loan, shortfall: i128) -> i128 {
+        if shortfall <= 0 {
+            return 0;
+        }
+
+        let mut collected = 0;
+        if let Some(guarantor) = &loan.guarantor {
+            let token_addr: Address = env.storage().instance().get(&DataKey::TokenAddress)
+                .expect("Not initialized");
+            let client = token::Client::new(env, &token_addr);
+            let pool = env.current_contract_address();
+            collected = shortfall
+                .min(client.allowance(guarantor, &pool))
+                .min(client.balance(guarantor));
+            if collected > 0 {
+                client.transfer_from(&pool, guarantor, &pool, &collected);
+                Self::publish_loan_event(env, "guarantee_called", loan.id, guarantor.clone(), collected);
+            }
+        }
+
+        if collected < shortfall {
+            let bad_debt = Self::get_bad_debt(env.clone()) + shortfall - collected;
+            env.storage().instance().set(&DataKey::BadDebt, &bad_debt);
+        }
+        collected
+    }
+
+    // GET BAD DEBT: Defaulted principal that was never recovered
+    pub fn get_bad_debt(env: Env) -> i128 {
+        env.storage().instance().get(&DataKey::BadDebt).unwrap_or(0)
+    }
+
+    // GET COLLATERAL CLAIM: Collateral a liquidator has earned from a loan through partial liquidations
+    pub fn get_collateral_claim(env: Env, loan_id: u64, liquidator: Address) -> i128 {
+        let claims: Map<Address, i128> = env.storage().persistent()
+            .get(&DataKey::CollateralClaims(loan_id))
+            .unwrap_or(Map::new(&env));
+        claims.get(liquidator).unwrap_or(0)
+    }
+
+    // Helper function to mark a loan's locked invoice as seized
+    fn seize_collateral(env: &Env, loan: &Loan) {
+        let invoice_contract = Self::get_invoice_contract(env.clone()).expect("Invoice contract not set");
+        InvoiceClient::new(env, &invoice_contract).mark_seized(&loan.invoice_id);
+    }
+
+    // Helper function to unlock a loan's invoice and record that it no longer backs the loan
+    fn unlock_collateral(env: &Env, loan: &mut Loan) {
+        let invoice_contract = Self::get_invoice_contract(env.clone()).expect("Invoice contract not set");
+        InvoiceClient::new(env, &invoice_contract).unlock_invoice(&loan.invoice_id);
+        loan.collateral_locked = false;
+        Self::save_loan(env, loan);
+    }
+
+    // RELEASE COLLATERAL: Unlock the invoice once principal is paid down to the release ratio (borrower only)
+    pub fn release_collateral(env: Env, loan_id: u64) {
+        Self::check_paused(&env, Operation::Repayments);
+
+        let mut loan: Loan = Self::load_loan(&env, loan_id)
+            .expect("Loan not found");
+        loan.borrower.require_auth();
+
+        // A defaulted loan's collateral belongs to the pool now, however little principal was left
+        if loan.is_defaulted {
+            panic!("Loan defaulted");
+        }
+        if !loan.collateral_locked {
+            panic!("Collateral not locked");
+        }
+
+        // remaining / principal <= ratio, kept in integers
+        let ratio = Self::get_release_ratio(env.clone()) as i128;
+        if loan.remaining_principal * 10_000 > loan.principal * ratio {
+            panic!("Loan still outstanding");
+        }
+
+        Self::unlock_collateral(&env, &mut loan);
+        Self::extend_storage_ttl(&env);
+
+        Self::publish_loan_event(&env, "collateral_released", loan_id, loan.borrower, ());
+    }
+
+    // SET RELEASE RATIO: Share of principal (bps) still outstanding at which collateral can be released (admin only)
+    pub fn set_release_ratio(env: Env, ratio_bps: u32) {
+        Self::require_admin(&env);
+        if ratio_bps > 10_000 {
+            panic!("Invalid release ratio");
+        }
+        let old = Self::get_release_ratio(env.clone());
+        env.storage().instance().set(&ConfigKey::ReleaseRatioBps, &ratio_bps);
+        Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "release_ratio_bps", old, ratio_bps);
+    }
+
+    // GET RELEASE RATIO: Returns the release ratio in basis points, 0 = only once fully repaid
+    pub fn get_release_ratio(env: Env) -> u32 {
+        env.storage().instance().get(&ConfigKey::ReleaseRatioBps).unwrap_or(0)
+    }
+
+    // VERIFY WIRING: Cross-check that this pool and an InvoiceContract are linked to each other and live
+    pub fn verify_wiring(env: Env, invoice_contract: Address) -> WiringReport {
+        let invoice_client = InvoiceClient::new(&env, &invoice_contract);
+
+        let pool_knows_invoice = Self::get_invoice_contract(env.clone()) == Some(invoice_contract.clone());
+        let invoice_knows_pool = invoice_client.get_lending_pool() == Some(env.current_contract_address());
+        let backend_key_shared = match env.storage().instance().get::<_, BytesN<32>>(&DataKey::BackendPubkey) {
+            Some(pubkey) => invoice_client.get_backends().values().contains(&pubkey),
+            None => false,
+        };
+        let pool_active = !Self::is_paused(env.clone());
+        let invoice_active = !invoice_client.is_paused();
+
+        WiringReport {
+            pool_knows_invoice,
+            invoice_knows_pool,
+            backend_key_shared,
+            pool_active,
+            invoice_active,
+            healthy: pool_knows_invoice && invoice_knows_pool && backend_key_shared && pool_active && invoice_active,
+        }
+    }
+
+    // SET INVOICE CONTRACT: Link the InvoiceContract holding loan collateral (admin only)
+    pub fn set_invoice_contract(env: Env, invoice_contract: Address) {
+        Self::require_admin(&env);
+        let old = Self::get_invoice_contract(env.clone());
+        env.storage().instance().set(&DataKey::InvoiceContract, &invoice_contract);
+        Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "invoice_contract", old, Some(invoice_contract));
+    }
+
+    // GET INVOICE CONTRACT: The linked InvoiceContract, if any
+    pub fn get_invoice_contract(env: Env) -> Option<Address> {
+        env.storage().instance().get(&DataKey::InvoiceContract)
+    }
+
+    // SET LIQUIDATION THRESHOLD: Health factor (bps) below which loans become liquidatable (admin only, 0 = disabled)
+    pub fn set_liquidation_threshold(env: Env, threshold_bps: u32) {
+        Self::require_admin(&env);
+        let old = Self::get_liquidation_threshold(env.clone());
+        env.storage().instance().set(&ConfigKey::LiquidationThresholdBps, &threshold_bps);
+        Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "liquidation_threshold_bps", old, threshold_bps);
+    }
+
+    // GET LIQUIDATION THRESHOLD: Returns the configured threshold in basis points
+    pub fn get_liquidation_threshold(env: Env) -> u32 {
+        env.storage().instance().get(&ConfigKey::LiquidationThresholdBps).unwrap_or(0)
+    }
+
+    // SET MAX LTV: Cap a new loan's principal as a share of its invoice amount, in basis points (admin only, 0 = uncapped)
+    pub fn set_max_ltv(env: Env, ltv_bps: u32) {
+        Self::require_admin(&env);
+        if ltv_bps > 10_000 {
+            panic!("Invalid LTV cap");
+        }
+        let old = Self::get_max_ltv(env.clone());
+        env.storage().instance().set(&ConfigKey::MaxLtvBps, &ltv_bps);
+        Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "max_ltv_bps", old, ltv_bps);
+    }
+
+    // GET MAX LTV: Returns the LTV cap in basis points (0 = uncapped)
+    pub fn get_max_ltv(env: Env) -> u32 {
+        env.storage().instance().get(&ConfigKey::MaxLtvBps).unwrap_or(0)
+    }
+
+    // REQUIRED COLLATERAL: Smallest collateral value (oracle price, else face amount) the LTV cap accepts for a borrow
+    // amount, 0 when uncapped
+    pub fn required_collateral(env: Env, borrow_amount: i128) -> i128 {
+        let max_ltv = Self::get_max_ltv(env) as i128;
+        if max_ltv == 0 || borrow_amount <= 0 {
+            return 0;
+        }
+        // Rounded up, since rounding down would leave the loan just over the cap
+        (borrow_amount * 10_000 + max_ltv - 1) / max_ltv
+    }
+
+    // SET ORACLE: Price collateral through an external source instead of its face amount (admin only)
+    pub fn set_oracle(env: Env, oracle: Address) {
+        Self::require_admin(&env);
+        let old = Self::get_oracle(env.clone());
+        env.storage().instance().set(&ConfigKey::Oracle, &oracle);
+        Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "oracle", old, Some(oracle));
+    }
+
+    // GET ORACLE: The collateral price source, if any
+    pub fn get_oracle(env: Env) -> Option<Address> {
+        env.storage().instance().get(&ConfigKey::Oracle)
+    }
+
+    // GET COLLATERAL VALUE: What a loan's collateral is worth now, per the oracle or else its face amount
+    pub fn get_collateral_value(env: Env, loan_id: u64) -> i128 {
+        let loan: Loan = Self::load_loan(&env, loan_id)
+            .expect("Loan not found");
+        Self::collateral_value(&env, &loan)
+    }
+
+    // Helper function to value a loan's collateral, falling back to face amount without an oracle
+    fn collateral_value(env: &Env, loan: &Loan) -> i128 {
+        Self::invoice_value(env, loan.invoice_id, loan.invoice_amount)
+    }
+
+    // Helper function to value an invoice, falling back to its face amount without an oracle
+    fn invoice_value(env: &Env, invoice_id: u64, invoice_amount: i128) -> i128 {
+        match Self::get_oracle(env.clone()) {
+            Some(oracle) => OracleClient::new(env, &oracle).price_of(&invoice_id),
+            None => invoice_amount,
+        }
+    }
+
+    // SET MIN HEALTH FACTOR: Health factor (bps) a partial repayment must leave a loan at (admin only, 0 = disabled)
+    pub fn set_min_health_factor(env: Env, health_factor_bps: u32) {
+        Self::require_admin(&env);
+        let old = Self::get_min_health_factor(env.clone());
+        env.storage().instance().set(&ConfigKey::MinHealthFactor, &health_factor_bps);
+        Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "min_health_factor_bps", old, health_factor_bps);
+    }
+
+    // GET MIN HEALTH FACTOR: Returns the minimum health factor in basis points (0 = disabled)
+    pub fn get_min_health_factor(env: Env) -> u32 {
+        env.storage().instance().get(&ConfigKey::MinHealthFactor).unwrap_or(0)
+    }
+
+    // REQUIRED REPAYMENT: Smallest partial repayment that brings a loan back to the minimum health factor, 0 if it is there
+    pub fn required_repayment(env: Env, loan_id: u64) -> i128 {
+        let loan: Loan = Self::load_loan(&env, loan_id)
+            .expect("Loan not found");
+        let min_health = Self::get_min_health_factor(env.clone()) as i128;
+        if min_health == 0 || loan.is_repaid || loan.is_defaulted {
+            return 0;
+        }
+        let owed = loan.remaining_principal + Self::interest_due(&env, &loan, env.ledger().timestamp());
+        let max_owed = Self::collateral_value(&env, &loan) * 10_000 / min_health;
+        (owed - max_owed).max(0)
+    }
+
+    // GET HEALTH FACTOR: Collateral value vs amount owed, in basis points (10000 = fully covered)
+    pub fn get_health_factor(env: Env, loan_id: u64) -> u32 {
+        let loan: Loan = Self::load_loan(&env, loan_id)
+            .expect("Loan not found");
+        Self::health_factor(&env, &loan)
+    }
+
+    // IS LIQUIDATABLE: A loan can be liquidated once past due or when its health factor drops below the threshold
+    pub fn is_liquidatable(env: Env, loan_id: u64) -> bool {
+        let loan: Loan = Self::load_loan(&env, loan_id)
+            .expect("Loan not found");
+        if loan.is_repaid || loan.is_defaulted {
+            return false;
+        }
+        Self::check_liquidatable(&env, &loan)
+    }
+
+    // Helper function to compute a loan's health factor at the current ledger time
+    fn health_factor(env: &Env, loan: &Loan) -> u32 {
+        let owed = loan.remaining_principal + Self::interest_due(env, loan, env.ledger().timestamp());
+        if owed <= 0 {
+            return u32::MAX;
+        }
+        let factor = Self::collateral_value(env, loan) * 10_000 / owed;
+        if factor > u32::MAX as i128 {
+            u32::MAX
+        } else {
+            factor as u32
+        }
+    }
+
+    // Helper function to check the time-based and value-based liquidation triggers
+    fn check_liquidatable(env: &Env, loan: &Loan) -> bool {
+        if Self::is_in_default(env, loan) {
+            return true;
+        }
+        let threshold = Self::get_liquidation_threshold(env.clone());
+        threshold > 0 && Self::health_factor(env, loan) < threshold
+    }
+
+    // POKE LOAN: Emit loan_overdue the first time a loan is seen past its due date (permissionless, for keepers)
+    pub fn poke_loan(env: Env, loan_id: u64) -> bool {
+        let loan: Loan = Self::load_loan(&env, loan_id)
+            .expect("Loan not found");
+        let notified_key = DataKey::OverdueNotified(loan_id);
+        if loan.is_repaid
+            || loan.is_defaulted
+            || env.ledger().timestamp() <= loan.due_date
+            || env.storage().instance().has(&notified_key)
+        {
+            return false;
+        }
+
+        env.storage().instance().set(&notified_key, &true);
+        Self::extend_storage_ttl(&env);
+        Self::publish_loan_event(&env, "loan_overdue", loan_id, loan.borrower.clone(), loan.due_date);
+        true
+    }
+
+    // QUOTE EXTENSION: Fee for pushing a loan's due date back, priced as interest on the outstanding principal over the extra time
+    pub fn quote_extension(env: Env, loan_id: u64, additional_seconds: u64) -> i128 {
+        let loan: Loan = Self::load_loan(&env, loan_id)
+            .expect("Loan not found");
+        Self::extension_fee(&env, &loan, additional_seconds)
+    }
+
+    // Helper function to price an extension of a loan's term
+    fn extension_fee(env: &Env, loan: &Loan, additional_seconds: u64) -> i128 {
+        let new_due_date = loan.due_date.saturating_add(additional_seconds);
+        Self::loan_interest(env, loan.id, loan.start_time, loan.remaining_principal, loan.due_date, new_due_date)
+    }
+
+    // EXTEND LOAN: Push the due date back, paying the extension fee up front (borrower only)
+    pub fn extend_loan(env: Env, loan_id: u64, additional_seconds: u64) -> i128 {
+        Self::check_paused(&env, Operation::Borrows);
+
+        let mut loan: Loan = Self::load_loan(&env, loan_id)
+            .expect("Loan not found");
+        loan.borrower.require_auth();
+
+        if loan.is_repaid || loan.is_defaulted {
+            panic!("Loan is closed");
+        }
+        // An undisbursed loan can still be cancelled, which would forfeit a fee paid to extend it
+        if !loan.is_disbursed {
+            panic!("Loan not disbursed");
+        }
+        if additional_seconds == 0 {
+            panic!("Invalid extension");
+        }
+
+        let fee = Self::extension_fee(&env, &loan, additional_seconds);
+        if fee > 0 && !Self::collect_in_fee_token(&env, &loan.borrower, fee) {
+            let token_addr: Address = env.storage().instance().get(&DataKey::TokenAddress)
+                .expect("Not initialized");
+            token::Client::new(&env, &token_addr)
+                .transfer(&loan.borrower, &env.current_contract_address(), &fee);
+            Self::record_interest(&env, fee);
+
+            // Paid to the pool, the fee prepays the extension's interest, so credit it against accrual rather than
+            // charge that window twice. A fee taken in the fee token goes to the protocol and leaves interest owed
+            let adjustment: i128 = env.storage().instance().get(&DataKey::InterestAdjustment(loan_id)).unwrap_or(0);
+            env.storage().instance().set(&DataKey::InterestAdjustment(loan_id), &(adjustment - fee));
+            env.storage().instance().remove(&DataKey::OwedSnapshot(loan_id));
+        }
+
+        loan.due_date = loan.due_date.saturating_add(additional_seconds);
+        if let Some(invoice_contract) = Self::get_invoice_contract(env.clone()).filter(|_| loan.collateral_locked) {
+            if loan.due_date > InvoiceClient::new(&env, &invoice_contract).get_due_date(&loan.invoice_id) {
+                panic!("Loan outlives invoice");
+            }
+        }
+        Self::save_loan(&env, &loan);
+        Self::extend_storage_ttl(&env);
+
+        Self::publish_loan_event(&env, "loan_extended", loan_id, loan.borrower.clone(), (loan.due_date, fee));
+        fee
+    }
+
+    // SET BORROW FEE: Origination fee charged when a loan is disbursed, in basis points of principal (admin only)
+    pub fn set_borrow_fee(env: Env, fee_bps: u32) {
+        Self::require_admin(&env);
+        if fee_bps > 10_000 {
+            panic!("Invalid borrow fee");
+        }
+        let old = Self::get_borrow_fee(env.clone());
+        env.storage().instance().set(&ConfigKey::BorrowFeeBps, &fee_bps);
+        Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "borrow_fee_bps", old, fee_bps);
+    }
+
+    // GET BORROW FEE: Returns the origination fee in basis points
+    pub fn get_borrow_fee(env: Env) -> u32 {
+        env.storage().instance().get(&ConfigKey::BorrowFeeBps).unwrap_or(0)
+    }
+
+    // SET FEE TOKEN: Collect origination and extension fees in this token instead of the lending token,
+    // priced through its exchange rate to the lending token; None reverts to the lending token (admin only)
+    pub fn set_fee_token(env: Env, fee_token: Option<Address>) {
+        Self::require_admin(&env);
+        let old = Self::get_fee_token(env.clone());
+        match &fee_token {
+            Some(fee_token) => env.storage().instance().set(&ConfigKey::FeeToken, fee_token),
+            None => env.storage().instance().remove(&ConfigKey::FeeToken),
+        }
+        Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "fee_token", old, fee_token);
+    }
+
+    // GET FEE TOKEN: The token borrower fees are collected in, if not the lending token
+    pub fn get_fee_token(env: Env) -> Option<Address> {
+        env.storage().instance().get(&ConfigKey::FeeToken)
+    }
+
+    // Helper function to take a fee (in lending token units) from the payer in the fee token, returning false
+    // when no separate fee token is set and the caller should collect it in the lending token
+    fn collect_in_fee_token(env: &Env, payer: &Address, fee: i128) -> bool {
+        let token_addr: Address = env.storage().instance().get(&DataKey::TokenAddress).expect("Not initialized");
+        let fee_token = match Self::get_fee_token(env.clone()).filter(|fee_token| *fee_token != token_addr) {
+            Some(fee_token) => fee_token,
+            None => return false,
+        };
+
+        let amount = Self::convert_repayment(env, &fee_token, &token_addr, fee);
+        token::Client::new(env, &fee_token).transfer(payer, &env.current_contract_address(), &amount);
+        let collected = Self::get_collected_fees(env.clone(), fee_token.clone()) + amount;
+        env.storage().instance().set(&DataKey::CollectedFees(fee_token), &collected);
+        true
+    }
+
+    // GET COLLECTED FEES: Fees collected in a separate fee token and not yet withdrawn
+    pub fn get_collected_fees(env: Env, fee_token: Address) -> i128 {
+        env.storage().instance().get(&DataKey::CollectedFees(fee_token)).unwrap_or(0)
+    }
+
+    // WITHDRAW FEES: Pay out fees collected in a separate fee token (admin only)
+    pub fn withdraw_fees(env: Env, fee_token: Address, to: Address, amount: i128) {
+        Self::require_admin(&env);
+        let collected = Self::get_collected_fees(env.clone(), fee_token.clone());
+        if amount <= 0 || amount > collected {
+            panic!("Invalid fee withdrawal");
+        }
+
+        env.storage().instance().set(&DataKey::CollectedFees(fee_token.clone()), &(collected - amount));
+        Self::extend_storage_ttl(&env);
+        token::Client::new(&env, &fee_token).transfer(&env.current_contract_address(), &to, &amount);
+
+        Self::publish_event(&env, (Symbol::new(&env, "fees_withdrawn"), fee_token, to), amount);
+    }
+
+    // RESCHEDULE: Bring a loan's due date forward by mutual agreement (borrower and admin)
+    pub fn reschedule(env: Env, loan_id: u64, new_due_date: u64) {
+        let mut loan: Loan = Self::load_loan(&env, loan_id)
+            .expect("Loan not found");
+        loan.borrower.require_auth();
+        Self::require_admin(&env);
+
+        if loan.is_repaid || loan.is_defaulted {
+            panic!("Loan is closed");
+        }
+        if new_due_date >= loan.due_date {
+            panic!("Due date can only move earlier");
+        }
+        if new_due_date < env.ledger().timestamp() {
+            panic!("Due date in the past");
+        }
+
+        loan.due_date = new_due_date;
+        loan.interest = Self::loan_interest(&env, loan_id, loan.start_time, loan.principal, loan.start_time, new_due_date);
+        Self::save_loan(&env, &loan);
+        Self::extend_storage_ttl(&env);
+
+        Self::publish_loan_event(&env, "loan_rescheduled", loan_id, loan.borrower.clone(), new_due_date);
+    }
+
+    // CAPITALIZE INTEREST: Roll a loan's accrued interest into its principal under a workout agreement (borrower and admin)
+    pub fn capitalize_interest(env: Env, loan_id: u64) -> i128 {
+        let mut loan: Loan = Self::load_loan(&env, loan_id)
+            .expect("Loan not found");
+        loan.borrower.require_auth();
+        Self::require_admin(&env);
+
+        if loan.is_repaid || loan.is_defaulted {
+            panic!("Loan is closed");
+        }
+        // Growing the principal of an undisbursed loan would grow its payout too
+        if !loan.is_disbursed {
+            panic!("Loan not disbursed");
+        }
+
+        let current_time = env.ledger().timestamp();
+        let (accrued, fraction) = Self::accrue(&env, &loan, current_time);
+        let capitalized = accrued.max(0);
+
+        // The loan restarts from now on the larger principal, so its rate schedule and term run from here too
+        loan.principal += capitalized;
+        loan.remaining_principal += capitalized;
+        loan.unpaid_interest = 0;
+        loan.start_time = current_time;
+        loan.last_payment_time = current_time;
+        loan.last_interest_payment = current_time;
+        loan.interest = Self::loan_interest(&env, loan_id, loan.start_time, loan.principal, loan.start_time, loan.due_date);
+        Self::save_loan(&env, &loan);
+        env.storage().instance().remove(&DataKey::OwedSnapshot(loan_id));
+        Self::carry_accrual(&env, loan_id, accrued, fraction);
+        Self::adjust_total_outstanding(&env, capitalized);
+        Self::extend_storage_ttl(&env);
+
+        Self::publish_loan_event(&env, "capitalized", loan_id, loan.borrower.clone(), (capitalized, loan.remaining_principal));
+        capitalized
+    }
+
+    // SET GRACE PERIOD: Seconds an overdue loan is tolerated before liquidation (admin only)
+    pub fn set_grace_period(env: Env, grace_period: u64) {
+        Self::require_admin(&env);
+        let old = Self::get_grace_period(env.clone());
+        env.storage().instance().set(&ConfigKey::GracePeriod, &grace_period);
+        Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "grace_period", old, grace_period);
+    }
+
+    // GET GRACE PERIOD: Returns the grace period in seconds
+    pub fn get_grace_period(env: Env) -> u64 {
+        env.storage().instance().get(&ConfigKey::GracePeriod).unwrap_or(0)
+    }
+
+    // SET PENALTY RATE: Annual rate overdue loans accrue at from their due date until repaid or liquidated (admin only, 0 = none)
+    pub fn set_penalty_rate(env: Env, rate_bps: u32) {
+        Self::require_admin(&env);
+        let old = Self::get_penalty_rate(env.clone());
+        env.storage().instance().set(&ConfigKey::PenaltyRateBps, &rate_bps);
+        Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "penalty_rate_bps", old, rate_bps);
+    }
+
+    // GET PENALTY RATE: Returns the overdue penalty rate in basis points, 0 when overdue loans keep their own rate
+    pub fn get_penalty_rate(env: Env) -> u32 {
+        env.storage().instance().get(&ConfigKey::PenaltyRateBps).unwrap_or(0)
+    }
+
+    // TIME TO DUE: Seconds until the loan is due, negative once overdue
+    pub fn time_to_due(env: Env, loan_id: u64) -> i64 {
+        let loan: Loan = Self::load_loan(&env, loan_id)
+            .expect("Loan not found");
+        loan.due_date as i64 - env.ledger().timestamp() as i64
+    }
+
+    // TIME TO LIQUIDATION: Seconds until the grace period ends, negative once the loan can be liquidated
+    pub fn time_to_liquidation(env: Env, loan_id: u64) -> i64 {
+        let loan: Loan = Self::load_loan(&env, loan_id)
+            .expect("Loan not found");
+        Self::liquidation_time(&env, &loan) as i64 - env.ledger().timestamp() as i64
+    }
+
+    // SET INTEREST GRACE PERIOD: Seconds interest may go unserviced before the loan is defaultable (admin only, 0 = disabled)
+    pub fn set_interest_grace_period(env: Env, grace_period: u64) {
+        Self::require_admin(&env);
+        let old = Self::get_interest_grace_period(env.clone());
+        env.storage().instance().set(&ConfigKey::InterestGracePeriod, &grace_period);
+        Self::extend_storage_ttl(&env);
+        Self::config_changed(&env, "interest_grace_period", old, grace_period);
+    }
+
+    // GET INTEREST GRACE PERIOD: Returns the interest grace period in seconds
+    pub fn get_interest_grace_period(env: Env) -> u64 {
+        env.storage().instance().get(&ConfigKey::InterestGracePeriod).unwrap_or(0)
+    }
+
+    // Helper function to check whether accrued interest has gone unpaid for longer than the interest grace period
+    fn interest_overdue(env: &Env, loan: &Loan) -> bool {
+        let window = Self::get_interest_grace_period(env.clone());
+        let current_time = env.ledger().timestamp();
+        window > 0
+            && current_time > loan.last_interest_payment.saturating_add(window)
+            && Self::interest_due(env, loan, current_time) > 0
+    }
+
+    // Helper function for whether a loan is in default: past its grace period, or behind on periodic interest
+    fn is_in_default(env: &Env, loan: &Loan) -> bool {
+        env.ledger().timestamp() > Self::liquidation_time(env, loan) || Self::interest_overdue(env, loan)
+    }
+
+    // Helper function for the last moment an overdue loan is still protected by the grace period
+    fn liquidation_time(env: &Env, loan: &Loan) -> u64 {
+        loan.due_date.saturating_add(Self::get_grace_period(env.clone()))
+    }
+
+    // GET ACTIVE LOANS: Page through the ids of open loans, in creation order, for liquidation scanning
+    pub fn get_active_loans(env: Env, start: u32, limit: u32) -> Vec<u64> {
+        if limit > MAX_PAGE_SIZE {
+            panic!("Page too large");
+        }
+        let active_loans = Self::active_loans(&env);
+        if start >= active_loans.len() {
+            return Vec::new(&env);
+        }
+        let end = start.saturating_add(limit).min(active_loans.len());
+        active_loans.slice(start..end)
+    }
+
+    // GET LOANS DUE BETWEEN: Scan a page of the active set for loans due within [from_ts, to_ts]
+    // start and limit page through the active set, so a page may hold fewer than limit matches
+    pub fn get_loans_due_between(env: Env, from_ts: u64, to_ts: u64, start: u64, limit: u32) -> Vec<u64> {
+        let mut due = Vec::new(&env);
+        if start > u32::MAX as u64 {
+            return due;
+        }
+        for loan_id in Self::get_active_loans(env.clone(), start as u32, limit).iter() {
+            let loan: Loan = Self::load_loan(&env, loan_id)
+                .expect("Loan not found");
+            if loan.due_date >= from_ts && loan.due_date <= to_ts {
+                due.push_back(loan_id);
+            }
+        }
+        due
+    }
+
+    // GET PLEDGED INVOICES: Scan a page of the active set for invoices still locked as collateral
+    // start and limit page through the active set, so a page may hold fewer than limit invoices
+    pub fn get_pledged_invoices(env: Env, start: u32, limit: u32) -> Vec<u64> {
+        let mut pledged = Vec::new(&env);
+        for loan_id in Self::get_active_loans(env.clone(), start, limit).iter() {
+            let loan: Loan = Self::load_loan(&env, loan_id)
+                .expect("Loan not found");
+            if loan.collateral_locked {
+                pledged.push_back(loan.invoice_id);
+            }
+        }
+        pledged
+    }
+
+    // TOTAL ACCRUED INTEREST: Interest owed right now across a page of the active set, for provisioning
+    // start and limit page through the active set like get_active_loans; sum the pages for the pool-wide figure
+    pub fn total_accrued_interest(env: Env, start: u32, limit: u32) -> i128 {
+        let current_time = env.ledger().timestamp();
+        let mut total = 0;
+        for loan_id in Self::get_active_loans(env.clone(), start, limit).iter() {
+            let loan: Loan = Self::load_loan(&env, loan_id)
+                .expect("Loan not found");
+            total += Self::interest_due(&env, &loan, current_time);
+        }
+        total
+    }
+
+    // Helper function to read the ids of open loans
+    fn active_loans(env: &Env) -> Vec<u64> {
+        env.storage().instance().get(&DataKey::ActiveLoans).unwrap_or(Vec::new(env))
+    }
+
+    // Helper function to drop a closed loan from the active set
+    fn remove_active_loan(env: &Env, loan_id: u64) {
+        let mut active_loans = Self::active_loans(env);
+        if let Some(index) = active_loans.first_index_of(loan_id) {
+            active_loans.remove(index);
+            env.storage().instance().set(&DataKey::ActiveLoans, &active_loans);
+        }
+    }
+
+    // GET LOANS BY BORROWER: Ids of every loan a borrower is or was liable for, oldest first
+    pub fn get_loans_by_borrower(env: Env, borrower: Address) -> Vec<u64> {
+        env.storage().persistent().get(&DataKey::BorrowerLoans(borrower)).unwrap_or(Vec::new(&env))
+    }
+
+    // Helper function to store the ids of the loans a borrower is or was liable for
+    fn set_loans_by_borrower(env: &Env, borrower: &Address, loan_ids: &Vec<u64>) {
+        Self::set_persistent(env, &DataKey::BorrowerLoans(borrower.clone()), loan_ids);
+    }
+
+    // GET UNIQUE BORROWER COUNT: How many distinct addresses have ever taken a loan
+    pub fn get_unique_borrower_count(env: Env) -> u64 {
+        env.storage().instance().get(&DataKey::UniqueBorrowers).unwrap_or(0)
+    }
+
+    // ASSIGN LOAN: Novate an open loan to a new borrower (current borrower, new borrower, any guarantor and admin).
+    // The pledged invoice moves with the loan, so the new borrower gets it back on repayment
+    pub fn assign_loan(env: Env, loan_id: u64, new_borrower: Address) {
+        Self::check_paused(&env, Operation::Borrows);
+        let mut loan: Loan = Self::load_loan(&env, loan_id)
+            .expect("Loan not found");
+        loan.borrower.require_auth();
+        new_borrower.require_auth();
+        // A guarantee covers a particular borrower, so the guarantor has to agree to back the new one
+        if let Some(guarantor) = &loan.guarantor {
+            guarantor.require_auth();
+        }
+        Self::require_admin(&env);
+
+        if loan.is_repaid || loan.is_defaulted {
+            panic!("Loan is closed");
+        }
+        if new_borrower == loan.borrower {
+            panic!("Loan already held by borrower");
+        }
+        Self::check_not_blocked(&env, &new_borrower);
+
+        let previous = loan.borrower.clone();
+        let mut previous_loans = Self::get_loans_by_borrower(env.clone(), previous.clone());
+        if let Some(index) = previous_loans.first_index_of(loan_id) {
+            previous_loans.remove(index);
+            Self::set_loans_by_borrower(&env, &previous, &previous_loans);
+        }
+        let mut new_loans = Self::get_loans_by_borrower(env.clone(), new_borrower.clone());
+        new_loans.push_back(loan_id);
+        Self::set_loans_by_borrower(&env, &new_borrower, &new_loans);
+        Self::mark_seen_borrower(&env, &new_borrower);
+
+        if loan.collateral_locked {
+            let invoice_contract = Self::get_invoice_contract(env.clone()).expect("Invoice contract not set");
+            InvoiceClient::new(&env, &invoice_contract).reassign_invoice(&loan.invoice_id, &new_borrower);
+        }
+
+        loan.borrower = new_borrower.clone();
+        Self::save_loan(&env, &loan);
+        Self::extend_storage_ttl(&env);
+
+        Self::publish_loan_event(&env, "loan_assigned", loan_id, new_borrower, previous);
+    }
+
+    // Helper function to count an address among unique borrowers the first time it holds a loan
+    // Loan lists change on cancellation and assignment, so first-time borrowers are tracked separately
+    fn mark_seen_borrower(env: &Env, borrower: &Address) {
+        let seen_key = DataKey::SeenBorrower(borrower.clone());
+        if !env.storage().persistent().has(&seen_key) {
+            Self::set_persistent(env, &seen_key, &true);
+            let unique = Self::get_unique_borrower_count(env.clone()) + 1;
+            env.storage().instance().set(&DataKey::UniqueBorrowers, &unique);
+        }
+    }
+
+    // GET BORROWER EXPOSURE: Aggregate risk across all of a borrower's open loans
+    pub fn get_borrower_exposure(env: Env, borrower: Address) -> BorrowerExposure {
+        let loan_ids = Self::get_loans_by_borrower(env.clone(), borrower);
+        let current_time = env.ledger().timestamp();
+
+        let mut exposure = BorrowerExposure {
+            active_loans: 0,
+            outstanding_principal: 0,
+            accrued_interest: 0,
+            overdue_loans: 0,
+        };
+        for loan_id in loan_ids.iter() {
+            let loan: Loan = match Self::load_loan(&env, loan_id) {
+                Some(loan) => loan,
+                None => continue,
+            };
+            if loan.is_repaid || loan.is_defaulted {
+                continue;
+            }
+            exposure.active_loans += 1;
+            exposure.outstanding_principal += loan.remaining_principal;
+            exposure.accrued_interest += Self::interest_due(&env, &loan, current_time);
+            if current_time > loan.due_date {
+                exposure.overdue_loans += 1;
+            }
+        }
+        exposure
+    }
+
+    // GET EFFECTIVE APR: Annualized cost of a loan in bps, from everything charged over its term vs the principal
+    pub fn get_effective_apr(env: Env, loan_id: u64) -> u32 {
+        let loan: Loan = Self::load_loan(&env, loan_id)
+            .expect("Loan not found");
+        let term = loan.due_date.saturating_sub(loan.start_time);
+        if term == 0 || loan.principal <= 0 {
+            return 0;
+        }
+        // The origination fee is part of the cost: what disbursement charged, or what it would charge today
+        let fee = if loan.is_disbursed {
+            env.storage().instance().get(&DataKey::BorrowFee(loan_id)).unwrap_or(0)
+        } else {
+            loan.principal * Self::get_borrow_fee(env.clone()) as i128 / 10_000
+        };
+        let apr = (loan.interest + fee) * 10_000 * Self::year_length(&env) as i128 / (loan.principal * term as i128);
+        apr.clamp(0, u32::MAX as i128) as u32
+    }
+
+    // GET CONFIG: Every admin-configurable parameter in one read
+    pub fn get_config(env: Env) -> LendingConfig {
+        LendingConfig {
+            interest_rate_bps: APY_BPS as u32,
+            compounding_frequency: Self::get_compounding_frequency(env.clone()),
+            grace_period: Self::get_grace_period(env.clone()),
+            interest_grace_period: Self::get_interest_grace_period(env.clone()),
+            pool_cap: Self::get_pool_cap(env.clone()),
+            liquidation_threshold_bps: Self::get_liquidation_threshold(env.clone()),
+            max_ltv_bps: Self::get_max_ltv(env.clone()),
+            min_health_factor_bps: Self::get_min_health_factor(env.clone()),
+            min_partial_repayment: Self::get_min_partial_repayment(env.clone()),
+            deterministic_loan_ids: Self::deterministic_loan_ids(env.clone()),
+            protocol_share_bps: Self::get_protocol_share(env.clone()),
+            snapshot_window: Self::get_snapshot_window(env.clone()),
+            release_ratio_bps: Self::get_release_ratio(env.clone()),
+            withdraw_cooldown: Self::get_withdraw_cooldown(env.clone()),
+            withdraw_queue: Self::is_withdraw_queue_enabled(env.clone()),
+            liquidator_whitelist: Self::is_liquidator_whitelist_enabled(env.clone()),
+            borrow_fee_bps: Self::get_borrow_fee(env.clone()),
+            fee_token: Self::get_fee_token(env.clone()),
+            min_hold_period: Self::get_min_hold_period(env.clone()),
+            early_repayment_penalty_bps: Self::get_early_repayment_penalty(env.clone()),
+            max_total_loans: Self::get_max_total_loans(env.clone()),
+            interest_recovery_bps: Self::get_interest_recovery(env.clone()),
+            auto_approve_limit: Self::get_auto_approve_limit(env.clone()),
+            early_repay_discount_bps: Self::get_early_repay_discount(env.clone()),
+            min_liquidation_amount: Self::get_min_liquidation_amount(env.clone()),
+            penalty_rate_bps: Self::get_penalty_rate(env.clone()),
+            day_count_convention: Self::get_day_count_convention(env.clone()),
+            pause_flags: Self::get_pause_flags(env),
+        }
+    }
+
+    // GET LOAN: Retrieve loan details
+    pub fn get_loan(env: Env, loan_id: u64) -> Option<Loan> {
+        Self::load_loan(&env, loan_id)
+    }
+
+    // GET REPAYMENT SCHEDULE: Upcoming payments, one maturity entry for a bullet loan or periodic interest then
+    // maturity when the interest grace period makes the loan interest-only
+    pub fn get_repayment_schedule(env: Env, loan_id: u64) -> Vec<ScheduleEntry> {
+        let loan: Loan = Self::load_loan(&env, loan_id)
+            .expect("Loan not found");
+        let mut schedule = Vec::new(&env);
+        if loan.is_repaid || loan.is_defaulted {
+            return schedule;
+        }
+
+        // Interest owed up to the first entry includes anything already carried on the loan
+        let current_time = env.ledger().timestamp();
+        let mut previous: Option<u64> = None;
+        let mut interest_to = |at: u64| match previous.replace(at) {
+            None => Self::interest_due(&env, &loan, at),
+            Some(from) => Self::loan_interest(&env, loan.id, loan.start_time, loan.remaining_principal, from, at),
+        };
+
+        let window = Self::get_interest_grace_period(env.clone());
+        if window > 0 {
+            let mut payment_date = loan.last_interest_payment.saturating_add(window);
+            while payment_date < loan.due_date {
+                if payment_date > current_time {
+                    if schedule.len() + 1 >= MAX_SCHEDULE_ENTRIES {
+                        panic!("Schedule too long");
+                    }
+                    schedule.push_back(ScheduleEntry { due_date: payment_date, principal: 0, interest: interest_to(payment_date) });
+                }
+                payment_date = payment_date.saturating_add(window);
+            }
+        }
+
+        schedule.push_back(ScheduleEntry {
+            due_date: loan.due_date,
+            principal: loan.remaining_principal,
+            interest: interest_to(loan.due_date),
+        });
+        schedule
+    }
+
+    // GET LOAN VIEW: Loan details with the interest owed now and the sub-unit interest carried between payments
+    pub fn get_loan_view(env: Env, loan_id: u64) -> LoanView {
+        let loan: Loan = Self::load_loan(&env, loan_id)
+            .expect("Loan not found");
+        let interest_due = if loan.is_repaid || loan.is_defaulted {
+            0
+        } else {
+            Self::interest_due(&env, &loan, env.ledger().timestamp())
+        };
+        let carried_fraction = env.storage().instance().get(&DataKey::AccruedFraction(loan_id)).unwrap_or(0);
+        LoanView { loan, interest_due, carried_fraction }
     }
 
     // 4. VIEW: Check contract balance
@@ -0,0 +1,59 @@
+// LP share math for deposits tracked as pool shares: proportional conversions between amounts
+// and shares that fail loudly instead of overflowing, and bootstrap an empty pool at 1:1
+
+// Shares minted for depositing amount, rounded down in the pool's favour
+// The first deposit into a pool without shares mints 1:1
+pub fn shares_for_deposit(amount: i128, total_shares: i128, pool_value: i128) -> i128 {
+    if amount < 0 || total_shares < 0 || pool_value < 0 {
+        panic!("Invalid share math input");
+    }
+    if total_shares == 0 {
+        return amount;
+    }
+    // Outstanding shares over a worthless pool would let a new deposit claim value owed to no one
+    if pool_value == 0 {
+        panic!("Pool has no value");
+    }
+    mul_div(amount, total_shares, pool_value)
+}
+
+// Amount a holder receives for redeeming shares, rounded down in the pool's favour
+pub fn amount_for_shares(shares: i128, total_shares: i128, pool_value: i128) -> i128 {
+    if shares < 0 || total_shares < 0 || pool_value < 0 || shares > total_shares {
+        panic!("Invalid share math input");
+    }
+    if shares == 0 {
+        return 0;
+    }
+    mul_div(shares, pool_value, total_shares)
+}
+
+// Shares burned to withdraw amount, rounded up in the pool's favour
+pub fn shares_for_withdrawal(amount: i128, total_shares: i128, pool_value: i128) -> i128 {
+    if amount < 0 || total_shares < 0 || pool_value < 0 || amount > pool_value {
+        panic!("Invalid share math input");
+    }
+    if amount == 0 {
+        return 0;
+    }
+    let shares = mul_div(amount, total_shares, pool_value);
+    if mul_div(shares, pool_value, total_shares) < amount {
+        shares + 1
+    } else {
+        shares
+    }
+}
+
+// Helper function for a * b / d on non-negative values, rounded down
+// When a * b alone overflows, splits a into whole multiples of d and a remainder so results that fit still come out
+fn mul_div(a: i128, b: i128, d: i128) -> i128 {
+    if let Some(product) = a.checked_mul(b) {
+        return product.checked_div(d).expect("Division by zero");
+    }
+    let whole = (a / d).checked_mul(b);
+    let part = (a % d).checked_mul(b).map(|product| product / d);
+    match (whole, part) {
+        (Some(whole), Some(part)) => whole.checked_add(part).expect("Share math overflow"),
+        _ => panic!("Share math overflow"),
+    }
+}
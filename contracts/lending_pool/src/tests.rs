@@ -1,5 +1,4 @@
 use soroban_sdk::contracterror;
-use crate::LendingPool;
 
 #[contracterror]
 #[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
@@ -17,14 +16,94 @@ pub enum Error {
 }
 
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests {
-    use super::*;
-    use soroban_sdk::{testutils::Address as TestAddress, testutils::Bytes as TestBytes};
-    use soroban_sdk::contractclient::LendingPoolClient;
+    extern crate std;
+
+    use crate::{testutils, DataKey, DayCountConvention, LendingConfig, LoanSummary, LegacyLoan, LendingPool, LendingPoolClient, PauseFlags, Permissions, PoolSnapshot, RateStep, RepaymentReceipt, ScheduleEntry, WiringReport};
+    use ed25519_dalek::{Signer, SigningKey};
+    use invoice_nft::{Invoice, InvoiceContract, InvoiceContractClient, InvoiceStatus};
+    use soroban_sdk::{contract, contractimpl, contracttype, testutils::{storage::Instance as _, Address as _, Events, Ledger, MockAuth, MockAuthInvoke}, token, vec, Address, Bytes, BytesN, Env, IntoVal, Symbol, TryFromVal};
+
+    // Smallest Wasm the host accepts as contract code: a header plus the env interface version (protocol 21) it targets
+    const EMPTY_CONTRACT_WASM: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // \0asm, version 1
+        0x00, 0x1e, 0x11, // custom section of 30 bytes, named with 17 bytes
+        b'c', b'o', b'n', b't', b'r', b'a', b'c', b't', b'e', b'n', b'v', b'm', b'e', b't', b'a', b'v', b'0',
+        0x00, 0x00, 0x00, 0x00, // SCEnvMetaKind::InterfaceVersion
+        0x00, 0x00, 0x00, 0x15, 0x00, 0x00, 0x00, 0x00, // protocol 21, no pre-release
+    ];
+
+    // Hash of the off-chain agreement test loans are written under
+    fn terms_hash(env: &Env) -> BytesN<32> {
+        BytesN::from_array(env, &[7; 32])
+    }
+
+    // Pays a created loan out from freshly minted pool funds to a throwaway recipient, leaving test balances as they were
+    fn disburse_loan(env: &Env, client: &LendingPoolClient, token_address: &Address, loan_id: u64) {
+        let principal = client.get_loan(&loan_id).unwrap().principal;
+        testutils::mint(env, token_address, &client.address, principal);
+        client.disburse(&loan_id, &Some(Address::generate(env)));
+    }
+
+    // Stores an invoice directly in the invoice contract, bypassing signed minting
+    fn seed_invoice(env: &Env, invoice_contract: &Address, id: u64, owner: &Address, amount: i128, due_date: u64) {
+        let invoice = Invoice {
+            id,
+            owner: owner.clone(),
+            amount,
+            due_date,
+            status: InvoiceStatus::Active,
+            paid_amount: 0,
+            issuer: owner.clone(),
+        };
+        env.as_contract(invoice_contract, || {
+            env.storage().instance().set(&invoice_nft::DataKey::Invoice(id), &invoice);
+        });
+    }
+
+    // Oracle quoting whatever price a test has set for each invoice
+    #[contract]
+    struct MockOracle;
+
+    #[contractimpl]
+    impl MockOracle {
+        pub fn set_price(env: Env, invoice_id: u64, price: i128) {
+            env.storage().instance().set(&invoice_id, &price);
+        }
+
+        pub fn price_of(env: Env, invoice_id: u64) -> i128 {
+            env.storage().instance().get(&invoice_id).unwrap()
+        }
+    }
+
+    // Storage layout the Stellar Asset Contract uses for balances
+    #[contracttype]
+    enum AssetDataKey {
+        Balance(Address),
+    }
+
+    #[contracttype]
+    struct BalanceValue {
+        amount: i128,
+        authorized: bool,
+        clawback: bool,
+    }
+
+    // Deploys the native XLM asset contract and credits an address directly, since native XLM can't be minted
+    fn setup_native_token(env: &Env, holder: &Address, stroops: i128) -> Address {
+        let native_token = env.deployer().with_stellar_asset(Bytes::from_array(env, &[0u8; 4])).deploy();
+        env.as_contract(&native_token, || {
+            let balance = BalanceValue { amount: stroops, authorized: true, clawback: false };
+            env.storage().persistent().set(&AssetDataKey::Balance(holder.clone()), &balance);
+        });
+        native_token
+    }
 
     #[test]
     fn test_initialization() {
         let env = Env::default();
+        env.mock_all_auths();
         let contract_id = env.register_contract(None, LendingPool);
         let client = LendingPoolClient::new(&env, &contract_id);
 
@@ -40,6 +119,7 @@ mod tests {
     #[should_panic(expected = "Already initialized")]
     fn test_double_initialization() {
         let env = Env::default();
+        env.mock_all_auths();
         let contract_id = env.register_contract(None, LendingPool);
         let client = LendingPoolClient::new(&env, &contract_id);
 
@@ -53,6 +133,7 @@ mod tests {
     #[test]
     fn test_pause_functionality() {
         let env = Env::default();
+        env.mock_all_auths();
         let contract_id = env.register_contract(None, LendingPool);
         let client = LendingPoolClient::new(&env, &contract_id);
 
@@ -73,6 +154,7 @@ mod tests {
     #[should_panic(expected = "CONTRACT_PAUSED")]
     fn test_deposit_when_paused() {
         let env = Env::default();
+        env.mock_all_auths();
         let contract_id = env.register_contract(None, LendingPool);
         let client = LendingPoolClient::new(&env, &contract_id);
 
@@ -88,8 +170,9 @@ mod tests {
 
     #[test]
     #[should_panic(expected = "CONTRACT_PAUSED")]
-    fn test_borrow_when_paused() {
+    fn test_disburse_when_paused() {
         let env = Env::default();
+        env.mock_all_auths();
         let contract_id = env.register_contract(None, LendingPool);
         let client = LendingPoolClient::new(&env, &contract_id);
 
@@ -97,15 +180,18 @@ mod tests {
         let token_address = Address::generate(&env);
         client.init(&admin, &token_address);
 
-        client.set_paused(&true);
-
         let borrower = Address::generate(&env);
-        client.borrow(&borrower, &1000);
+        let due_date = env.ledger().timestamp() + 86400;
+        let loan_id = client.create_loan(&borrower, &1, &1000, &1000, &due_date, &None, &terms_hash(&env));
+
+        client.set_paused(&true);
+        client.disburse(&loan_id, &None);
     }
 
     #[test]
     fn test_create_loan() {
         let env = Env::default();
+        env.mock_all_auths();
         let contract_id = env.register_contract(None, LendingPool);
         let client = LendingPoolClient::new(&env, &contract_id);
 
@@ -115,7 +201,7 @@ mod tests {
 
         let borrower = Address::generate(&env);
         let due_date = env.ledger().timestamp() + 86400;
-        let loan_id = client.create_loan(&borrower, &1, &1000, &due_date);
+        let loan_id = client.create_loan(&borrower, &1, &1000, &1000, &due_date, &None, &terms_hash(&env));
 
         let loan = client.get_loan(&loan_id).unwrap();
         assert_eq!(loan.borrower, borrower);
@@ -128,85 +214,119 @@ mod tests {
     #[test]
     fn test_repay_loan() {
         let env = Env::default();
+        env.mock_all_auths();
         let contract_id = env.register_contract(None, LendingPool);
         let client = LendingPoolClient::new(&env, &contract_id);
 
         let admin = Address::generate(&env);
-        let token_address = Address::generate(&env);
+        let token_address = testutils::register_token(&env, &admin);
         client.init(&admin, &token_address);
+        testutils::mint(&env, &token_address, &contract_id, 1000);
 
         let borrower = Address::generate(&env);
-        let due_date = env.ledger().timestamp() + 86400;
-        let loan_id = client.create_loan(&borrower, &1, &1000, &due_date);
+        testutils::mint(&env, &token_address, &borrower, 100);
+        let year = 31_536_000;
+        let loan_id = client.create_loan(&borrower, &1, &1000, &1000, &year, &None, &terms_hash(&env));
+        client.disburse(&loan_id, &None);
+        assert_eq!(testutils::balance(&env, &token_address, &borrower), 1100);
+        assert_eq!(testutils::balance(&env, &token_address, &contract_id), 0);
 
-        // In a real test, we would set up the token contract and balance
-        // For now, we'll just test the logic
-        client.repay_loan(&loan_id);
+        // Half a year at 5% on 1000 is 25 interest, paid on top of the principal
+        testutils::advance_time(&env, year / 2);
+        client.repay_loan(&loan_id, &None);
 
-        let loan = client.get_loan(&loan_id).unwrap();
-        assert!(loan.is_repaid);
+        assert!(client.get_loan(&loan_id).is_none());
+        assert!(client.get_loan_summary(&loan_id).is_some());
+        assert_eq!(testutils::balance(&env, &token_address, &borrower), 75);
+        assert_eq!(testutils::balance(&env, &token_address, &contract_id), 1025);
+
+        // The archived loan still holds on to its invoice, which cannot finance a second loan
+        assert_eq!(client.get_invoice_loan(&1), Some(loan_id));
+        assert!(client.try_create_loan(&borrower, &1, &1000, &1000, &year, &None, &terms_hash(&env)).is_err());
+
+        // A cancelled loan lets its invoice go
+        let cancelled = client.create_loan(&borrower, &2, &1000, &1000, &year, &None, &terms_hash(&env));
+        client.cancel_loan(&borrower, &cancelled);
+        assert_eq!(client.get_invoice_loan(&2), None);
+        client.create_loan(&borrower, &2, &1000, &1000, &year, &None, &terms_hash(&env));
     }
 
     #[test]
     #[should_panic(expected = "Loan already repaid")]
     fn test_repay_already_repaid_loan() {
         let env = Env::default();
+        env.mock_all_auths();
         let contract_id = env.register_contract(None, LendingPool);
         let client = LendingPoolClient::new(&env, &contract_id);
 
         let admin = Address::generate(&env);
-        let token_address = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
         client.init(&admin, &token_address);
 
         let borrower = Address::generate(&env);
+        token::StellarAssetClient::new(&env, &token_address).mint(&borrower, &1000);
         let due_date = env.ledger().timestamp() + 86400;
-        let loan_id = client.create_loan(&borrower, &1, &1000, &due_date);
+        let loan_id = client.create_loan(&borrower, &1, &1000, &1000, &due_date, &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, loan_id);
 
-        client.repay_loan(&loan_id);
-        client.repay_loan(&loan_id);
+        client.repay_loan(&loan_id, &None);
+        client.repay_loan(&loan_id, &None);
     }
 
     #[test]
     fn test_liquidate_loan() {
         let env = Env::default();
+        env.mock_all_auths();
         let contract_id = env.register_contract(None, LendingPool);
         let client = LendingPoolClient::new(&env, &contract_id);
 
         let admin = Address::generate(&env);
-        let token_address = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
         client.init(&admin, &token_address);
+        token::StellarAssetClient::new(&env, &token_address).mint(&contract_id, &1000);
 
+        env.ledger().with_mut(|li| li.timestamp = 2 * 86400);
         let borrower = Address::generate(&env);
         let past_date = env.ledger().timestamp() - 86400; // Past due date
-        let loan_id = client.create_loan(&borrower, &1, &1000, &past_date);
+        let loan_id = client.create_loan(&borrower, &1, &1000, &1000, &past_date, &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, loan_id);
 
-        client.liquidate(&loan_id);
+        // The liquidator covers the remaining principal and takes the collateral
+        let liquidator = Address::generate(&env);
+        token::StellarAssetClient::new(&env, &token_address).mint(&liquidator, &1000);
+        client.liquidate(&liquidator, &loan_id);
 
         let loan = client.get_loan(&loan_id).unwrap();
         assert!(loan.is_defaulted);
+        assert_eq!(token::Client::new(&env, &token_address).balance(&liquidator), 0);
+        assert_eq!(client.get_collateral_claim(&loan_id, &liquidator), 1000);
+        assert_eq!(client.get_bad_debt(), 0);
     }
 
     #[test]
     #[should_panic(expected = "Cannot liquidate healthy loan")]
     fn test_liquidate_healthy_loan() {
         let env = Env::default();
+        env.mock_all_auths();
         let contract_id = env.register_contract(None, LendingPool);
         let client = LendingPoolClient::new(&env, &contract_id);
 
         let admin = Address::generate(&env);
-        let token_address = Address::generate(&env);
+        let token_address = testutils::register_token(&env, &admin);
         client.init(&admin, &token_address);
 
         let borrower = Address::generate(&env);
         let future_date = env.ledger().timestamp() + 86400; // Future due date
-        let loan_id = client.create_loan(&borrower, &1, &1000, &future_date);
+        let loan_id = client.create_loan(&borrower, &1, &1000, &1000, &future_date, &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, loan_id);
 
-        client.liquidate(&loan_id);
+        client.liquidate(&Address::generate(&env), &loan_id);
     }
 
     #[test]
     fn test_interest_calculation() {
         let env = Env::default();
+        env.mock_all_auths();
         let contract_id = env.register_contract(None, LendingPool);
         let client = LendingPoolClient::new(&env, &contract_id);
 
@@ -216,10 +336,3758 @@ mod tests {
 
         let borrower = Address::generate(&env);
         let one_year_later = env.ledger().timestamp() + 31_536_000; // 1 year
-        let loan_id = client.create_loan(&borrower, &1, &1000, &one_year_later);
+        let loan_id = client.create_loan(&borrower, &1, &1000, &1000, &one_year_later, &None, &terms_hash(&env));
 
         let loan = client.get_loan(&loan_id).unwrap();
         // 5% of 1000 = 50 interest for 1 year
         assert_eq!(loan.interest, 50);
     }
+
+    #[test]
+    fn test_pool_cap() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+
+        let token_client = token::Client::new(&env, &token_address);
+        let lp = Address::generate(&env);
+        token::StellarAssetClient::new(&env, &token_address).mint(&lp, &2000);
+
+        // Unlimited by default
+        assert_eq!(client.get_pool_cap(), 0);
+        assert_eq!(client.get_remaining_capacity(), i128::MAX);
+
+        client.set_pool_cap(&1000);
+        assert_eq!(client.get_pool_cap(), 1000);
+
+        // Fill the pool exactly to the cap
+        client.deposit(&lp, &600);
+        assert_eq!(client.get_remaining_capacity(), 400);
+        client.deposit(&lp, &400);
+        assert_eq!(client.get_remaining_capacity(), 0);
+        assert_eq!(client.get_pool_balance(), 1000);
+
+        // The overflowing deposit is rejected and no funds move
+        assert!(client.try_deposit(&lp, &1).is_err());
+        assert_eq!(client.get_pool_balance(), 1000);
+        assert_eq!(token_client.balance(&lp), 1000);
+
+        // Nor can a deposit be empty or negative
+        client.set_pool_cap(&0);
+        assert!(client.try_deposit(&lp, &0).is_err());
+        assert!(client.try_deposit(&lp, &-1).is_err());
+        assert_eq!(token_client.balance(&lp), 1000);
+    }
+
+    #[test]
+    fn test_pool_cap_counts_outstanding_loans() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+        client.set_pool_cap(&1000);
+
+        let lp = Address::generate(&env);
+        testutils::mint(&env, &token_address, &lp, 700);
+        client.deposit(&lp, &700);
+        assert_eq!(client.get_remaining_capacity(), 300);
+
+        // Lent-out principal still counts toward the pool's size, but only once
+        let borrower = Address::generate(&env);
+        let due_date = env.ledger().timestamp() + 86400;
+        let loan_id = client.create_loan(&borrower, &1, &700, &700, &due_date, &None, &terms_hash(&env));
+        assert_eq!(client.get_remaining_capacity(), 300);
+        client.disburse(&loan_id, &None);
+        assert_eq!(client.get_remaining_capacity(), 300);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid pool cap")]
+    fn test_negative_pool_cap() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = Address::generate(&env);
+        client.init(&admin, &token_address);
+
+        client.set_pool_cap(&-1);
+    }
+
+    #[test]
+    fn test_liquidation_threshold_before_due_date() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+        token::StellarAssetClient::new(&env, &token_address).mint(&contract_id, &1000);
+
+        // Require 104% coverage; a 1050 invoice backing 1000 starts at 105%
+        client.set_liquidation_threshold(&10_400);
+        assert_eq!(client.get_liquidation_threshold(), 10_400);
+
+        let borrower = Address::generate(&env);
+        let due_date = env.ledger().timestamp() + 2 * 31_536_000; // 2 years
+        let loan_id = client.create_loan(&borrower, &1, &1050, &1000, &due_date, &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, loan_id);
+        assert_eq!(client.get_health_factor(&loan_id), 10_500);
+        assert!(!client.is_liquidatable(&loan_id));
+
+        // After one year 50 interest has accrued: 1050 / 1050 = 100%
+        env.ledger().with_mut(|li| li.timestamp = 31_536_000);
+        assert_eq!(client.get_health_factor(&loan_id), 10_000);
+        assert!(client.is_liquidatable(&loan_id));
+
+        let liquidator = Address::generate(&env);
+        token::StellarAssetClient::new(&env, &token_address).mint(&liquidator, &1000);
+        client.liquidate(&liquidator, &loan_id);
+        assert!(client.get_loan(&loan_id).unwrap().is_defaulted);
+        assert!(!client.is_liquidatable(&loan_id));
+    }
+
+    #[test]
+    fn test_liquidation_threshold_disabled_by_default() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = Address::generate(&env);
+        client.init(&admin, &token_address);
+
+        let borrower = Address::generate(&env);
+        let due_date = env.ledger().timestamp() + 2 * 31_536_000;
+        let loan_id = client.create_loan(&borrower, &1, &1000, &1000, &due_date, &None, &terms_hash(&env));
+
+        // Health factor is below 100% but only the due date can trigger liquidation
+        env.ledger().with_mut(|li| li.timestamp = 31_536_000);
+        assert!(client.get_health_factor(&loan_id) < 10_000);
+        assert!(!client.is_liquidatable(&loan_id));
+
+        env.ledger().with_mut(|li| li.timestamp = due_date + 1);
+        assert!(client.is_liquidatable(&loan_id));
+    }
+
+    #[test]
+    fn test_pause_all() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+        let invoice_id = env.register_contract(None, InvoiceContract);
+        let invoice_client = InvoiceContractClient::new(&env, &invoice_id);
+
+        let admin = Address::generate(&env);
+        let token_address = Address::generate(&env);
+        client.init(&admin, &token_address);
+        invoice_client.init(&admin, &token_address);
+
+        client.pause_all(&invoice_id);
+
+        assert!(client.is_paused());
+        assert!(invoice_client.is_paused());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_pause_all_requires_shared_admin() {
+        let env = Env::default();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+        let invoice_id = env.register_contract(None, InvoiceContract);
+        let invoice_client = InvoiceContractClient::new(&env, &invoice_id);
+
+        let admin = Address::generate(&env);
+        let other_admin = Address::generate(&env);
+        client.init(&admin, &Address::generate(&env));
+        invoice_client.init(&other_admin, &Address::generate(&env));
+
+        // Only the pool admin signs, so the invoice contract rejects the pause
+        client
+            .mock_auths(&[MockAuth {
+                address: &admin,
+                invoke: &MockAuthInvoke {
+                    contract: &contract_id,
+                    fn_name: "pause_all",
+                    args: (&invoice_id,).into_val(&env),
+                    sub_invokes: &[],
+                },
+            }])
+            .pause_all(&invoice_id);
+    }
+
+    #[test]
+    fn test_bump_ttl() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+        let admin = Address::generate(&env);
+        client.init(&admin, &Address::generate(&env));
+
+        let ttl_before = env.as_contract(&contract_id, || env.storage().instance().get_ttl());
+        assert!(ttl_before < client.get_ttl_info());
+
+        client.bump_ttl();
+
+        let ttl_after = env.as_contract(&contract_id, || env.storage().instance().get_ttl());
+        assert_eq!(ttl_after, client.get_ttl_info());
+
+        let (_, topics, data) = env.events().all().last().unwrap();
+        assert_eq!(topics, (Symbol::new(&env, "ttl_bumped"),).into_val(&env));
+        assert_eq!(u32::try_from_val(&env, &data).unwrap(), client.get_ttl_info());
+    }
+
+    #[test]
+    fn test_disburse_exact_principal() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+        token::StellarAssetClient::new(&env, &token_address).mint(&contract_id, &5000);
+
+        let borrower = Address::generate(&env);
+        let due_date = env.ledger().timestamp() + 86400;
+        let loan_id = client.create_loan(&borrower, &1, &1200, &1000, &due_date, &None, &terms_hash(&env));
+        assert!(!client.get_loan(&loan_id).unwrap().is_disbursed);
+
+        client.disburse(&loan_id, &None);
+
+        let token_client = token::Client::new(&env, &token_address);
+        assert_eq!(token_client.balance(&borrower), 1000);
+        assert_eq!(client.get_pool_balance(), 4000);
+        assert!(client.get_loan(&loan_id).unwrap().is_disbursed);
+    }
+
+    #[test]
+    #[should_panic(expected = "Loan already disbursed")]
+    fn test_disburse_twice() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+        token::StellarAssetClient::new(&env, &token_address).mint(&contract_id, &5000);
+
+        let borrower = Address::generate(&env);
+        let due_date = env.ledger().timestamp() + 86400;
+        let loan_id = client.create_loan(&borrower, &1, &1200, &1000, &due_date, &None, &terms_hash(&env));
+
+        client.disburse(&loan_id, &None);
+        client.disburse(&loan_id, &None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Insufficient pool liquidity")]
+    fn test_disburse_insufficient_liquidity() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+        token::StellarAssetClient::new(&env, &token_address).mint(&contract_id, &500);
+
+        let borrower = Address::generate(&env);
+        let due_date = env.ledger().timestamp() + 86400;
+        let loan_id = client.create_loan(&borrower, &1, &1200, &1000, &due_date, &None, &terms_hash(&env));
+
+        client.disburse(&loan_id, &None);
+    }
+
+    #[test]
+    fn test_compounding_frequency() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        client.init(&admin, &Address::generate(&env));
+        assert_eq!(client.get_compounding_frequency(), 0);
+
+        let borrower = Address::generate(&env);
+        let one_year_later = env.ledger().timestamp() + 31_536_000;
+
+        // Simple: 5% of 1,000,000
+        let simple = client.create_loan(&borrower, &1, &1_000_000, &1_000_000, &one_year_later, &None, &terms_hash(&env));
+        assert_eq!(client.get_loan(&simple).unwrap().interest, 50_000);
+
+        // Monthly: 1,000,000 * (1 + 0.05 / 12)^12 - 1,000,000
+        client.set_compounding_frequency(&12);
+        assert_eq!(client.get_compounding_frequency(), 12);
+        let monthly = client.create_loan(&borrower, &2, &1_000_000, &1_000_000, &one_year_later, &None, &terms_hash(&env));
+        assert_eq!(client.get_loan(&monthly).unwrap().interest, 51_161);
+
+        // Daily: 1,000,000 * (1 + 0.05 / 365)^365 - 1,000,000
+        client.set_compounding_frequency(&365);
+        let daily = client.create_loan(&borrower, &3, &1_000_000, &1_000_000, &one_year_later, &None, &terms_hash(&env));
+        assert_eq!(client.get_loan(&daily).unwrap().interest, 51_267);
+    }
+
+    #[test]
+    #[should_panic(expected = "Compounding frequency too high")]
+    fn test_compounding_frequency_too_high() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        client.init(&admin, &Address::generate(&env));
+        client.set_compounding_frequency(&366);
+    }
+
+    #[test]
+    fn test_borrower_exposure() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+
+        let borrower = Address::generate(&env);
+        let other_borrower = Address::generate(&env);
+        token::StellarAssetClient::new(&env, &token_address).mint(&borrower, &1000);
+
+        let year = 31_536_000;
+        let repaid = client.create_loan(&borrower, &1, &1000, &1000, &(year / 2), &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, repaid);
+        client.repay_loan(&repaid, &None);
+        client.create_loan(&borrower, &2, &2000, &2000, &(year / 2), &None, &terms_hash(&env)); // overdue after a year
+        client.create_loan(&borrower, &3, &4000, &4000, &(2 * year), &None, &terms_hash(&env));
+        client.create_loan(&other_borrower, &4, &8000, &8000, &(2 * year), &None, &terms_hash(&env));
+
+        env.ledger().with_mut(|li| li.timestamp = year);
+        let exposure = client.get_borrower_exposure(&borrower);
+
+        assert_eq!(exposure.active_loans, 2);
+        assert_eq!(exposure.outstanding_principal, 6000);
+        assert_eq!(exposure.accrued_interest, 300); // 5% of 6000 over one year
+        assert_eq!(exposure.overdue_loans, 1);
+    }
+
+    #[test]
+    fn test_partial_repayment_minimum() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+        client.set_min_partial_repayment(&500);
+        assert_eq!(client.get_min_partial_repayment(), 500);
+
+        let borrower = Address::generate(&env);
+        token::StellarAssetClient::new(&env, &token_address).mint(&borrower, &2000);
+        let due_date = env.ledger().timestamp() + 31_536_000;
+        let loan_id = client.create_loan(&borrower, &1, &1000, &1000, &due_date, &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, loan_id);
+
+        // Dust payment is rejected
+        assert!(client.try_repay_partial(&loan_id, &100).is_err());
+
+        client.repay_partial(&loan_id, &600);
+        let loan = client.get_loan(&loan_id).unwrap();
+        assert_eq!(loan.remaining_principal, 400);
+        assert!(!loan.is_repaid);
+
+        // 400 is below the minimum but settles the loan, and only the amount owed is charged
+        client.repay_partial(&loan_id, &450);
+        assert!(client.get_loan(&loan_id).is_none());
+        assert!(client.get_loan_summary(&loan_id).is_some());
+        assert_eq!(token::Client::new(&env, &token_address).balance(&borrower), 1000);
+    }
+
+    #[test]
+    fn test_partial_repayment_pays_interest_first() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+
+        let borrower = Address::generate(&env);
+        token::StellarAssetClient::new(&env, &token_address).mint(&borrower, &2000);
+        let year = 31_536_000;
+        let loan_id = client.create_loan(&borrower, &1, &1000, &1000, &(2 * year), &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, loan_id);
+
+        // Half a year of 5% on 1000 is 25 interest
+        env.ledger().with_mut(|li| li.timestamp = year / 2);
+        assert_eq!(client.get_total_owed(&loan_id), 1025);
+
+        client.repay_partial(&loan_id, &125);
+        let loan = client.get_loan(&loan_id).unwrap();
+        assert_eq!(loan.remaining_principal, 900);
+        assert_eq!(loan.unpaid_interest, 0);
+
+        // A payment smaller than the accrued interest carries the rest forward
+        env.ledger().with_mut(|li| li.timestamp = year);
+        client.repay_partial(&loan_id, &10);
+        let loan = client.get_loan(&loan_id).unwrap();
+        assert_eq!(loan.remaining_principal, 900);
+        assert_eq!(loan.unpaid_interest, 12); // 22 accrued on 900, 10 paid
+        assert_eq!(client.get_total_owed(&loan_id), 912);
+
+        client.repay_loan(&loan_id, &None);
+        assert!(client.get_loan_summary(&loan_id).is_some());
+        assert_eq!(token::Client::new(&env, &token_address).balance(&borrower), 2000 - 125 - 10 - 912);
+    }
+
+    #[test]
+    fn test_liquidation_seizes_invoice() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+        let invoice_contract = env.register_contract(None, InvoiceContract);
+        let invoice_client = InvoiceContractClient::new(&env, &invoice_contract);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+        invoice_client.init(&admin, &token_address);
+        token::StellarAssetClient::new(&env, &token_address).mint(&contract_id, &1000);
+
+        // Wire the contracts to each other
+        client.set_invoice_contract(&invoice_contract);
+        invoice_client.set_lending_pool(&contract_id);
+
+        let borrower = Address::generate(&env);
+        let due_date = env.ledger().timestamp() + 86400;
+        seed_invoice(&env, &invoice_contract, 1, &borrower, 1000, due_date);
+        let loan_id = client.create_loan(&borrower, &1, &1000, &1000, &due_date, &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, loan_id);
+
+        env.ledger().with_mut(|li| li.timestamp = due_date + 1);
+        let liquidator = Address::generate(&env);
+        token::StellarAssetClient::new(&env, &token_address).mint(&liquidator, &1000);
+        client.liquidate(&liquidator, &loan_id);
+
+        assert!(client.get_loan(&loan_id).unwrap().is_defaulted);
+        assert_eq!(invoice_client.get_invoice_status(&1), InvoiceStatus::Seized);
+    }
+
+    #[test]
+    fn test_mark_seized_rejects_other_callers() {
+        let env = Env::default();
+        let invoice_contract = env.register_contract(None, InvoiceContract);
+        let invoice_client = InvoiceContractClient::new(&env, &invoice_contract);
+        let contract_id = env.register_contract(None, LendingPool);
+
+        let admin = Address::generate(&env);
+        env.mock_all_auths();
+        invoice_client.init(&admin, &Address::generate(&env));
+        invoice_client.set_lending_pool(&contract_id);
+        seed_invoice(&env, &invoice_contract, 1, &Address::generate(&env), 1000, 86400);
+
+        // Without the lending pool's authorization the call is rejected
+        env.set_auths(&[]);
+        assert!(invoice_client.try_mark_seized(&1).is_err());
+        assert_eq!(invoice_client.get_invoice_status(&1), InvoiceStatus::Active);
+    }
+
+    #[test]
+    fn test_deterministic_loan_ids() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        client.init(&Address::generate(&env), &Address::generate(&env));
+        assert!(!client.deterministic_loan_ids());
+        client.set_deterministic_loan_ids(&true);
+
+        let borrower = Address::generate(&env);
+        let due_date = env.ledger().timestamp() + 86400;
+        let loan_id = client.create_loan(&borrower, &1, &1000, &1000, &due_date, &None, &terms_hash(&env));
+
+        // The same inputs on a fresh pool derive the same id
+        let other_id = env.register_contract(None, LendingPool);
+        let other = LendingPoolClient::new(&env, &other_id);
+        other.init(&Address::generate(&env), &Address::generate(&env));
+        other.set_deterministic_loan_ids(&true);
+        assert_eq!(other.create_loan(&borrower, &1, &1000, &1000, &due_date, &None, &terms_hash(&env)), loan_id);
+
+        // Any differing input gives a different id
+        let next_id = client.create_loan(&borrower, &2, &1000, &1000, &due_date, &None, &terms_hash(&env));
+        assert_ne!(next_id, loan_id);
+        assert_eq!(client.get_loan(&loan_id).unwrap().invoice_id, 1);
+        assert_eq!(client.get_loan(&next_id).unwrap().invoice_id, 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "Loan already exists")]
+    fn test_deterministic_loan_id_duplicate() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        client.init(&Address::generate(&env), &Address::generate(&env));
+        client.set_deterministic_loan_ids(&true);
+
+        let borrower = Address::generate(&env);
+        let due_date = env.ledger().timestamp() + 86400;
+        client.create_loan(&borrower, &1, &1000, &1000, &due_date, &None, &terms_hash(&env));
+        client.create_loan(&borrower, &1, &1000, &1000, &due_date, &None, &terms_hash(&env));
+    }
+
+    #[test]
+    fn test_time_to_due_and_liquidation() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        client.init(&Address::generate(&env), &Address::generate(&env));
+        client.set_grace_period(&3600);
+        assert_eq!(client.get_grace_period(), 3600);
+
+        env.ledger().with_mut(|li| li.timestamp = 1_000);
+        let due_date = 1_000 + 86400;
+        let loan_id = client.create_loan(&Address::generate(&env), &1, &1000, &1000, &due_date, &None, &terms_hash(&env));
+
+        // On time
+        assert_eq!(client.time_to_due(&loan_id), 86400);
+        assert_eq!(client.time_to_liquidation(&loan_id), 86400 + 3600);
+        assert!(!client.is_liquidatable(&loan_id));
+
+        // Overdue but within the grace period
+        env.ledger().with_mut(|li| li.timestamp = due_date + 600);
+        assert_eq!(client.time_to_due(&loan_id), -600);
+        assert_eq!(client.time_to_liquidation(&loan_id), 3000);
+        assert!(!client.is_liquidatable(&loan_id));
+
+        // Past the grace period
+        env.ledger().with_mut(|li| li.timestamp = due_date + 7200);
+        assert_eq!(client.time_to_due(&loan_id), -7200);
+        assert_eq!(client.time_to_liquidation(&loan_id), -3600);
+        assert!(client.is_liquidatable(&loan_id));
+    }
+
+    #[test]
+    fn test_event_namespace() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        client.init(&Address::generate(&env), &Address::generate(&env));
+        let borrower = Address::generate(&env);
+        let due_date = env.ledger().timestamp() + 86400;
+
+        // Standard topics until a namespace is configured
+        assert_eq!(client.get_event_namespace(), None);
+        let loan_id = client.create_loan(&borrower, &1, &1000, &1000, &due_date, &None, &terms_hash(&env));
+        let (_, topics, _) = env.events().all().last().unwrap();
+        assert_eq!(topics, (Symbol::new(&env, "loan_created"), loan_id, borrower.clone()).into_val(&env));
+
+        let namespace = Symbol::new(&env, "pool_eu");
+        client.set_event_namespace(&namespace);
+        assert_eq!(client.get_event_namespace(), Some(namespace.clone()));
+
+        let next_id = client.create_loan(&borrower, &2, &1000, &1000, &due_date, &None, &terms_hash(&env));
+        let (_, topics, data) = env.events().all().last().unwrap();
+        assert_eq!(topics, (namespace.clone(), Symbol::new(&env, "loan_created"), next_id, borrower).into_val(&env));
+        assert_eq!(i128::try_from_val(&env, &data).unwrap(), 1000);
+        assert_ne!(next_id, loan_id);
+
+        client.bump_ttl();
+        let (_, topics, _) = env.events().all().last().unwrap();
+        assert_eq!(topics, (namespace, Symbol::new(&env, "ttl_bumped")).into_val(&env));
+    }
+
+    #[test]
+    fn test_liquidate_partial_two_liquidators() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+
+        let first = Address::generate(&env);
+        let second = Address::generate(&env);
+        let token_admin = token::StellarAssetClient::new(&env, &token_address);
+        token_admin.mint(&first, &500);
+        token_admin.mint(&second, &500);
+
+        let due_date = env.ledger().timestamp() + 86400;
+        let loan_id = client.create_loan(&Address::generate(&env), &1, &2000, &1000, &due_date, &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, loan_id);
+        env.ledger().with_mut(|li| li.timestamp = due_date + 1);
+
+        // The first half leaves the loan open
+        assert_eq!(client.liquidate_partial(&first, &loan_id, &500), 1000);
+        let loan = client.get_loan(&loan_id).unwrap();
+        assert_eq!(loan.remaining_principal, 500);
+        assert!(!loan.is_defaulted);
+
+        // The second half closes it out
+        assert_eq!(client.liquidate_partial(&second, &loan_id, &500), 1000);
+        let loan = client.get_loan(&loan_id).unwrap();
+        assert_eq!(loan.remaining_principal, 0);
+        assert_eq!(loan.collateral_claimed, 2000);
+        assert!(loan.is_defaulted);
+
+        assert_eq!(client.get_collateral_claim(&loan_id, &first), 1000);
+        assert_eq!(client.get_collateral_claim(&loan_id, &second), 1000);
+        env.as_contract(&contract_id, || {
+            assert!(env.storage().persistent().has(&DataKey::CollateralClaims(loan_id)));
+        });
+        let token_client = token::Client::new(&env, &token_address);
+        assert_eq!(token_client.balance(&first), 0);
+        assert_eq!(token_client.balance(&second), 0);
+        assert_eq!(token_client.balance(&contract_id), 1000);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot liquidate healthy loan")]
+    fn test_liquidate_partial_healthy_loan() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let token_address = testutils::register_token(&env, &Address::generate(&env));
+        client.init(&Address::generate(&env), &token_address);
+        let due_date = env.ledger().timestamp() + 86400;
+        let loan_id = client.create_loan(&Address::generate(&env), &1, &2000, &1000, &due_date, &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, loan_id);
+
+        client.liquidate_partial(&Address::generate(&env), &loan_id, &500);
+    }
+
+    #[test]
+    fn test_get_config() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        client.init(&Address::generate(&env), &Address::generate(&env));
+        assert_eq!(client.get_config(), LendingConfig {
+            interest_rate_bps: 500,
+            compounding_frequency: 0,
+            grace_period: 0,
+            interest_grace_period: 0,
+            pool_cap: 0,
+            liquidation_threshold_bps: 0,
+            max_ltv_bps: 0,
+            min_health_factor_bps: 0,
+            min_partial_repayment: 0,
+            deterministic_loan_ids: false,
+            protocol_share_bps: 0,
+            snapshot_window: 0,
+            release_ratio_bps: 0,
+            withdraw_cooldown: 0,
+            withdraw_queue: false,
+            liquidator_whitelist: false,
+            borrow_fee_bps: 0,
+            fee_token: None,
+            min_hold_period: 0,
+            early_repayment_penalty_bps: 0,
+            max_total_loans: 0,
+            interest_recovery_bps: 0,
+            auto_approve_limit: 0,
+            early_repay_discount_bps: 0,
+            min_liquidation_amount: 0,
+            penalty_rate_bps: 0,
+            day_count_convention: DayCountConvention::Actual365,
+            pause_flags: PauseFlags { deposits: false, borrows: false, repayments: false, liquidations: false },
+        });
+
+        client.set_compounding_frequency(&12);
+        client.set_grace_period(&3600);
+        client.set_interest_grace_period(&86400);
+        client.set_pool_cap(&1_000_000);
+        client.set_liquidation_threshold(&11_000);
+        client.set_max_ltv(&8000);
+        client.set_min_health_factor(&12_000);
+        client.set_min_partial_repayment(&50);
+        client.set_deterministic_loan_ids(&true);
+        client.set_protocol_share(&3000);
+        client.set_snapshot_window(&600);
+        client.set_release_ratio(&2000);
+        client.set_withdraw_cooldown(&86400);
+        client.set_withdraw_queue(&true);
+        client.set_liquidator_whitelist(&true);
+        let fee_token = Address::generate(&env);
+        client.set_borrow_fee(&100);
+        client.set_fee_token(&Some(fee_token.clone()));
+        client.set_min_hold_period(&604_800);
+        client.set_early_repayment_penalty(&150);
+        client.set_max_total_loans(&500);
+        client.set_interest_recovery(&5000);
+        client.set_auto_approve_limit(&50_000);
+        client.set_early_repay_discount(&2000);
+        client.set_min_liquidation_amount(&100);
+        client.set_penalty_rate(&2000);
+        client.set_day_count_convention(&DayCountConvention::Actual360);
+        client.set_paused(&true);
+
+        assert_eq!(client.get_config(), LendingConfig {
+            interest_rate_bps: 500,
+            compounding_frequency: 12,
+            grace_period: 3600,
+            interest_grace_period: 86400,
+            pool_cap: 1_000_000,
+            liquidation_threshold_bps: 11_000,
+            max_ltv_bps: 8000,
+            min_health_factor_bps: 12_000,
+            min_partial_repayment: 50,
+            deterministic_loan_ids: true,
+            protocol_share_bps: 3000,
+            snapshot_window: 600,
+            release_ratio_bps: 2000,
+            withdraw_cooldown: 86400,
+            withdraw_queue: true,
+            liquidator_whitelist: true,
+            borrow_fee_bps: 100,
+            fee_token: Some(fee_token),
+            min_hold_period: 604_800,
+            early_repayment_penalty_bps: 150,
+            max_total_loans: 500,
+            interest_recovery_bps: 5000,
+            auto_approve_limit: 50_000,
+            early_repay_discount_bps: 2000,
+            min_liquidation_amount: 100,
+            penalty_rate_bps: 2000,
+            day_count_convention: DayCountConvention::Actual360,
+            pause_flags: PauseFlags { deposits: true, borrows: true, repayments: true, liquidations: true },
+        });
+    }
+
+    #[test]
+    fn test_repay_loan_receipt() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+
+        let borrower = Address::generate(&env);
+        token::StellarAssetClient::new(&env, &token_address).mint(&borrower, &2000);
+        let year = 31_536_000;
+        let loan_id = client.create_loan(&borrower, &1, &1000, &1000, &(2 * year), &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, loan_id);
+
+        // Half a year of 5% on 1000 is 25 interest
+        env.ledger().with_mut(|li| li.timestamp = year / 2);
+        let receipt = client.repay_loan(&loan_id, &None);
+
+        assert_eq!(receipt, RepaymentReceipt {
+            principal_paid: 1000,
+            interest_paid: 25,
+            fee_paid: 0,
+            total_paid: 1025,
+        });
+        assert_eq!(receipt.principal_paid + receipt.interest_paid + receipt.fee_paid, receipt.total_paid);
+
+        let token_client = token::Client::new(&env, &token_address);
+        assert_eq!(token_client.balance(&borrower), 2000 - receipt.total_paid);
+        assert_eq!(token_client.balance(&contract_id), receipt.total_paid);
+    }
+
+    #[test]
+    fn test_protocol_share_split() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+        client.set_protocol_share(&3000);
+        assert_eq!(client.get_protocol_share(), 3000);
+
+        let borrower = Address::generate(&env);
+        token::StellarAssetClient::new(&env, &token_address).mint(&borrower, &4000);
+        let year = 31_536_000;
+        let first = client.create_loan(&borrower, &1, &2000, &2000, &(2 * year), &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, first);
+        let second = client.create_loan(&borrower, &2, &1000, &1000, &(2 * year), &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, second);
+
+        // A full year of 5% on 2000 is 100 interest, 30 of it to the treasury
+        env.ledger().with_mut(|li| li.timestamp = year);
+        assert_eq!(client.repay_loan(&first, &None).interest_paid, 100);
+        assert_eq!(client.get_treasury_balance(), 30);
+        assert_eq!(client.get_lp_yield(), 70);
+
+        // Interest covered by a partial repayment is split the same way
+        client.repay_partial(&second, &50);
+        assert_eq!(client.get_treasury_balance(), 30 + 15);
+        assert_eq!(client.get_lp_yield(), 70 + 35);
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid protocol share")]
+    fn test_protocol_share_above_full() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        client.init(&Address::generate(&env), &Address::generate(&env));
+        client.set_protocol_share(&10_001);
+    }
+
+    #[test]
+    fn test_zero_interest_loan() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+        client.set_compounding_frequency(&365);
+
+        let borrower = Address::generate(&env);
+        token::StellarAssetClient::new(&env, &token_address).mint(&borrower, &1500);
+
+        // Due the instant it starts
+        env.ledger().with_mut(|li| li.timestamp = 1_000);
+        let loan_id = client.create_loan(&borrower, &1, &1000, &1000, &1_000, &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, loan_id);
+        assert_eq!(client.get_loan(&loan_id).unwrap().interest, 0);
+        assert_eq!(client.get_total_owed(&loan_id), 1000);
+
+        let receipt = client.repay_loan(&loan_id, &None);
+        assert_eq!(receipt.interest_paid, 0);
+        assert_eq!(receipt.total_paid, 1000);
+
+        let token_client = token::Client::new(&env, &token_address);
+        assert_eq!(token_client.balance(&borrower), 500);
+        assert_eq!(token_client.balance(&contract_id), 1000);
+        assert_eq!(client.get_lp_yield(), 0);
+        assert!(client.get_loan_summary(&loan_id).is_some());
+    }
+
+    #[test]
+    fn test_loan_locks_invoice_until_repaid() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+        let invoice_contract = env.register_contract(None, InvoiceContract);
+        let invoice_client = InvoiceContractClient::new(&env, &invoice_contract);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+        invoice_client.init(&admin, &token_address);
+        client.set_invoice_contract(&invoice_contract);
+        invoice_client.set_lending_pool(&contract_id);
+
+        let borrower = Address::generate(&env);
+        token::StellarAssetClient::new(&env, &token_address).mint(&borrower, &1000);
+        let due_date = env.ledger().timestamp() + 86400;
+        seed_invoice(&env, &invoice_contract, 1, &borrower, 1000, due_date);
+
+        assert!(!invoice_client.is_locked(&1));
+        let loan_id = client.create_loan(&borrower, &1, &1000, &1000, &due_date, &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, loan_id);
+        assert_eq!(invoice_client.get_invoice_status(&1), InvoiceStatus::Locked);
+        assert!(invoice_client.is_locked(&1));
+
+        // The borrower can't move the collateral or borrow against it again
+        assert!(invoice_client.try_transfer(&borrower, &Address::generate(&env), &1).is_err());
+        assert!(client.try_create_loan(&borrower, &1, &1000, &1000, &due_date, &None, &terms_hash(&env)).is_err());
+
+        client.repay_loan(&loan_id, &None);
+        assert_eq!(invoice_client.get_invoice_status(&1), InvoiceStatus::Active);
+        assert!(!invoice_client.is_locked(&1));
+    }
+
+    #[test]
+    fn test_native_xlm_round_trip() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        // 100 XLM, in stroops
+        let xlm = 10_000_000;
+        let lp = Address::generate(&env);
+        let native_token = setup_native_token(&env, &lp, 100 * xlm);
+
+        client.init_native(&Address::generate(&env));
+        assert!(client.is_native());
+        let token_client = token::Client::new(&env, &native_token);
+        assert_eq!(token_client.decimals(), 7);
+
+        client.deposit(&lp, &(60 * xlm));
+        assert_eq!(client.get_pool_balance(), 60 * xlm);
+        assert_eq!(client.get_lp_deposit(&lp), 60 * xlm);
+
+        // Borrow 25.5 XLM and get exactly that many stroops
+        let borrower = Address::generate(&env);
+        let due_date = env.ledger().timestamp() + 86400;
+        let loan_id = client.create_loan(&borrower, &1, &(50 * xlm), &(25 * xlm + xlm / 2), &due_date, &None, &terms_hash(&env));
+        client.disburse(&loan_id, &None);
+        assert_eq!(token_client.balance(&borrower), 255_000_000);
+
+        // Repaid in the same ledger, so the pool is made whole
+        client.repay_loan(&loan_id, &None);
+        assert_eq!(token_client.balance(&borrower), 0);
+        assert_eq!(client.get_pool_balance(), 60 * xlm);
+
+        client.withdraw(&lp, &(60 * xlm));
+        assert_eq!(token_client.balance(&lp), 100 * xlm);
+        assert_eq!(client.get_lp_deposit(&lp), 0);
+    }
+
+    #[test]
+    fn test_withdraw_limits() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+
+        let lp = Address::generate(&env);
+        token::StellarAssetClient::new(&env, &token_address).mint(&lp, &1000);
+        client.deposit(&lp, &1000);
+
+        // No more than was deposited
+        assert!(client.try_withdraw(&lp, &1001).is_err());
+        assert!(client.try_withdraw(&Address::generate(&env), &1).is_err());
+
+        // No more than is sitting idle in the pool
+        let due_date = env.ledger().timestamp() + 86400;
+        let loan_id = client.create_loan(&Address::generate(&env), &1, &1000, &800, &due_date, &None, &terms_hash(&env));
+        client.disburse(&loan_id, &None);
+        assert!(client.try_withdraw(&lp, &300).is_err());
+
+        client.withdraw(&lp, &200);
+        assert_eq!(client.get_lp_deposit(&lp), 800);
+        assert_eq!(token::Client::new(&env, &token_address).balance(&lp), 200);
+    }
+
+    #[test]
+    fn test_snapshot_owed() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+        client.set_snapshot_window(&3600);
+
+        let borrower = Address::generate(&env);
+        let principal = 1_000_000_000;
+        token::StellarAssetClient::new(&env, &token_address).mint(&borrower, &(2 * principal));
+        let year = 31_536_000;
+        let loan_id = client.create_loan(&borrower, &1, &principal, &principal, &(2 * year), &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, loan_id);
+
+        env.ledger().with_mut(|li| li.timestamp = year / 2);
+        let snapshot = client.snapshot_owed(&loan_id);
+        assert_eq!(snapshot, client.get_total_owed(&loan_id));
+        assert_eq!(client.get_owed_snapshot(&loan_id).unwrap().timestamp, year / 2);
+
+        // Within the window the snapshot is charged even though interest kept accruing
+        env.ledger().with_mut(|li| li.timestamp = year / 2 + 1800);
+        assert!(client.get_total_owed(&loan_id) > snapshot);
+        assert_eq!(client.repay_loan(&loan_id, &None).total_paid, snapshot);
+        assert_eq!(client.get_owed_snapshot(&loan_id), None);
+    }
+
+    #[test]
+    fn test_stale_snapshot_ignored() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+        client.set_snapshot_window(&3600);
+
+        let borrower = Address::generate(&env);
+        let principal = 1_000_000_000;
+        token::StellarAssetClient::new(&env, &token_address).mint(&borrower, &(2 * principal));
+        let year = 31_536_000;
+        let loan_id = client.create_loan(&borrower, &1, &principal, &principal, &(2 * year), &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, loan_id);
+
+        env.ledger().with_mut(|li| li.timestamp = year / 2);
+        let snapshot = client.snapshot_owed(&loan_id);
+
+        // Past the window the live figure is charged instead
+        env.ledger().with_mut(|li| li.timestamp = year / 2 + 7200);
+        let live = client.get_total_owed(&loan_id);
+        assert!(live > snapshot);
+        assert_eq!(client.repay_loan(&loan_id, &None).total_paid, live);
+    }
+
+    #[test]
+    fn test_release_collateral() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+        let invoice_contract = env.register_contract(None, InvoiceContract);
+        let invoice_client = InvoiceContractClient::new(&env, &invoice_contract);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+        invoice_client.init(&admin, &token_address);
+        client.set_invoice_contract(&invoice_contract);
+        invoice_client.set_lending_pool(&contract_id);
+        client.set_release_ratio(&2000);
+
+        let borrower = Address::generate(&env);
+        token::StellarAssetClient::new(&env, &token_address).mint(&borrower, &1000);
+        let due_date = env.ledger().timestamp() + 86400;
+        seed_invoice(&env, &invoice_contract, 1, &borrower, 1000, due_date);
+        let loan_id = client.create_loan(&borrower, &1, &1000, &1000, &due_date, &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, loan_id);
+        assert!(client.get_loan(&loan_id).unwrap().collateral_locked);
+
+        // 30% still outstanding is above the 20% release ratio
+        client.repay_partial(&loan_id, &700);
+        assert!(client.try_release_collateral(&loan_id).is_err());
+
+        client.repay_partial(&loan_id, &150);
+        assert!(invoice_client.is_locked(&1));
+        client.release_collateral(&loan_id);
+        assert!(!invoice_client.is_locked(&1));
+        assert!(!client.get_loan(&loan_id).unwrap().collateral_locked);
+        assert_eq!(invoice_client.get_invoice_status(&1), InvoiceStatus::Active);
+
+        // Closing the loan afterwards doesn't touch the released invoice
+        client.repay_loan(&loan_id, &None);
+        assert!(client.get_loan_summary(&loan_id).is_some());
+        assert_eq!(invoice_client.get_invoice_status(&1), InvoiceStatus::Active);
+
+        // A loan paid down below the ratio but then defaulted keeps its invoice with the pool
+        seed_invoice(&env, &invoice_contract, 2, &borrower, 1000, due_date);
+        let defaulted = client.create_loan(&borrower, &2, &1000, &1000, &due_date, &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, defaulted);
+        token::StellarAssetClient::new(&env, &token_address).mint(&borrower, &850);
+        client.repay_partial(&defaulted, &850);
+        env.ledger().with_mut(|li| li.timestamp = due_date + 1);
+        client.sweep_defaults(&vec![&env, defaulted]);
+        assert!(client.try_release_collateral(&defaulted).is_err());
+        assert_eq!(invoice_client.get_invoice_status(&2), InvoiceStatus::Locked);
+
+        // Until a liquidator covers what is left and the pool seizes it
+        let liquidator = Address::generate(&env);
+        token::StellarAssetClient::new(&env, &token_address).mint(&liquidator, &150);
+        client.liquidate(&liquidator, &defaulted);
+        assert_eq!(invoice_client.get_invoice_status(&2), InvoiceStatus::Seized);
+    }
+
+    #[test]
+    #[should_panic(expected = "Loan still outstanding")]
+    fn test_release_collateral_outstanding() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+        let invoice_contract = env.register_contract(None, InvoiceContract);
+        let invoice_client = InvoiceContractClient::new(&env, &invoice_contract);
+
+        let admin = Address::generate(&env);
+        client.init(&admin, &Address::generate(&env));
+        invoice_client.init(&admin, &Address::generate(&env));
+        client.set_invoice_contract(&invoice_contract);
+        invoice_client.set_lending_pool(&contract_id);
+
+        let borrower = Address::generate(&env);
+        let due_date = env.ledger().timestamp() + 86400;
+        seed_invoice(&env, &invoice_contract, 1, &borrower, 1000, due_date);
+        let loan_id = client.create_loan(&borrower, &1, &1000, &1000, &due_date, &None, &terms_hash(&env));
+
+        client.release_collateral(&loan_id);
+    }
+
+    #[test]
+    fn test_disburse_to_designated_recipient() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+        token::StellarAssetClient::new(&env, &token_address).mint(&contract_id, &1000);
+
+        let borrower = Address::generate(&env);
+        let financier = Address::generate(&env);
+        let due_date = env.ledger().timestamp() + 86400;
+        let loan_id = client.create_loan(&borrower, &1, &1000, &1000, &due_date, &None, &terms_hash(&env));
+
+        client.disburse(&loan_id, &Some(financier.clone()));
+
+        let token_client = token::Client::new(&env, &token_address);
+        assert_eq!(token_client.balance(&financier), 1000);
+        assert_eq!(token_client.balance(&borrower), 0);
+
+        // The obligation still sits with the borrower
+        let loan = client.get_loan(&loan_id).unwrap();
+        assert_eq!(loan.borrower, borrower);
+        assert_eq!(client.get_total_owed(&loan_id), 1000);
+        assert_eq!(client.get_borrower_exposure(&borrower).outstanding_principal, 1000);
+        assert_eq!(client.get_borrower_exposure(&financier).active_loans, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Counter overflow")]
+    fn test_loan_id_overflow() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        client.init(&Address::generate(&env), &Address::generate(&env));
+        env.as_contract(&contract_id, || {
+            env.storage().instance().set(&crate::DataKey::LoanId, &(u64::MAX - 1));
+        });
+
+        // The last id is still usable, the one after is not
+        let borrower = Address::generate(&env);
+        let due_date = env.ledger().timestamp() + 86400;
+        assert_eq!(client.create_loan(&borrower, &1, &1000, &1000, &due_date, &None, &terms_hash(&env)), u64::MAX);
+        client.create_loan(&borrower, &2, &1000, &1000, &due_date, &None, &terms_hash(&env));
+    }
+
+    #[test]
+    fn test_missed_interest_makes_loan_liquidatable() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+        let month = 2_592_000;
+        client.set_interest_grace_period(&month);
+        assert_eq!(client.get_interest_grace_period(), month);
+
+        let borrower = Address::generate(&env);
+        token::StellarAssetClient::new(&env, &token_address).mint(&borrower, &1_000_000);
+        let year = 31_536_000;
+        let loan_id = client.create_loan(&borrower, &1, &2_000_000, &1_000_000, &year, &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, loan_id);
+
+        // Servicing interest within the window keeps the loan healthy
+        env.ledger().with_mut(|li| li.timestamp = month - 1);
+        let interest = client.get_total_owed(&loan_id) - 1_000_000;
+        client.repay_partial(&loan_id, &interest);
+        assert_eq!(client.get_loan(&loan_id).unwrap().unpaid_interest, 0);
+
+        env.ledger().with_mut(|li| li.timestamp = 2 * month - 2);
+        assert!(!client.is_liquidatable(&loan_id));
+
+        // A full window without an interest payment, long before the principal is due
+        env.ledger().with_mut(|li| li.timestamp = 2 * month);
+        assert!(client.time_to_due(&loan_id) > 0);
+        assert!(client.is_liquidatable(&loan_id));
+    }
+
+    // Asserts the most recent event is a lifecycle event for loan_id, with the id as the second topic
+    fn assert_loan_event(env: &Env, name: &str, loan_id: u64, party: &Address) {
+        let (_, topics, _) = env.events().all().last().unwrap();
+        assert_eq!(topics, (Symbol::new(env, name), loan_id, party.clone()).into_val(env));
+    }
+
+    #[test]
+    fn test_loan_lifecycle_events() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+        let token_admin = token::StellarAssetClient::new(&env, &token_address);
+        token_admin.mint(&contract_id, &2000);
+
+        let borrower = Address::generate(&env);
+        let due_date = env.ledger().timestamp() + 86400;
+        let repaid = client.create_loan(&borrower, &1, &1000, &1000, &due_date, &None, &terms_hash(&env));
+        assert_loan_event(&env, "loan_created", repaid, &borrower);
+
+        client.disburse(&repaid, &None);
+        assert_loan_event(&env, "loan_disbursed", repaid, &borrower);
+
+        client.repay_partial(&repaid, &400);
+        assert_loan_event(&env, "partial_repay", repaid, &borrower);
+
+        client.repay_loan(&repaid, &None);
+        assert_loan_event(&env, "loan_repaid", repaid, &borrower);
+
+        let liquidated = client.create_loan(&borrower, &2, &1000, &1000, &due_date, &None, &terms_hash(&env));
+        client.disburse(&liquidated, &None);
+        env.ledger().with_mut(|li| li.timestamp = due_date + 1);
+
+        let liquidator = Address::generate(&env);
+        token_admin.mint(&liquidator, &1000);
+        client.liquidate_partial(&liquidator, &liquidated, &400);
+        assert_loan_event(&env, "partial_liquidation", liquidated, &liquidator);
+
+        client.liquidate(&liquidator, &liquidated);
+        assert_loan_event(&env, "loan_liquidated", liquidated, &liquidator);
+    }
+
+    #[test]
+    fn test_verify_wiring() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+        let invoice_contract = env.register_contract(None, InvoiceContract);
+        let invoice_client = InvoiceContractClient::new(&env, &invoice_contract);
+
+        let admin = Address::generate(&env);
+        client.init(&admin, &Address::generate(&env));
+        invoice_client.init(&admin, &Address::generate(&env));
+
+        // Freshly deployed, nothing is linked yet
+        let report = client.verify_wiring(&invoice_contract);
+        assert_eq!(report, WiringReport {
+            pool_knows_invoice: false,
+            invoice_knows_pool: false,
+            backend_key_shared: false,
+            pool_active: true,
+            invoice_active: true,
+            healthy: false,
+        });
+
+        let backend_key = BytesN::from_array(&env, &[7u8; 32]);
+        client.set_invoice_contract(&invoice_contract);
+        invoice_client.set_lending_pool(&contract_id);
+        client.set_backend_pubkey(&backend_key);
+        invoice_client.add_backend(&backend_key);
+        assert!(client.verify_wiring(&invoice_contract).healthy);
+
+        // A paused invoice contract or a different deployment is flagged
+        invoice_client.set_paused(&true);
+        let report = client.verify_wiring(&invoice_contract);
+        assert!(!report.invoice_active);
+        assert!(!report.healthy);
+
+        let other_invoice = env.register_contract(None, InvoiceContract);
+        InvoiceContractClient::new(&env, &other_invoice).init(&admin, &Address::generate(&env));
+        let report = client.verify_wiring(&other_invoice);
+        assert!(!report.pool_knows_invoice);
+        assert!(!report.invoice_knows_pool);
+        assert!(!report.backend_key_shared);
+    }
+
+    #[test]
+    fn test_stepped_rate_schedule() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        client.init(&Address::generate(&env), &Address::generate(&env));
+        let day = 86_400;
+        let year = 31_536_000;
+        let principal = 1_000_000_000;
+        let loan_id = client.create_loan(&Address::generate(&env), &1, &principal, &principal, &(180 * day), &None, &terms_hash(&env));
+
+        // 3% for 90 days, then 6%
+        let schedule = vec![
+            &env,
+            RateStep { start_offset: 0, rate_bps: 300 },
+            RateStep { start_offset: 90 * day, rate_bps: 600 },
+        ];
+        client.set_rate_schedule(&loan_id, &schedule);
+        assert_eq!(client.get_rate_schedule(&loan_id), Some(schedule));
+
+        let first_step = principal * 300 * (90 * day) as i128 / (10_000 * year as i128);
+        let second_step = principal * 600 * (90 * day) as i128 / (10_000 * year as i128);
+        assert_eq!(client.get_loan(&loan_id).unwrap().interest, first_step + second_step);
+
+        // Part way through, only the elapsed part of each step has accrued, truncated once over both steps
+        env.ledger().with_mut(|li| li.timestamp = 120 * day);
+        let accrued = principal * (300 * 90 + 600 * 30) * day as i128 / (10_000 * year as i128);
+        assert_eq!(client.get_total_owed(&loan_id), principal + accrued);
+    }
+
+    #[test]
+    fn test_invalid_rate_schedule() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        client.init(&Address::generate(&env), &Address::generate(&env));
+        let loan_id = client.create_loan(&Address::generate(&env), &1, &1000, &1000, &86_400, &None, &terms_hash(&env));
+
+        // Must start at the loan start and strictly increase
+        let late_start = vec![&env, RateStep { start_offset: 10, rate_bps: 300 }];
+        assert!(client.try_set_rate_schedule(&loan_id, &late_start).is_err());
+        let unordered = vec![
+            &env,
+            RateStep { start_offset: 0, rate_bps: 300 },
+            RateStep { start_offset: 0, rate_bps: 600 },
+        ];
+        assert!(client.try_set_rate_schedule(&loan_id, &unordered).is_err());
+        assert!(client.try_set_rate_schedule(&loan_id, &vec![&env]).is_err());
+        assert_eq!(client.get_rate_schedule(&loan_id), None);
+    }
+
+    #[test]
+    fn test_effective_apr() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let token_address = testutils::register_token(&env, &Address::generate(&env));
+        client.init(&Address::generate(&env), &token_address);
+        let year = 31_536_000;
+        let principal = 1_000_000_000;
+
+        // A plain loan costs exactly the nominal rate
+        let plain = client.create_loan(&Address::generate(&env), &1, &principal, &principal, &year, &None, &terms_hash(&env));
+        assert_eq!(client.get_effective_apr(&plain), 500);
+
+        // Monthly compounding makes the true cost higher than the nominal 5%
+        client.set_compounding_frequency(&12);
+        let compounded = client.create_loan(&Address::generate(&env), &2, &principal, &principal, &year, &None, &terms_hash(&env));
+        assert_eq!(client.get_effective_apr(&compounded), 511);
+
+        // Stepped rates average out over the term
+        client.set_compounding_frequency(&0);
+        let stepped = client.create_loan(&Address::generate(&env), &3, &principal, &principal, &year, &None, &terms_hash(&env));
+        client.set_rate_schedule(&stepped, &vec![
+            &env,
+            RateStep { start_offset: 0, rate_bps: 300 },
+            RateStep { start_offset: year / 2, rate_bps: 900 },
+        ]);
+        assert_eq!(client.get_effective_apr(&stepped), 600);
+
+        // An instantaneous loan has no term to annualize over
+        let instant = client.create_loan(&Address::generate(&env), &4, &principal, &principal, &0, &None, &terms_hash(&env));
+        assert_eq!(client.get_effective_apr(&instant), 0);
+
+        // A 1% origination fee on a one-year loan adds a point on top of the interest
+        client.set_borrow_fee(&100);
+        let with_fee = client.create_loan(&Address::generate(&env), &5, &principal, &principal, &year, &None, &terms_hash(&env));
+        assert_eq!(client.get_effective_apr(&with_fee), 600);
+
+        // Once disbursed, the fee actually charged counts, whatever the fee is set to later
+        disburse_loan(&env, &client, &token_address, with_fee);
+        client.set_borrow_fee(&0);
+        assert_eq!(client.get_effective_apr(&with_fee), 600);
+        assert_eq!(client.get_effective_apr(&plain), 500);
+
+        // Annualized under the same day count interest accrues on, so a 360-day year still costs the nominal rate
+        client.set_day_count_convention(&DayCountConvention::Actual360);
+        let year_360 = 360 * 86_400;
+        let act_360 = client.create_loan(&Address::generate(&env), &6, &principal, &principal, &year_360, &None, &terms_hash(&env));
+        assert_eq!(client.get_effective_apr(&act_360), 500);
+    }
+
+    #[test]
+    fn test_withdraw_cooldown() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+        client.set_withdraw_cooldown(&3600);
+        assert_eq!(client.get_withdraw_cooldown(), 3600);
+
+        let lp = Address::generate(&env);
+        token::StellarAssetClient::new(&env, &token_address).mint(&lp, &1000);
+        env.ledger().with_mut(|li| li.timestamp = 1_000);
+        client.deposit(&lp, &1000);
+
+        // Straight after depositing the funds are locked
+        assert_eq!(client.time_until_withdrawable(&lp), 3600);
+        assert!(client.try_withdraw(&lp, &1000).is_err());
+
+        env.ledger().with_mut(|li| li.timestamp = 1_000 + 3599);
+        assert_eq!(client.time_until_withdrawable(&lp), 1);
+        assert!(client.try_withdraw(&lp, &1000).is_err());
+
+        env.ledger().with_mut(|li| li.timestamp = 1_000 + 3600);
+        assert_eq!(client.time_until_withdrawable(&lp), 0);
+        client.withdraw(&lp, &1000);
+        assert_eq!(token::Client::new(&env, &token_address).balance(&lp), 1000);
+    }
+
+    #[test]
+    fn test_sweep_defaults() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+        client.set_grace_period(&3600);
+
+        env.ledger().with_mut(|li| li.timestamp = 10 * 86400);
+        let now = env.ledger().timestamp();
+        let borrower = Address::generate(&env);
+        let overdue = client.create_loan(&borrower, &1, &1000, &1000, &(now - 86400), &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, overdue);
+        let in_grace = client.create_loan(&borrower, &2, &1000, &1000, &(now - 60), &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, in_grace);
+        let healthy = client.create_loan(&borrower, &3, &1000, &1000, &(now + 86400), &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, healthy);
+        let repaid = client.create_loan(&borrower, &4, &1000, &1000, &(now - 86400), &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, repaid);
+        token::StellarAssetClient::new(&env, &token_address).mint(&borrower, &2000);
+        client.repay_loan(&repaid, &None);
+
+        let swept = client.sweep_defaults(&vec![&env, overdue, in_grace, healthy, repaid, 99]);
+        assert_eq!(swept, vec![&env, overdue]);
+        assert!(client.get_loan(&overdue).unwrap().is_defaulted);
+        assert!(!client.get_loan(&in_grace).unwrap().is_defaulted);
+        assert!(!client.get_loan(&healthy).unwrap().is_defaulted);
+        assert!(client.get_loan(&repaid).is_none());
+
+        // Already-defaulted loans aren't reported twice
+        assert_eq!(client.sweep_defaults(&vec![&env, overdue]).len(), 0);
+
+        // The unrecovered principal is written off, but a liquidator can still recover it
+        assert_eq!(client.get_bad_debt(), 1000);
+        let liquidator = Address::generate(&env);
+        token::StellarAssetClient::new(&env, &token_address).mint(&liquidator, &1000);
+        client.liquidate(&liquidator, &overdue);
+        assert_eq!(client.get_bad_debt(), 0);
+        assert_eq!(client.get_collateral_claim(&overdue, &liquidator), 1000);
+        assert!(client.try_liquidate(&liquidator, &overdue).is_err());
+
+        // Falling behind on periodic interest is a default too, well before the due date
+        client.set_interest_grace_period(&(30 * 86400));
+        let behind = client.create_loan(&borrower, &5, &1000, &1000, &(now + 365 * 86400), &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, behind);
+        env.ledger().with_mut(|li| li.timestamp = now + 31 * 86400);
+        assert_eq!(client.sweep_defaults(&vec![&env, behind]), vec![&env, behind]);
+
+        let mut oversized = vec![&env];
+        for id in 0..51u64 {
+            oversized.push_back(id);
+        }
+        assert!(client.try_sweep_defaults(&oversized).is_err());
+    }
+
+    #[test]
+    fn test_day_count_convention() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        client.init(&Address::generate(&env), &Address::generate(&env));
+        assert_eq!(client.get_day_count_convention(), DayCountConvention::Actual365);
+
+        let borrower = Address::generate(&env);
+        let principal = 1_000_000_000;
+        let one_year_later = env.ledger().timestamp() + 31_536_000;
+        let actual_365 = client.create_loan(&borrower, &1, &principal, &principal, &one_year_later, &None, &terms_hash(&env));
+
+        client.set_day_count_convention(&DayCountConvention::Actual360);
+        assert_eq!(client.get_day_count_convention(), DayCountConvention::Actual360);
+        let actual_360 = client.create_loan(&borrower, &2, &principal, &principal, &one_year_later, &None, &terms_hash(&env));
+
+        client.set_day_count_convention(&DayCountConvention::ActualActual);
+        let actual_actual = client.create_loan(&borrower, &3, &principal, &principal, &one_year_later, &None, &terms_hash(&env));
+
+        // 365 days at 5% is exactly 5% on a 365 basis, and 365/360 of that on a banker's year
+        let interest_365 = client.get_loan(&actual_365).unwrap().interest;
+        let interest_360 = client.get_loan(&actual_360).unwrap().interest;
+        assert_eq!(interest_365, 50_000_000);
+        assert_eq!(interest_360, 50_694_444);
+        assert!(interest_360 > interest_365);
+        assert!(client.get_loan(&actual_actual).unwrap().interest < interest_365);
+    }
+
+    #[test]
+    fn test_active_loans() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+        token::StellarAssetClient::new(&env, &token_address).mint(&contract_id, &1000);
+
+        env.ledger().with_mut(|li| li.timestamp = 10 * 86400);
+        let now = env.ledger().timestamp();
+        let borrower = Address::generate(&env);
+        let repaid = client.create_loan(&borrower, &1, &1000, &1000, &(now + 86400), &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, repaid);
+        let liquidated = client.create_loan(&borrower, &2, &1000, &1000, &(now - 86400), &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, liquidated);
+        let open_a = client.create_loan(&borrower, &3, &1000, &1000, &(now + 86400), &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, open_a);
+        let open_b = client.create_loan(&borrower, &4, &1000, &1000, &(now + 86400), &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, open_b);
+        assert_eq!(client.get_active_loans(&0, &10), vec![&env, repaid, liquidated, open_a, open_b]);
+
+        token::StellarAssetClient::new(&env, &token_address).mint(&borrower, &2000);
+        client.repay_loan(&repaid, &None);
+        let liquidator = Address::generate(&env);
+        token::StellarAssetClient::new(&env, &token_address).mint(&liquidator, &1000);
+        client.liquidate(&liquidator, &liquidated);
+        assert_eq!(client.get_active_loans(&0, &10), vec![&env, open_a, open_b]);
+
+        // Pages split the set without overlap, and running off the end is empty
+        assert_eq!(client.get_active_loans(&0, &1), vec![&env, open_a]);
+        assert_eq!(client.get_active_loans(&1, &5), vec![&env, open_b]);
+        assert_eq!(client.get_active_loans(&2, &5).len(), 0);
+        assert!(client.try_get_active_loans(&0, &51).is_err());
+    }
+
+    #[test]
+    fn test_guarantor_covers_default() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+        let token_admin = token::StellarAssetClient::new(&env, &token_address);
+        let token_client = token::Client::new(&env, &token_address);
+        token_admin.mint(&contract_id, &2000);
+
+        env.ledger().with_mut(|li| li.timestamp = 10 * 86400);
+        let past_due = env.ledger().timestamp() - 86400;
+        let borrower = Address::generate(&env);
+        let guarantor = Address::generate(&env);
+        let guaranteed = client.create_loan(&borrower, &1, &1000, &1000, &past_due, &Some(guarantor.clone()), &terms_hash(&env));
+        assert!(env.auths().iter().any(|(address, _)| *address == guarantor));
+        assert_eq!(client.get_loan(&guaranteed).unwrap().guarantor, Some(guarantor.clone()));
+        disburse_loan(&env, &client, &token_address, guaranteed);
+
+        // The guarantor backs the loan by letting the pool draw on their funds
+        token_admin.mint(&guarantor, &1500);
+        token_client.approve(&guarantor, &contract_id, &1000, &(env.ledger().sequence() + 1000));
+
+        client.sweep_defaults(&vec![&env, guaranteed]);
+        assert_eq!(token_client.balance(&guarantor), 500);
+        assert_eq!(token_client.balance(&contract_id), 3000);
+        assert_eq!(client.get_bad_debt(), 0);
+
+        // Without a guarantor the loss is recorded as bad debt
+        let unguaranteed = client.create_loan(&borrower, &2, &1000, &1000, &past_due, &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, unguaranteed);
+        client.sweep_defaults(&vec![&env, unguaranteed]);
+        assert_eq!(token_client.balance(&contract_id), 3000);
+        assert_eq!(client.get_bad_debt(), 1000);
+    }
+
+    #[test]
+    fn test_pause_borrows_only() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+        token::StellarAssetClient::new(&env, &token_address).mint(&contract_id, &1000);
+
+        let borrower = Address::generate(&env);
+        let due_date = env.ledger().timestamp() + 86400;
+        let loan_id = client.create_loan(&borrower, &1, &1000, &1000, &due_date, &None, &terms_hash(&env));
+        client.disburse(&loan_id, &None);
+
+        let borrows_paused = PauseFlags { deposits: false, borrows: true, repayments: false, liquidations: false };
+        client.set_pause_flags(&borrows_paused);
+        assert_eq!(client.get_pause_flags(), borrows_paused);
+        assert!(client.is_paused());
+
+        // New borrowing is blocked...
+        assert!(client.try_create_loan(&borrower, &2, &1000, &1000, &due_date, &None, &terms_hash(&env)).is_err());
+
+        // ...while the open loan can still be repaid
+        token::StellarAssetClient::new(&env, &token_address).mint(&borrower, &1000);
+        client.repay_loan(&loan_id, &None);
+        assert!(client.get_loan_summary(&loan_id).is_some());
+
+        // set_paused remains a switch for every operation at once
+        client.set_paused(&false);
+        assert!(!client.is_paused());
+        client.create_loan(&borrower, &2, &1000, &1000, &due_date, &None, &terms_hash(&env));
+    }
+
+    #[test]
+    fn test_project_interest() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        client.init(&Address::generate(&env), &Address::generate(&env));
+        env.ledger().with_mut(|li| li.timestamp = 1_000);
+        let start = env.ledger().timestamp();
+        let due_date = start + 90 * 86400;
+
+        let loan_id = client.create_loan(&Address::generate(&env), &1, &10_000, &10_000, &due_date, &None, &terms_hash(&env));
+        assert_eq!(client.project_interest(&10_000, &start, &due_date), client.get_loan(&loan_id).unwrap().interest);
+
+        // Projections follow the pool's compounding settings too
+        client.set_compounding_frequency(&12);
+        let compounded = client.create_loan(&Address::generate(&env), &2, &10_000, &10_000, &due_date, &None, &terms_hash(&env));
+        assert_eq!(client.project_interest(&10_000, &start, &due_date), client.get_loan(&compounded).unwrap().interest);
+
+        // Empty or inverted terms accrue nothing
+        assert_eq!(client.project_interest(&10_000, &due_date, &start), 0);
+    }
+
+    #[test]
+    fn test_reschedule_earlier() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        client.init(&admin, &Address::generate(&env));
+        env.ledger().with_mut(|li| li.timestamp = 1_000);
+
+        let borrower = Address::generate(&env);
+        let start = env.ledger().timestamp();
+        let loan_id = client.create_loan(&borrower, &1, &10_000, &10_000, &(start + 31_536_000), &None, &terms_hash(&env));
+        assert_eq!(client.get_loan(&loan_id).unwrap().interest, 500);
+
+        let new_due_date = start + 31_536_000 / 2;
+        client.reschedule(&loan_id, &new_due_date);
+        let auths = env.auths();
+        assert!(auths.iter().any(|(address, _)| *address == borrower));
+        assert!(auths.iter().any(|(address, _)| *address == admin));
+
+        let loan = client.get_loan(&loan_id).unwrap();
+        assert_eq!(loan.due_date, new_due_date);
+        assert_eq!(loan.interest, 250);
+
+        // Only earlier, and never into the past
+        assert!(client.try_reschedule(&loan_id, &(new_due_date + 1)).is_err());
+        assert!(client.try_reschedule(&loan_id, &(start - 1)).is_err());
+    }
+
+    #[test]
+    fn test_reschedule_requires_both_parties() {
+        let env = Env::default();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let borrower = Address::generate(&env);
+        env.mock_all_auths();
+        client.init(&admin, &Address::generate(&env));
+        let due_date = env.ledger().timestamp() + 86400;
+        let loan_id = client.create_loan(&borrower, &1, &1000, &1000, &due_date, &None, &terms_hash(&env));
+
+        let new_due_date = due_date - 3600;
+        let invoke = MockAuthInvoke {
+            contract: &contract_id,
+            fn_name: "reschedule",
+            args: (loan_id, new_due_date).into_val(&env),
+            sub_invokes: &[],
+        };
+
+        env.mock_auths(&[MockAuth { address: &borrower, invoke: &invoke }]);
+        assert!(client.try_reschedule(&loan_id, &new_due_date).is_err());
+
+        env.mock_auths(&[MockAuth { address: &admin, invoke: &invoke }]);
+        assert!(client.try_reschedule(&loan_id, &new_due_date).is_err());
+
+        env.mock_auths(&[MockAuth { address: &borrower, invoke: &invoke }, MockAuth { address: &admin, invoke: &invoke }]);
+        client.reschedule(&loan_id, &new_due_date);
+        assert_eq!(client.get_loan(&loan_id).unwrap().due_date, new_due_date);
+    }
+
+    #[test]
+    fn test_poke_loan_overdue_event() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        client.init(&Address::generate(&env), &Address::generate(&env));
+        let borrower = Address::generate(&env);
+        let due_date = env.ledger().timestamp() + 86400;
+        let loan_id = client.create_loan(&borrower, &1, &1000, &1000, &due_date, &None, &terms_hash(&env));
+
+        let overdue_events = |env: &Env| {
+            env.events().all().iter()
+                .filter(|(_, topics, _)| *topics == (Symbol::new(env, "loan_overdue"), loan_id, borrower.clone()).into_val(env))
+                .count()
+        };
+
+        // Nothing to report until the due date has passed
+        assert!(!client.poke_loan(&loan_id));
+        assert_eq!(overdue_events(&env), 0);
+
+        env.ledger().with_mut(|li| li.timestamp = due_date + 1);
+        assert!(client.poke_loan(&loan_id));
+        assert_eq!(overdue_events(&env), 1);
+
+        // Later pokes don't add another event
+        assert!(!client.poke_loan(&loan_id));
+        assert_eq!(overdue_events(&env), 1);
+    }
+
+    #[test]
+    fn test_sub_unit_interest_carried() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+
+        let borrower = Address::generate(&env);
+        token::StellarAssetClient::new(&env, &token_address).mint(&borrower, &1000);
+        let day = 86_400;
+        let loan_id = client.create_loan(&borrower, &1, &1000, &1000, &(365 * day), &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, loan_id);
+
+        // A day's interest on 1000 at 5% is about 0.137 units, which truncates to nothing on its own
+        for paid_days in 1..=7u64 {
+            env.ledger().with_mut(|li| li.timestamp = paid_days * day);
+            client.repay_partial(&loan_id, &1);
+            assert_eq!(client.get_lp_yield(), 0);
+        }
+        let view = client.get_loan_view(&loan_id);
+        assert_eq!(view.loan.remaining_principal, 993);
+        assert_eq!(view.interest_due, 0);
+        assert!(view.carried_fraction > 900_000);
+
+        // The carried fractions add up to a whole unit on the eighth day, which the payment collects
+        env.ledger().with_mut(|li| li.timestamp = 8 * day);
+        assert_eq!(client.get_loan_view(&loan_id).interest_due, 1);
+        client.repay_partial(&loan_id, &1);
+        assert_eq!(client.get_lp_yield(), 1);
+        let view = client.get_loan_view(&loan_id);
+        assert_eq!(view.loan.remaining_principal, 993);
+        assert!(view.carried_fraction < 100_000);
+    }
+
+    #[test]
+    fn test_pool_apy() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+        client.set_protocol_share(&2000);
+        let token_admin = token::StellarAssetClient::new(&env, &token_address);
+        assert_eq!(client.get_pool_apy(), 0);
+
+        let year = 31_536_000;
+        let lp = Address::generate(&env);
+        token_admin.mint(&lp, &10_000);
+        client.deposit(&lp, &10_000);
+
+        // The whole pool is lent out for a year at 5%, of which LPs keep 80%
+        let borrower = Address::generate(&env);
+        token_admin.mint(&borrower, &500);
+        let loan_id = client.create_loan(&borrower, &1, &10_000, &10_000, &year, &None, &terms_hash(&env));
+        client.disburse(&loan_id, &None);
+        env.ledger().with_mut(|li| li.timestamp = year);
+        client.repay_loan(&loan_id, &None);
+        assert_eq!(client.get_lp_yield(), 400);
+
+        let apy = client.get_pool_apy();
+        assert!((399..=401).contains(&apy));
+
+        // Idle time after the interest was earned dilutes the realized figure
+        env.ledger().with_mut(|li| li.timestamp = 2 * year);
+        let apy = client.get_pool_apy();
+        assert!((199..=201).contains(&apy));
+    }
+
+    #[test]
+    fn test_loans_due_between() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+
+        let day = 86_400;
+        let borrower = Address::generate(&env);
+        let tomorrow = client.create_loan(&borrower, &1, &1000, &1000, &day, &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, tomorrow);
+        let next_month = client.create_loan(&borrower, &2, &1000, &1000, &(30 * day), &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, next_month);
+        let this_week = client.create_loan(&borrower, &3, &1000, &1000, &(7 * day), &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, this_week);
+        let repaid = client.create_loan(&borrower, &4, &1000, &1000, &(3 * day), &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, repaid);
+        token::StellarAssetClient::new(&env, &token_address).mint(&borrower, &1000);
+        client.repay_loan(&repaid, &None);
+
+        // Window bounds are inclusive, and closed loans are left out
+        assert_eq!(client.get_loans_due_between(&0, &(7 * day), &0, &50), vec![&env, tomorrow, this_week]);
+        assert_eq!(client.get_loans_due_between(&(2 * day), &(30 * day), &0, &50), vec![&env, next_month, this_week]);
+
+        // Paging splits the scan over the active set
+        assert_eq!(client.get_loans_due_between(&0, &(7 * day), &0, &2), vec![&env, tomorrow]);
+        assert_eq!(client.get_loans_due_between(&0, &(7 * day), &2, &2), vec![&env, this_week]);
+        assert!(client.try_get_loans_due_between(&0, &(7 * day), &0, &51).is_err());
+    }
+
+    #[test]
+    fn test_adjust_loan_interest() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+
+        let year = 31_536_000;
+        let borrower = Address::generate(&env);
+        token::StellarAssetClient::new(&env, &token_address).mint(&borrower, &1100);
+        let loan_id = client.create_loan(&borrower, &1, &1000, &1000, &year, &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, loan_id);
+        assert_eq!(client.get_loan(&loan_id).unwrap().interest, 50);
+
+        client.adjust_loan_interest(&loan_id, &30);
+        assert_eq!(env.auths()[0].0, admin);
+        let (_, _, data) = env.events().all().last().unwrap();
+        assert_eq!(<(i128, i128)>::try_from_val(&env, &data).unwrap(), (50, 30));
+        assert_eq!(client.get_loan(&loan_id).unwrap().interest, 30);
+
+        // Held to maturity the borrower pays the corrected figure
+        env.ledger().with_mut(|li| li.timestamp = year);
+        let receipt = client.repay_loan(&loan_id, &None);
+        assert_eq!(receipt.interest_paid, 30);
+        assert_eq!(token::Client::new(&env, &token_address).balance(&borrower), 70);
+
+        // Settled loans can no longer be adjusted
+        assert!(client.try_adjust_loan_interest(&loan_id, &10).is_err());
+    }
+
+    #[test]
+    fn test_adjust_loan_interest_admin_only() {
+        let env = Env::default();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        env.mock_all_auths();
+        let admin = Address::generate(&env);
+        client.init(&admin, &Address::generate(&env));
+        let borrower = Address::generate(&env);
+        let loan_id = client.create_loan(&borrower, &1, &1000, &1000, &31_536_000, &None, &terms_hash(&env));
+
+        // The borrower can't lower their own interest
+        env.mock_auths(&[MockAuth {
+            address: &borrower,
+            invoke: &MockAuthInvoke {
+                contract: &contract_id,
+                fn_name: "adjust_loan_interest",
+                args: (loan_id, 0i128).into_val(&env),
+                sub_invokes: &[],
+            },
+        }]);
+        assert!(client.try_adjust_loan_interest(&loan_id, &0).is_err());
+        assert_eq!(client.get_loan(&loan_id).unwrap().interest, 50);
+    }
+
+    #[test]
+    fn test_extension_fee_scales_with_duration() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = testutils::register_token(&env, &admin);
+        client.init(&admin, &token_address);
+
+        let day = 86_400;
+        let borrower = Address::generate(&env);
+        testutils::mint(&env, &token_address, &borrower, 1_000_000);
+        let loan_id = client.create_loan(&borrower, &1, &1_000_000, &1_000_000, &(90 * day), &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, loan_id);
+
+        // 5% a year on 1,000,000 is about 137 a day, so doubling the extension doubles the fee
+        let month = client.quote_extension(&loan_id, &(30 * day));
+        let two_months = client.quote_extension(&loan_id, &(60 * day));
+        assert_eq!(month, 4109);
+        assert_eq!(two_months, 8219);
+
+        let fee = client.extend_loan(&loan_id, &(30 * day));
+        assert_eq!(fee, month);
+        assert_eq!(testutils::balance(&env, &token_address, &borrower), 1_000_000 - month);
+        assert_eq!(client.get_loan(&loan_id).unwrap().due_date, 120 * day);
+        assert_eq!(client.get_lp_yield(), month);
+
+        assert!(client.try_extend_loan(&loan_id, &0).is_err());
+
+        // The fee prepaid the extra month's interest, so paying off at the new due date costs 120 days of interest in all
+        testutils::mint(&env, &token_address, &borrower, 1_000_000);
+        env.ledger().with_mut(|li| li.timestamp = 120 * day);
+        let receipt = client.repay_loan(&loan_id, &None);
+        assert_eq!(receipt.interest_paid, 16_438 - fee);
+        assert_eq!(fee + receipt.total_paid, 1_000_000 + 16_438);
+    }
+
+    #[test]
+    fn test_repayment_schedule() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        client.init(&Address::generate(&env), &Address::generate(&env));
+        let day = 86_400;
+        let year = 365 * day;
+
+        // A bullet loan is one payment of principal plus interest at maturity
+        let bullet = client.create_loan(&Address::generate(&env), &1, &1000, &1000, &year, &None, &terms_hash(&env));
+        assert_eq!(client.get_repayment_schedule(&bullet), vec![
+            &env,
+            ScheduleEntry { due_date: year, principal: 1000, interest: 50 },
+        ]);
+
+        // With interest due every 100 days the loan is interest-only until maturity
+        client.set_interest_grace_period(&(100 * day));
+        let interest_only = client.create_loan(&Address::generate(&env), &2, &36_500, &36_500, &year, &None, &terms_hash(&env));
+        assert_eq!(client.get_repayment_schedule(&interest_only), vec![
+            &env,
+            ScheduleEntry { due_date: 100 * day, principal: 0, interest: 500 },
+            ScheduleEntry { due_date: 200 * day, principal: 0, interest: 500 },
+            ScheduleEntry { due_date: 300 * day, principal: 0, interest: 500 },
+            ScheduleEntry { due_date: year, principal: 36_500, interest: 325 },
+        ]);
+
+        // Payment dates already behind the ledger are folded into the next one
+        env.ledger().with_mut(|li| li.timestamp = 150 * day);
+        let schedule = client.get_repayment_schedule(&interest_only);
+        assert_eq!(schedule.len(), 3);
+        assert_eq!(schedule.get(0).unwrap(), ScheduleEntry { due_date: 200 * day, principal: 0, interest: 1000 });
+    }
+
+    #[test]
+    fn test_migrate_legacy_loans() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = testutils::register_token(&env, &admin);
+        client.init(&admin, &token_address);
+        let borrower = Address::generate(&env);
+
+        // Seed loans and the pause flag the way deployments before the upgrade stored them
+        let legacy = |id: u64, is_repaid: bool| LegacyLoan {
+            id,
+            borrower: borrower.clone(),
+            invoice_id: id,
+            principal: 1000 * id as i128,
+            interest: 50,
+            start_time: 0,
+            due_date: 86_400,
+            is_repaid,
+            is_defaulted: false,
+        };
+        env.as_contract(&contract_id, || {
+            env.storage().instance().set(&DataKey::Loan(1), &legacy(1, false));
+            env.storage().instance().set(&DataKey::Loan(2), &legacy(2, true));
+            env.storage().instance().set(&DataKey::Loan(3), &legacy(3, false));
+            env.storage().instance().set(&DataKey::LoanId, &3u64);
+            env.storage().instance().set(&DataKey::Paused, &true);
+        });
+        assert!(client.is_paused());
+        assert_eq!(client.get_pause_flags(), PauseFlags { deposits: true, borrows: true, repayments: true, liquidations: true });
+
+        assert_eq!(client.migrate_loans(&1, &2), 2);
+        env.as_contract(&contract_id, || {
+            for loan_id in [1, 2] {
+                assert!(env.storage().persistent().has(&DataKey::Loan(loan_id)));
+                assert!(!env.storage().instance().has(&DataKey::Loan(loan_id)));
+            }
+            assert!(env.storage().instance().has(&DataKey::Loan(3)));
+            let flags: PauseFlags = env.storage().instance().get(&DataKey::Paused).unwrap();
+            assert!(flags.borrows);
+        });
+
+        // Open loans are booked as disbursed and outstanding, repaid ones are not
+        let open = client.get_loan(&1).unwrap();
+        assert!(open.is_disbursed && !open.is_repaid);
+        assert_eq!((open.remaining_principal, open.invoice_amount, open.last_payment_time), (1000, 1000, 0));
+        assert_eq!(client.get_loan(&2).unwrap().remaining_principal, 0);
+
+        // A loan outside the migrated range is converted on first access
+        assert_eq!(client.get_loan(&3).unwrap().remaining_principal, 3000);
+        assert_eq!(client.get_active_loans(&0, &10), vec![&env, 1, 3]);
+        assert_eq!(client.get_loans_by_borrower(&borrower), vec![&env, 1, 2, 3]);
+        assert_eq!(client.export_state().total_outstanding, 4000);
+
+        // Re-running finds nothing left to move, and migrated loans repay normally
+        assert_eq!(client.migrate_loans(&1, &10), 0);
+        client.set_paused(&false);
+        testutils::mint(&env, &token_address, &borrower, 1000);
+        client.repay_loan(&1, &None);
+        assert!(client.get_loan_summary(&1).is_some());
+        assert_eq!(client.export_state().total_outstanding, 3000);
+
+        assert!(client.try_migrate_loans(&10, &1).is_err());
+    }
+
+    #[test]
+    fn test_admin_setters_emit_config_changed() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+        client.init(&Address::generate(&env), &Address::generate(&env));
+
+        // Reserve factor: the protocol's cut of interest
+        client.set_protocol_share(&1000);
+        let (_, topics, data) = env.events().all().last().unwrap();
+        assert_eq!(topics, (Symbol::new(&env, "config_changed"), Symbol::new(&env, "protocol_share_bps")).into_val(&env));
+        assert_eq!(<(u32, u32)>::try_from_val(&env, &data).unwrap(), (0, 1000));
+
+        client.set_protocol_share(&2500);
+        let (_, _, data) = env.events().all().last().unwrap();
+        assert_eq!(<(u32, u32)>::try_from_val(&env, &data).unwrap(), (1000, 2500));
+
+        // The quoted rate is fixed; compounding is the rate knob admins can turn
+        client.set_compounding_frequency(&12);
+        let (_, topics, data) = env.events().all().last().unwrap();
+        assert_eq!(topics, (Symbol::new(&env, "config_changed"), Symbol::new(&env, "compounding_frequency")).into_val(&env));
+        assert_eq!(<(u32, u32)>::try_from_val(&env, &data).unwrap(), (0, 12));
+    }
+
+    #[test]
+    fn test_oracle_price_drives_liquidation() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        client.init(&admin, &Address::generate(&env));
+        client.set_liquidation_threshold(&10_400);
+
+        // At face value a 1200 invoice covers 1000 of principal comfortably
+        let borrower = Address::generate(&env);
+        let due_date = env.ledger().timestamp() + 31_536_000;
+        let loan_id = client.create_loan(&borrower, &7, &1200, &1000, &due_date, &None, &terms_hash(&env));
+        assert_eq!(client.get_oracle(), None);
+        assert_eq!(client.get_collateral_value(&loan_id), 1200);
+        assert_eq!(client.get_health_factor(&loan_id), 12_000);
+        assert!(!client.is_liquidatable(&loan_id));
+
+        // The market discounts the invoice to 1000, below the 104% threshold
+        let oracle_id = env.register_contract(None, MockOracle);
+        MockOracleClient::new(&env, &oracle_id).set_price(&7, &1000);
+        client.set_oracle(&oracle_id);
+        assert_eq!(client.get_oracle(), Some(oracle_id));
+        assert_eq!(client.get_collateral_value(&loan_id), 1000);
+        assert_eq!(client.get_health_factor(&loan_id), 10_000);
+        assert!(client.is_liquidatable(&loan_id));
+    }
+
+    #[test]
+    fn test_cancel_loan_before_disbursement() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+        let invoice_contract = env.register_contract(None, InvoiceContract);
+        let invoice_client = InvoiceContractClient::new(&env, &invoice_contract);
+
+        let admin = Address::generate(&env);
+        let token_address = testutils::register_token(&env, &admin);
+        client.init(&admin, &token_address);
+        invoice_client.init(&admin, &token_address);
+        client.set_invoice_contract(&invoice_contract);
+        invoice_client.set_lending_pool(&contract_id);
+        client.set_pool_cap(&5000);
+
+        let borrower = Address::generate(&env);
+        let due_date = env.ledger().timestamp() + 86400;
+        seed_invoice(&env, &invoice_contract, 1, &borrower, 1000, due_date);
+        let loan_id = client.create_loan(&borrower, &1, &1000, &1000, &due_date, &None, &terms_hash(&env));
+
+        // Nothing has left the pool yet, so the loan doesn't count against the cap
+        assert_eq!(client.get_remaining_capacity(), 5000);
+
+        // Only the borrower or the admin may cancel
+        assert!(client.try_cancel_loan(&Address::generate(&env), &loan_id).is_err());
+
+        client.cancel_loan(&borrower, &loan_id);
+        assert!(client.get_loan(&loan_id).is_none());
+        assert!(!invoice_client.is_locked(&1));
+        assert_eq!(client.get_active_loans(&0, &10).len(), 0);
+        assert_eq!(client.get_remaining_capacity(), 5000);
+
+        // The freed invoice can back a new loan, which the admin can cancel too
+        let next_id = client.create_loan(&borrower, &1, &1000, &1000, &due_date, &None, &terms_hash(&env));
+        assert_ne!(next_id, loan_id);
+        client.cancel_loan(&admin, &next_id);
+        assert!(client.get_loan(&next_id).is_none());
+    }
+
+    #[test]
+    fn test_cancel_loan_rejected_after_disbursement() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = testutils::register_token(&env, &admin);
+        client.init(&admin, &token_address);
+        testutils::mint(&env, &token_address, &contract_id, 1000);
+
+        let borrower = Address::generate(&env);
+        let due_date = env.ledger().timestamp() + 86400;
+        let loan_id = client.create_loan(&borrower, &1, &1000, &1000, &due_date, &None, &terms_hash(&env));
+        client.disburse(&loan_id, &None);
+
+        assert!(client.try_cancel_loan(&borrower, &loan_id).is_err());
+        assert!(client.try_cancel_loan(&admin, &loan_id).is_err());
+        assert!(client.get_loan(&loan_id).unwrap().is_disbursed);
+    }
+
+    #[test]
+    fn test_repay_loan_in_another_token() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let usdc = testutils::register_token(&env, &admin);
+        let eurc = testutils::register_token(&env, &admin);
+        client.init(&admin, &usdc);
+
+        // 900 principal for a year at 5% owes 945 USDC
+        let year = 31_536_000;
+        let borrower = Address::generate(&env);
+        testutils::mint(&env, &eurc, &borrower, 1100);
+        let loan_id = client.create_loan(&borrower, &1, &1000, &900, &year, &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &usdc, loan_id);
+        env.ledger().with_mut(|li| li.timestamp = year);
+
+        // EURC is unusable until the admin sets a rate for it
+        assert!(client.try_repay_loan(&loan_id, &Some(eurc.clone())).is_err());
+
+        // One EURC is worth 0.9 USDC, so 945 USDC costs 1050 EURC
+        client.set_exchange_rate(&eurc, &usdc, &900_000_000);
+        assert_eq!(client.get_exchange_rate(&eurc, &usdc), Some(900_000_000));
+
+        // Nor without a desk to swap it into USDC
+        assert!(client.try_repay_loan(&loan_id, &Some(eurc.clone())).is_err());
+        let desk = Address::generate(&env);
+        testutils::mint(&env, &usdc, &desk, 1000);
+        token::Client::new(&env, &usdc).approve(&desk, &contract_id, &1000, &1000);
+        client.set_settlement_desk(&Some(desk.clone()));
+        assert_eq!(client.get_settlement_desk(), Some(desk.clone()));
+
+        // The desk takes the EURC and the pool is paid in USDC, so its books stay in one token
+        let receipt = client.repay_loan(&loan_id, &Some(eurc.clone()));
+        assert_eq!(receipt.total_paid, 945);
+        assert_eq!(receipt.interest_paid, 45);
+        assert!(client.get_loan_summary(&loan_id).is_some());
+        assert_eq!(testutils::balance(&env, &eurc, &borrower), 50);
+        assert_eq!(testutils::balance(&env, &eurc, &desk), 1050);
+        assert_eq!(testutils::balance(&env, &eurc, &contract_id), 0);
+        assert_eq!(testutils::balance(&env, &usdc, &desk), 55);
+        assert_eq!(testutils::balance(&env, &usdc, &contract_id), 945);
+        assert_eq!(client.get_lp_yield() + client.get_treasury_balance(), 45);
+
+        assert!(client.try_set_exchange_rate(&eurc, &usdc, &0).is_err());
+    }
+
+    #[test]
+    fn test_withdraw_queue_fulfilled_by_repayment() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = testutils::register_token(&env, &admin);
+        client.init(&admin, &token_address);
+
+        let lp = Address::generate(&env);
+        let other_lp = Address::generate(&env);
+        testutils::mint(&env, &token_address, &lp, 1000);
+        testutils::mint(&env, &token_address, &other_lp, 200);
+        client.deposit(&lp, &1000);
+        client.deposit(&other_lp, &200);
+
+        // 1000 of the 1200 deposited is lent out
+        let year = 31_536_000;
+        let borrower = Address::generate(&env);
+        let loan_id = client.create_loan(&borrower, &1, &1200, &1000, &year, &None, &terms_hash(&env));
+        client.disburse(&loan_id, &None);
+
+        // Without the queue a withdrawal beyond idle liquidity is rejected
+        assert!(client.try_withdraw(&lp, &600).is_err());
+
+        client.set_withdraw_queue(&true);
+        client.withdraw(&lp, &600);
+        // Idle liquidity would cover this one, but it waits its turn behind the first
+        client.withdraw(&other_lp, &100);
+        assert_eq!(client.get_queue_position(&lp), Some(0));
+        assert_eq!(client.get_queue_position(&other_lp), Some(1));
+        assert_eq!(client.get_lp_deposit(&lp), 400);
+        assert!(client.try_claim_queued_withdrawal(&lp).is_err());
+        assert_eq!(testutils::balance(&env, &token_address, &lp), 0);
+
+        // Repayment refills the pool and serves the queue in order
+        env.ledger().with_mut(|li| li.timestamp = year);
+        testutils::mint(&env, &token_address, &borrower, 50);
+        client.repay_loan(&loan_id, &None);
+        assert_eq!(client.get_queue_position(&lp), None);
+        assert_eq!(client.get_queue_position(&other_lp), None);
+
+        // Reserved liquidity can't be lent out before the LPs claim it
+        let next_id = client.create_loan(&borrower, &2, &1000, &600, &year, &None, &terms_hash(&env));
+        assert!(client.try_disburse(&next_id, &None).is_err());
+
+        assert_eq!(client.claim_queued_withdrawal(&lp), 600);
+        assert_eq!(client.claim_queued_withdrawal(&other_lp), 100);
+        assert_eq!(testutils::balance(&env, &token_address, &lp), 600);
+        assert_eq!(testutils::balance(&env, &token_address, &other_lp), 100);
+        assert!(client.try_claim_queued_withdrawal(&lp).is_err());
+    }
+
+    #[test]
+    fn test_lifetime_interest_survives_treasury_payouts() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = testutils::register_token(&env, &admin);
+        client.init(&admin, &token_address);
+        client.set_protocol_share(&2000);
+
+        // Two one-year loans each pay 50 interest, 10 of it to the treasury
+        let year = 31_536_000;
+        let borrower = Address::generate(&env);
+        testutils::mint(&env, &token_address, &borrower, 2100);
+        let first = client.create_loan(&borrower, &1, &1000, &1000, &year, &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, first);
+        let second = client.create_loan(&borrower, &2, &1000, &1000, &year, &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, second);
+        env.ledger().with_mut(|li| li.timestamp = year);
+
+        client.repay_loan(&first, &None);
+        assert_eq!(client.get_lifetime_interest(), 50);
+        assert_eq!(client.get_treasury_balance(), 10);
+
+        let treasury = Address::generate(&env);
+        client.withdraw_treasury(&treasury, &10);
+        assert_eq!(client.get_treasury_balance(), 0);
+        assert_eq!(client.get_lifetime_interest(), 50);
+        assert_eq!(testutils::balance(&env, &token_address, &treasury), 10);
+
+        client.repay_loan(&second, &None);
+        assert_eq!(client.get_lifetime_interest(), 100);
+        client.withdraw_treasury(&treasury, &10);
+        assert_eq!(client.get_lifetime_interest(), 100);
+        assert!(client.try_withdraw_treasury(&treasury, &1).is_err());
+    }
+
+    #[test]
+    fn test_loan_terms_hash_is_fixed_at_creation() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = testutils::register_token(&env, &admin);
+        client.init(&admin, &token_address);
+
+        let day = 86_400;
+        let agreement = env.crypto().sha256(&Bytes::from_slice(&env, b"Invoice financing agreement v1")).to_bytes();
+        let borrower = Address::generate(&env);
+        testutils::mint(&env, &token_address, &borrower, 1000);
+        let loan_id = client.create_loan(&borrower, &1, &1000, &1000, &(30 * day), &None, &agreement);
+        disburse_loan(&env, &client, &token_address, loan_id);
+        assert_eq!(client.get_loan(&loan_id).unwrap().terms_hash, agreement);
+
+        // Changing the loan's dates leaves the agreement it was written under untouched
+        client.extend_loan(&loan_id, &(30 * day));
+        client.reschedule(&loan_id, &(45 * day));
+        client.poke_loan(&loan_id);
+        let loan = client.get_loan(&loan_id).unwrap();
+        assert_eq!(loan.due_date, 45 * day);
+        assert_eq!(loan.terms_hash, agreement);
+    }
+
+    #[test]
+    fn test_mint_and_borrow_is_atomic() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+        let invoice_contract = env.register_contract(None, InvoiceContract);
+        let invoice_client = InvoiceContractClient::new(&env, &invoice_contract);
+
+        let admin = Address::generate(&env);
+        let token_address = testutils::register_token(&env, &admin);
+        client.init(&admin, &token_address);
+        invoice_client.init(&admin, &token_address);
+        client.set_invoice_contract(&invoice_contract);
+        invoice_client.set_lending_pool(&contract_id);
+
+        // The backend signs the owner's next mint
+        let signing_key = SigningKey::from_bytes(&[3; 32]);
+        let key_index = invoice_client.add_backend(&BytesN::from_array(&env, &signing_key.verifying_key().to_bytes()));
+        let owner = Address::generate(&env);
+        let due_date = env.ledger().timestamp() + 86400;
+        let payload = invoice_nft::testutils::mint_payload(&env, &owner, 1000, due_date, 700, 0);
+        let message: std::vec::Vec<u8> = payload.iter().collect();
+        let signature = BytesN::from_array(&env, &signing_key.sign(&message).to_bytes());
+
+        // A failing create_loan undoes the mint: no invoice, and the signature's nonce is unspent
+        client.set_pause_flags(&PauseFlags { deposits: false, borrows: true, repayments: false, liquidations: false });
+        assert!(client.try_mint_and_borrow(&owner, &1000, &due_date, &700, &key_index, &signature, &800, &terms_hash(&env)).is_err());
+        assert!(invoice_client.get_invoice(&1).is_none());
+        assert_eq!(invoice_client.get_mint_nonce(&owner), 0);
+
+        // The same signature then goes through once borrowing resumes
+        client.set_paused(&false);
+        let loan_id = client.mint_and_borrow(&owner, &1000, &due_date, &700, &key_index, &signature, &800, &terms_hash(&env));
+        let loan = client.get_loan(&loan_id).unwrap();
+        assert_eq!(loan.invoice_id, 1);
+        assert_eq!(loan.borrower, owner);
+        assert_eq!(loan.principal, 800);
+        assert_eq!(invoice_client.get_invoice(&1).unwrap().owner, owner);
+        assert!(invoice_client.is_locked(&1));
+        assert_eq!(invoice_client.get_mint_nonce(&owner), 1);
+    }
+
+    #[test]
+    fn test_export_state_unchanged_by_upgrade() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = testutils::register_token(&env, &admin);
+        client.init(&admin, &token_address);
+        client.set_protocol_share(&1000);
+        client.set_pool_cap(&1_000_000);
+
+        // Give every counter something to hold
+        let year = 31_536_000;
+        let lp = Address::generate(&env);
+        let borrower = Address::generate(&env);
+        testutils::mint(&env, &token_address, &lp, 5000);
+        testutils::mint(&env, &token_address, &borrower, 100);
+        client.deposit(&lp, &5000);
+        let repaid = client.create_loan(&borrower, &1, &1200, &1000, &year, &None, &terms_hash(&env));
+        client.disburse(&repaid, &None);
+        let open = client.create_loan(&borrower, &2, &3000, &2000, &(2 * year), &None, &terms_hash(&env));
+        client.disburse(&open, &None);
+        env.ledger().with_mut(|li| li.timestamp = year);
+        client.repay_loan(&repaid, &None);
+
+        let before = client.export_state();
+        assert_eq!(before.admin, admin);
+        assert_eq!(before.token, token_address);
+        assert_eq!(before.last_loan_id, 2);
+        assert_eq!(before.active_loans, 1);
+        assert_eq!(before.total_outstanding, 2000);
+        assert_eq!(before.total_deposits, 5000);
+        assert_eq!(before.treasury_balance, 5);
+        assert_eq!(before.lp_yield, 45);
+        assert_eq!(before.lifetime_interest, 50);
+        assert_eq!(before.config.pool_cap, 1_000_000);
+
+        let wasm_hash = env.deployer().upload_contract_wasm(EMPTY_CONTRACT_WASM);
+        client.upgrade(&wasm_hash);
+
+        // The new code exports nothing, so read the state the old code would see straight from storage
+        assert!(client.try_get_pool_cap().is_err());
+        let after: PoolSnapshot = env.as_contract(&contract_id, || LendingPool::export_state(env.clone()));
+        assert_eq!(after, before);
+    }
+
+    #[test]
+    fn test_reputation_tracks_repayment_behavior() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = testutils::register_token(&env, &admin);
+        client.init(&admin, &token_address);
+
+        let day = 86_400;
+        let borrower = Address::generate(&env);
+        testutils::mint(&env, &token_address, &borrower, 10_000);
+        assert_eq!(client.get_reputation(&borrower), 0);
+
+        // Repaying by the due date earns a point
+        let on_time = client.create_loan(&borrower, &1, &1000, &1000, &(10 * day), &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, on_time);
+        let late = client.create_loan(&borrower, &2, &1000, &1000, &(10 * day), &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, late);
+        let defaulted = client.create_loan(&borrower, &3, &1000, &1000, &(10 * day), &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, defaulted);
+        env.ledger().with_mut(|li| li.timestamp = 10 * day);
+        client.repay_loan(&on_time, &None);
+        assert_eq!(client.get_reputation(&borrower), 1);
+
+        // A late repayment earns nothing, and a default costs a point
+        env.ledger().with_mut(|li| li.timestamp = 11 * day);
+        client.repay_loan(&late, &None);
+        assert_eq!(client.get_reputation(&borrower), 1);
+        client.sweep_defaults(&vec![&env, defaulted]);
+        assert_eq!(client.get_reputation(&borrower), 0);
+
+        // Per-borrower and per-loan records live in persistent storage, off the contract instance
+        env.as_contract(&contract_id, || {
+            for key in [
+                DataKey::Reputation(borrower.clone()),
+                DataKey::SeenBorrower(borrower.clone()),
+                DataKey::BorrowerLoans(borrower.clone()),
+                DataKey::DefaultedAt(defaulted),
+            ] {
+                assert!(env.storage().persistent().has(&key));
+                assert!(!env.storage().instance().has(&key));
+            }
+        });
+    }
+
+    #[test]
+    fn test_required_collateral_meets_ltv_cap() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+        let invoice_contract = env.register_contract(None, InvoiceContract);
+        let invoice_client = InvoiceContractClient::new(&env, &invoice_contract);
+
+        let admin = Address::generate(&env);
+        client.init(&admin, &Address::generate(&env));
+        invoice_client.init(&admin, &Address::generate(&env));
+        client.set_invoice_contract(&invoice_contract);
+        invoice_client.set_lending_pool(&contract_id);
+        assert_eq!(client.required_collateral(&1000), 0);
+
+        client.set_max_ltv(&8000);
+        assert_eq!(client.required_collateral(&1000), 1250);
+
+        // An invoice worth exactly the required collateral is accepted, one unit less is over the cap
+        let borrower = Address::generate(&env);
+        let due_date = env.ledger().timestamp() + 86400;
+        seed_invoice(&env, &invoice_contract, 1, &borrower, 1249, due_date);
+        seed_invoice(&env, &invoice_contract, 2, &borrower, 1250, due_date);
+        assert!(client.try_create_loan(&borrower, &1, &1249, &1000, &due_date, &None, &terms_hash(&env)).is_err());
+        client.create_loan(&borrower, &2, &1250, &1000, &due_date, &None, &terms_hash(&env));
+
+        // The face amount comes from the invoice itself, so overstating it doesn't get past the cap
+        assert!(client.try_create_loan(&borrower, &1, &1250, &1000, &due_date, &None, &terms_hash(&env)).is_err());
+        assert_eq!(invoice_client.get_invoice_status(&1), InvoiceStatus::Active);
+
+        // Where the division isn't exact the figure rounds up onto the boundary
+        client.set_max_ltv(&7000);
+        let collateral = client.required_collateral(&1000);
+        assert_eq!(collateral, 1429);
+        seed_invoice(&env, &invoice_contract, 3, &borrower, collateral - 1, due_date);
+        seed_invoice(&env, &invoice_contract, 4, &borrower, collateral, due_date);
+        assert!(client.try_create_loan(&borrower, &3, &(collateral - 1), &1000, &due_date, &None, &terms_hash(&env)).is_err());
+        client.create_loan(&borrower, &4, &collateral, &1000, &due_date, &None, &terms_hash(&env));
+
+        // With an oracle the cap applies to the quoted value, so a discounted invoice supports less
+        client.set_max_ltv(&8000);
+        let oracle_id = env.register_contract(None, MockOracle);
+        MockOracleClient::new(&env, &oracle_id).set_price(&5, &900);
+        client.set_oracle(&oracle_id);
+        seed_invoice(&env, &invoice_contract, 5, &borrower, 1000, due_date);
+        assert_eq!(client.required_collateral(&720), 900);
+        assert!(client.try_create_loan(&borrower, &5, &1000, &800, &due_date, &None, &terms_hash(&env)).is_err());
+        assert_eq!(invoice_client.get_invoice_status(&5), InvoiceStatus::Active);
+        client.create_loan(&borrower, &5, &1000, &720, &due_date, &None, &terms_hash(&env));
+    }
+
+    #[test]
+    fn test_assign_loan_to_new_borrower() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = testutils::register_token(&env, &admin);
+        client.init(&admin, &token_address);
+
+        let year = 31_536_000;
+        let original = Address::generate(&env);
+        let assignee = Address::generate(&env);
+        let loan_id = client.create_loan(&original, &1, &1000, &1000, &year, &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, loan_id);
+        let kept = client.create_loan(&original, &2, &1000, &1000, &year, &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, kept);
+
+        // A blocked address can't take a loan over
+        let blocked = Address::generate(&env);
+        client.set_blocked(&blocked, &true);
+        assert!(client.try_assign_loan(&loan_id, &blocked).is_err());
+
+        client.assign_loan(&loan_id, &assignee);
+        let auths = env.auths();
+        assert_eq!(auths.len(), 3);
+        assert!(auths.iter().any(|(address, _)| *address == original));
+        assert!(auths.iter().any(|(address, _)| *address == assignee));
+        assert!(auths.iter().any(|(address, _)| *address == admin));
+
+        // Taking on a loan makes the assignee a borrower like any other
+        assert_eq!(client.get_unique_borrower_count(), 2);
+
+        assert_eq!(client.get_loan(&loan_id).unwrap().borrower, assignee);
+        assert_eq!(client.get_loans_by_borrower(&assignee), vec![&env, loan_id]);
+        assert_eq!(client.get_loans_by_borrower(&original), vec![&env, kept]);
+
+        // The assignee now owes the loan; the original borrower's money isn't touched
+        env.ledger().with_mut(|li| li.timestamp = year);
+        testutils::mint(&env, &token_address, &assignee, 1050);
+        client.repay_loan(&loan_id, &None);
+        assert_eq!(testutils::balance(&env, &token_address, &assignee), 0);
+        assert!(client.try_assign_loan(&loan_id, &original).is_err());
+    }
+
+    #[test]
+    fn test_assign_loan_moves_collateral_and_guarantee() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+        let invoice_contract = env.register_contract(None, InvoiceContract);
+        let invoice_client = InvoiceContractClient::new(&env, &invoice_contract);
+
+        let admin = Address::generate(&env);
+        let token_address = testutils::register_token(&env, &admin);
+        client.init(&admin, &token_address);
+        invoice_client.init(&admin, &Address::generate(&env));
+        client.set_invoice_contract(&invoice_contract);
+        invoice_client.set_lending_pool(&contract_id);
+
+        let original = Address::generate(&env);
+        let assignee = Address::generate(&env);
+        let guarantor = Address::generate(&env);
+        let due_date = env.ledger().timestamp() + 86400;
+        seed_invoice(&env, &invoice_contract, 1, &original, 1000, due_date);
+        let loan_id = client.create_loan(&original, &1, &1000, &1000, &due_date, &Some(guarantor.clone()), &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, loan_id);
+
+        // No assignments while borrowing is paused
+        client.set_paused(&true);
+        assert!(client.try_assign_loan(&loan_id, &assignee).is_err());
+        client.set_paused(&false);
+
+        // The guarantor has to agree to back the new borrower
+        client.assign_loan(&loan_id, &assignee);
+        let auths = env.auths();
+        assert_eq!(auths.len(), 4);
+        assert!(auths.iter().any(|(address, _)| *address == guarantor));
+        assert_eq!(client.get_loan(&loan_id).unwrap().guarantor, Some(guarantor));
+
+        // The pledged invoice follows the loan and goes back to the assignee once it is repaid
+        assert_eq!(invoice_client.owner_of(&1), Some(assignee.clone()));
+        assert_eq!(invoice_client.get_invoice_status(&1), InvoiceStatus::Locked);
+        testutils::mint(&env, &token_address, &assignee, 1000);
+        client.repay_loan(&loan_id, &None);
+        assert_eq!(invoice_client.get_invoice_status(&1), InvoiceStatus::Active);
+        assert_eq!(invoice_client.owner_of(&1), Some(assignee));
+    }
+
+    #[test]
+    fn test_partial_repayment_must_restore_health() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = testutils::register_token(&env, &admin);
+        client.init(&admin, &token_address);
+        client.set_min_health_factor(&12_000);
+
+        let borrower = Address::generate(&env);
+        testutils::mint(&env, &token_address, &borrower, 1000);
+        let due_date = env.ledger().timestamp() + 86400;
+        let loan_id = client.create_loan(&borrower, &7, &2000, &1000, &due_date, &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, loan_id);
+
+        // At face value the invoice covers any remainder
+        assert_eq!(client.required_repayment(&loan_id), 0);
+        client.repay_partial(&loan_id, &10);
+        assert_eq!(client.get_loan(&loan_id).unwrap().remaining_principal, 990);
+
+        // Marked down to 1100, at most 916 may stay outstanding to keep 120% coverage
+        let oracle_id = env.register_contract(None, MockOracle);
+        MockOracleClient::new(&env, &oracle_id).set_price(&7, &1100);
+        client.set_oracle(&oracle_id);
+        assert_eq!(client.required_repayment(&loan_id), 74);
+
+        // Paying down part of the shortfall still improves coverage, so it is accepted
+        let health_before = client.get_health_factor(&loan_id);
+        client.repay_partial(&loan_id, &50);
+        assert!(client.get_health_factor(&loan_id) > health_before);
+        assert!(client.get_health_factor(&loan_id) < 12_000);
+        assert_eq!(client.required_repayment(&loan_id), 24);
+
+        client.repay_partial(&loan_id, &24);
+        assert_eq!(client.get_loan(&loan_id).unwrap().remaining_principal, 916);
+        assert!(client.get_health_factor(&loan_id) >= 12_000);
+        assert_eq!(client.required_repayment(&loan_id), 0);
+
+        // With the collateral written off no repayment improves coverage, so none below the minimum is accepted
+        MockOracleClient::new(&env, &oracle_id).set_price(&7, &0);
+        assert_eq!(client.get_health_factor(&loan_id), 0);
+        assert!(client.try_repay_partial(&loan_id, &100).is_err());
+        assert_eq!(client.get_loan(&loan_id).unwrap().remaining_principal, 916);
+    }
+
+    #[test]
+    fn test_liquidator_whitelist() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = testutils::register_token(&env, &admin);
+        client.init(&admin, &token_address);
+
+        let listed = Address::generate(&env);
+        let outsider = Address::generate(&env);
+        testutils::mint(&env, &token_address, &listed, 500);
+        testutils::mint(&env, &token_address, &outsider, 500);
+
+        let due_date = env.ledger().timestamp() + 86400;
+        let loan_id = client.create_loan(&Address::generate(&env), &1, &2000, &1000, &due_date, &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, loan_id);
+        env.ledger().with_mut(|li| li.timestamp = due_date + 1);
+
+        // Open by default: anyone may liquidate
+        assert!(client.is_liquidator(&outsider));
+        client.liquidate_partial(&outsider, &loan_id, &100);
+
+        client.set_liquidator_whitelist(&true);
+        client.add_liquidator(&listed);
+        assert!(client.is_liquidator(&listed));
+        assert!(!client.is_liquidator(&outsider));
+        assert!(client.try_liquidate_partial(&outsider, &loan_id, &100).is_err());
+        client.liquidate_partial(&listed, &loan_id, &100);
+        assert_eq!(client.get_loan(&loan_id).unwrap().remaining_principal, 800);
+
+        // Delisting takes the right away again, and turning the whitelist off restores open access
+        client.remove_liquidator(&listed);
+        assert!(client.try_liquidate_partial(&listed, &loan_id, &100).is_err());
+        client.set_liquidator_whitelist(&false);
+        client.liquidate_partial(&outsider, &loan_id, &100);
+        assert_eq!(client.get_loan(&loan_id).unwrap().remaining_principal, 700);
+    }
+
+    #[test]
+    fn test_sweep_dust_into_treasury() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = testutils::register_token(&env, &admin);
+        client.init(&admin, &token_address);
+
+        let day = 86_400;
+        let borrower = Address::generate(&env);
+        testutils::mint(&env, &token_address, &borrower, 10_000);
+        let repaid = client.create_loan(&borrower, &1, &1000, &1000, &(10 * day), &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, repaid);
+        let defaulted = client.create_loan(&borrower, &2, &1000, &1000, &(4 * day), &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, defaulted);
+        let open = client.create_loan(&borrower, &3, &1000, &1000, &(20 * day), &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, open);
+
+        // Paying down principal before a whole unit of interest accrues leaves sub-unit remainders behind:
+        // a day of 5% on 1000 is 0.136986, three days 0.410958
+        env.ledger().with_mut(|li| li.timestamp = day);
+        client.repay_partial(&repaid, &500);
+        client.repay_partial(&open, &500);
+        env.ledger().with_mut(|li| li.timestamp = 3 * day);
+        client.repay_partial(&defaulted, &500);
+
+        // Seven more days on the remaining 500 add 0.479452 before the loan closes, still short of a unit
+        env.ledger().with_mut(|li| li.timestamp = 8 * day);
+        assert_eq!(client.repay_loan(&repaid, &None).interest_paid, 0);
+        client.sweep_defaults(&vec![&env, defaulted]);
+        assert!(client.get_loan(&defaulted).unwrap().is_defaulted);
+        let treasury = client.get_treasury_balance();
+
+        // Only the closed loans are swept; the open loan still needs its remainder
+        assert_eq!(client.sweep_dust(&vec![&env, repaid, defaulted, open, 99]), 616_438 + 410_958);
+        assert_eq!(client.get_treasury_balance(), treasury + 1);
+        assert_eq!(client.get_loan_view(&open).carried_fraction, 136_986);
+        assert_eq!(client.sweep_dust(&vec![&env, repaid, defaulted]), 0);
+
+        // The 0.027396 left over carries into the next sweep, where the open loan adds 0.856164 more once it closes
+        env.ledger().with_mut(|li| li.timestamp = 13 * day + day / 2);
+        assert_eq!(client.repay_loan(&open, &None).interest_paid, 0);
+        assert_eq!(client.sweep_dust(&vec![&env, open]), 136_986 + 856_164);
+        assert_eq!(client.get_treasury_balance(), treasury + 2);
+    }
+
+    #[test]
+    fn test_borrow_fee_paid_in_fee_token() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        let fee_token = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+
+        let lp = Address::generate(&env);
+        let borrower = Address::generate(&env);
+        token::StellarAssetClient::new(&env, &token_address).mint(&lp, &10_000);
+        token::StellarAssetClient::new(&env, &fee_token).mint(&borrower, &100);
+        client.deposit(&lp, &10_000);
+
+        // 2% origination fee, with two fee tokens worth one lending token
+        client.set_borrow_fee(&200);
+        client.set_fee_token(&Some(fee_token.clone()));
+        client.set_exchange_rate(&fee_token, &token_address, &500_000_000);
+
+        let due_date = env.ledger().timestamp() + 86400 * 30;
+        let loan_id = client.create_loan(&borrower, &1, &2000, &1000, &due_date, &None, &terms_hash(&env));
+        client.disburse(&loan_id, &None);
+
+        // The full principal arrives in the lending token while the fee comes out of the fee token
+        let lending = token::Client::new(&env, &token_address);
+        let fees = token::Client::new(&env, &fee_token);
+        assert_eq!(lending.balance(&borrower), 1000);
+        assert_eq!(fees.balance(&borrower), 60);
+        assert_eq!(client.get_collected_fees(&fee_token), 40);
+        assert_eq!(client.get_treasury_balance() + client.get_lp_yield(), 0);
+
+        let treasury = Address::generate(&env);
+        client.withdraw_fees(&fee_token, &treasury, &40);
+        assert_eq!(fees.balance(&treasury), 40);
+        assert_eq!(client.get_collected_fees(&fee_token), 0);
+
+        // Without a fee token the fee is held back from the payout as pool revenue
+        client.set_fee_token(&None);
+        let other = Address::generate(&env);
+        let loan_id = client.create_loan(&other, &2, &2000, &1000, &due_date, &None, &terms_hash(&env));
+        client.disburse(&loan_id, &None);
+        assert_eq!(lending.balance(&other), 980);
+        assert_eq!(client.get_lp_yield(), 20);
+
+        // A fee paid in the fee token goes to the protocol, so it neither prepays interest nor counts as pool revenue
+        client.set_fee_token(&Some(fee_token.clone()));
+        token::StellarAssetClient::new(&env, &fee_token).mint(&other, &8);
+        let lp_yield = client.get_lp_yield();
+        assert_eq!(client.extend_loan(&loan_id, &(86400 * 30)), 4);
+        assert_eq!(fees.balance(&other), 0);
+        assert_eq!(client.get_collected_fees(&fee_token), 8);
+        assert_eq!(client.get_lp_yield(), lp_yield);
+
+        // So the first loan, whose origination fee was also paid in the fee token, still owes its full interest
+        env.ledger().with_mut(|li| li.timestamp = due_date);
+        token::StellarAssetClient::new(&env, &token_address).mint(&borrower, &100);
+        token::StellarAssetClient::new(&env, &token_address).mint(&other, &100);
+        assert_eq!(client.repay_loan(&1, &None).interest_paid, 4);
+        let receipt = client.repay_loan(&loan_id, &None);
+        assert_eq!(receipt.interest_paid, 4);
+    }
+
+    #[test]
+    fn test_get_permissions() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+        client.set_liquidator_whitelist(&true);
+
+        assert_eq!(client.get_permissions(&admin), Permissions {
+            can_deposit: true,
+            can_borrow: true,
+            can_liquidate: false,
+            is_admin: true,
+            is_blocked: false,
+        });
+
+        // A whitelisted liquidator who also borrows
+        let borrower = Address::generate(&env);
+        client.add_liquidator(&borrower);
+        assert_eq!(client.get_permissions(&borrower), Permissions {
+            can_deposit: true,
+            can_borrow: true,
+            can_liquidate: true,
+            is_admin: false,
+            is_blocked: false,
+        });
+
+        client.set_pause_flags(&PauseFlags { deposits: false, borrows: true, repayments: false, liquidations: false });
+        assert!(!client.get_permissions(&borrower).can_borrow);
+        client.set_paused(&false);
+
+        let blocked = Address::generate(&env);
+        client.add_liquidator(&blocked);
+        client.set_blocked(&blocked, &true);
+        assert_eq!(client.get_permissions(&blocked), Permissions {
+            can_deposit: false,
+            can_borrow: false,
+            can_liquidate: false,
+            is_admin: false,
+            is_blocked: true,
+        });
+
+        let due_date = env.ledger().timestamp() + 86400;
+        assert!(client.try_create_loan(&blocked, &1, &2000, &1000, &due_date, &None, &terms_hash(&env)).is_err());
+        assert!(client.try_deposit(&blocked, &100).is_err());
+
+        client.set_blocked(&blocked, &false);
+        assert!(client.get_permissions(&blocked).can_borrow);
+        client.create_loan(&blocked, &1, &2000, &1000, &due_date, &None, &terms_hash(&env));
+    }
+
+    #[test]
+    fn test_capitalize_interest() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+
+        let lp = Address::generate(&env);
+        token::StellarAssetClient::new(&env, &token_address).mint(&lp, &1_000_000);
+        client.deposit(&lp, &1_000_000);
+
+        let borrower = Address::generate(&env);
+        let due_date = env.ledger().timestamp() + 2 * 31_536_000;
+        let loan_id = client.create_loan(&borrower, &1, &2_000_000, &1_000_000, &due_date, &None, &terms_hash(&env));
+        client.disburse(&loan_id, &None);
+
+        // A year at 5% accrues 50_000, which becomes principal
+        env.ledger().with_mut(|li| li.timestamp = 31_536_000);
+        assert_eq!(client.capitalize_interest(&loan_id), 50_000);
+        let loan = client.get_loan(&loan_id).unwrap();
+        assert_eq!(loan.remaining_principal, 1_050_000);
+        assert_eq!(loan.start_time, 31_536_000);
+        assert_eq!(loan.last_payment_time, 31_536_000);
+        assert_eq!(loan.interest, 52_500);
+        assert_eq!(client.get_total_owed(&loan_id), 1_050_000);
+
+        // The next year's interest accrues on the larger base, and the capitalized year is not charged again
+        env.ledger().with_mut(|li| li.timestamp = 2 * 31_536_000);
+        assert_eq!(client.get_total_owed(&loan_id), 1_102_500);
+
+        token::StellarAssetClient::new(&env, &token_address).mint(&borrower, &102_500);
+        client.repay_loan(&loan_id, &None);
+        assert!(client.try_capitalize_interest(&loan_id).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid backend key")]
+    fn test_set_backend_pubkey_rejects_zero_key() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        client.init(&Address::generate(&env), &Address::generate(&env));
+        client.set_backend_pubkey(&BytesN::from_array(&env, &[0u8; 32]));
+    }
+
+    #[test]
+    fn test_solvency_ratio() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+        assert_eq!(client.get_solvency_ratio(), u32::MAX);
+
+        let lp = Address::generate(&env);
+        token::StellarAssetClient::new(&env, &token_address).mint(&lp, &10_000);
+        client.deposit(&lp, &10_000);
+
+        // Lending out capital moves it from idle to outstanding without changing solvency
+        let borrower = Address::generate(&env);
+        let due_date = env.ledger().timestamp() + 86400;
+        let loan_id = client.create_loan(&borrower, &1, &2000, &1000, &due_date, &None, &terms_hash(&env));
+        client.disburse(&loan_id, &None);
+        assert_eq!(client.get_solvency_ratio(), 10_000);
+
+        // Once the loan defaults unrecovered, its principal is bad debt and LPs are short
+        env.ledger().with_mut(|li| li.timestamp = due_date + 1);
+        client.sweep_defaults(&vec![&env, loan_id]);
+        assert_eq!(client.get_bad_debt(), 1000);
+        assert_eq!(client.get_solvency_ratio(), 9000);
+    }
+
+    #[test]
+    fn test_early_repayment_penalty() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+        client.set_min_hold_period(&(7 * 86400));
+        client.set_early_repayment_penalty(&100);
+
+        let borrower = Address::generate(&env);
+        token::StellarAssetClient::new(&env, &token_address).mint(&borrower, &10_100);
+        let due_date = env.ledger().timestamp() + 30 * 86400;
+
+        // Closing inside the hold period costs 1% of principal on top of interest
+        let loan_id = client.create_loan(&borrower, &1, &20_000, &10_000, &due_date, &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, loan_id);
+        let receipt = client.repay_loan(&loan_id, &None);
+        assert_eq!(receipt.fee_paid, 100);
+        assert_eq!(receipt.total_paid, 10_100);
+        assert_eq!(client.get_lp_yield(), 100);
+
+        // Repaying in part can't dodge it: a payment covering principal and interest must include the penalty too
+        token::StellarAssetClient::new(&env, &token_address).mint(&borrower, &10_100);
+        let loan_id = client.create_loan(&borrower, &2, &20_000, &10_000, &due_date, &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, loan_id);
+        assert!(client.try_repay_partial(&loan_id, &10_000).is_err());
+        client.repay_partial(&loan_id, &10_100);
+        assert!(client.get_loan_summary(&loan_id).is_some());
+
+        // Once the hold period has passed, only principal and interest are due
+        let loan_id = client.create_loan(&borrower, &3, &20_000, &10_000, &due_date, &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, loan_id);
+        env.ledger().with_mut(|li| li.timestamp += 7 * 86400);
+        token::StellarAssetClient::new(&env, &token_address).mint(&borrower, &10_100);
+        let receipt = client.repay_loan(&loan_id, &None);
+        assert_eq!(receipt.fee_paid, 0);
+        assert_eq!(receipt.total_paid, receipt.principal_paid + receipt.interest_paid);
+    }
+
+    #[test]
+    fn test_defaulted_loan_stops_accruing() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let token_address = testutils::register_token(&env, &Address::generate(&env));
+        client.init(&Address::generate(&env), &token_address);
+        let borrower = Address::generate(&env);
+        let due_date = env.ledger().timestamp() + 30 * 86400;
+        let loan_id = client.create_loan(&borrower, &1, &2_000_000, &1_000_000, &due_date, &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, loan_id);
+
+        env.ledger().with_mut(|li| li.timestamp = due_date + 1);
+        client.sweep_defaults(&vec![&env, loan_id]);
+        assert_eq!(client.get_defaulted_at(&loan_id), Some(due_date + 1));
+        let owed_at_default = client.get_total_owed(&loan_id);
+        assert!(owed_at_default > 1_000_000);
+
+        // Interest is frozen at the default, however far the ledger moves on
+        env.ledger().with_mut(|li| li.timestamp = due_date + 365 * 86400);
+        assert_eq!(client.get_total_owed(&loan_id), owed_at_default);
+    }
+
+    #[test]
+    fn test_interest_per_second() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        client.init(&Address::generate(&env), &Address::generate(&env));
+        let term = 90 * 86400;
+        let due_date = env.ledger().timestamp() + term;
+        let loan_id = client.create_loan(&Address::generate(&env), &1, &20_000, &10_000, &due_date, &None, &terms_hash(&env));
+
+        // Ticking at the per-second rate for the whole term adds up to the loan's interest, within a unit of rounding
+        let per_second = client.interest_per_second(&loan_id);
+        assert!(per_second > 0);
+        let interest = client.get_loan(&loan_id).unwrap().interest;
+        assert!((per_second * term as i128 / 1_000_000_000_000 - interest).abs() <= 1);
+
+        // Stepped rates tick at whichever step applies now
+        let loan_id = client.create_loan(&Address::generate(&env), &2, &20_000, &10_000, &due_date, &None, &terms_hash(&env));
+        client.set_rate_schedule(&loan_id, &vec![&env, RateStep { start_offset: 0, rate_bps: 500 }, RateStep { start_offset: 86400, rate_bps: 1000 }]);
+        assert_eq!(client.interest_per_second(&loan_id), per_second);
+        env.ledger().with_mut(|li| li.timestamp += 86400);
+        assert!((client.interest_per_second(&loan_id) - 2 * per_second).abs() <= 1);
+    }
+
+    #[test]
+    fn test_max_total_loans() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+        assert_eq!(client.get_remaining_loan_slots(), u32::MAX);
+        client.set_max_total_loans(&2);
+
+        let borrower = Address::generate(&env);
+        token::StellarAssetClient::new(&env, &token_address).mint(&borrower, &1000);
+        let due_date = env.ledger().timestamp() + 86400;
+        let first = client.create_loan(&borrower, &1, &2000, &1000, &due_date, &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, first);
+        assert_eq!(client.get_remaining_loan_slots(), 1);
+        client.create_loan(&borrower, &2, &2000, &1000, &due_date, &None, &terms_hash(&env));
+        assert_eq!(client.get_remaining_loan_slots(), 0);
+        assert!(client.try_create_loan(&borrower, &3, &2000, &1000, &due_date, &None, &terms_hash(&env)).is_err());
+
+        // Closing a loan frees its slot
+        client.repay_loan(&first, &None);
+        assert_eq!(client.get_remaining_loan_slots(), 1);
+        client.create_loan(&borrower, &3, &2000, &1000, &due_date, &None, &terms_hash(&env));
+        assert_eq!(client.get_remaining_loan_slots(), 0);
+    }
+
+    #[test]
+    fn test_borrower_discovers_loans_by_event_topic() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        client.init(&Address::generate(&env), &Address::generate(&env));
+        let borrower = Address::generate(&env);
+        let other = Address::generate(&env);
+        let due_date = env.ledger().timestamp() + 86400;
+
+        // Filter the loan_created events a borrower would subscribe to as each loan is opened
+        let mut discovered: std::vec::Vec<u64> = std::vec::Vec::new();
+        for (invoice_id, party) in [(1, &borrower), (2, &other), (3, &borrower)] {
+            client.create_loan(party, &invoice_id, &2000, &1000, &due_date, &Some(Address::generate(&env)), &terms_hash(&env));
+            let (_, topics, _) = env.events().all().last().unwrap();
+            let (name, loan_id, topic_party) = <(Symbol, u64, Address)>::try_from_val(&env, &topics.to_val()).unwrap();
+            assert_eq!(name, Symbol::new(&env, "loan_created"));
+            if topic_party == borrower {
+                discovered.push(loan_id);
+            }
+        }
+
+        // The same loans the on-chain fallback lists
+        let listed: std::vec::Vec<u64> = client.get_loans_by_borrower(&borrower).iter().collect();
+        assert_eq!(discovered, listed);
+        assert_eq!(listed.len(), 2);
+    }
+
+    #[test]
+    fn test_share_math_bootstrap_and_large_amounts() {
+        use crate::shares::{amount_for_shares, shares_for_deposit, shares_for_withdrawal};
+
+        // The first depositor into an empty pool gets shares 1:1, however large the deposit
+        assert_eq!(shares_for_deposit(1000, 0, 0), 1000);
+        assert_eq!(shares_for_deposit(i128::MAX - 1, 0, 0), i128::MAX - 1);
+
+        // Near-max values whose naive product overflows i128 still convert exactly
+        let half = i128::MAX / 2;
+        assert_eq!(shares_for_deposit(half, half, half), half);
+        assert_eq!(amount_for_shares(half, half, half), half);
+        assert_eq!(shares_for_deposit(1_000_000_000_000_000_000_000_000_000_000, 100_000_000_000_000_000_000, 200_000_000_000_000_000_000), 500_000_000_000_000_000_000_000_000_000);
+
+        // Ordinary proportional conversion, rounding down both ways
+        assert_eq!(shares_for_deposit(100, 300, 400), 75);
+        assert_eq!(amount_for_shares(1, 3, 10), 3);
+        assert_eq!(amount_for_shares(0, 0, 0), 0);
+
+        // Withdrawals burn shares rounded up, so no one redeems value for less than it costs
+        assert_eq!(shares_for_withdrawal(10, 3, 10), 3);
+        assert_eq!(shares_for_withdrawal(4, 3, 10), 2);
+        assert_eq!(shares_for_withdrawal(half, half, half), half);
+    }
+
+    #[test]
+    fn test_later_depositor_gets_fewer_shares_after_yield() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = testutils::register_token(&env, &admin);
+        client.init(&admin, &token_address);
+
+        // The first LP gets shares 1:1
+        let early = Address::generate(&env);
+        let late = Address::generate(&env);
+        testutils::mint(&env, &token_address, &early, 1000);
+        testutils::mint(&env, &token_address, &late, 1050);
+        client.deposit(&early, &1000);
+        assert_eq!(client.get_lp_shares(&early), 1000);
+
+        // A year of 5% on 1000 earns the LPs 50
+        let year = 31_536_000;
+        let borrower = Address::generate(&env);
+        let loan_id = client.create_loan(&borrower, &1, &1000, &1000, &year, &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, loan_id);
+        env.ledger().with_mut(|li| li.timestamp = year);
+        testutils::mint(&env, &token_address, &borrower, 1050);
+        client.repay_loan(&loan_id, &None);
+        assert_eq!(client.get_lp_value(&early), 1050);
+
+        // Shares now cost 1.05, so the same money buys fewer of them and the yield stays with the early LP
+        client.deposit(&late, &1050);
+        assert_eq!(client.get_lp_shares(&late), 1000);
+        assert_eq!(client.get_total_shares(), 2000);
+        assert_eq!(client.get_lp_value(&late), 1050);
+        assert_eq!(client.get_lp_value(&early), 1050);
+
+        // Each can withdraw what their shares are worth, and no more
+        assert!(client.try_withdraw(&early, &1051).is_err());
+        client.withdraw(&early, &1050);
+        assert_eq!(testutils::balance(&env, &token_address, &early), 1050);
+        assert_eq!(client.get_lp_shares(&early), 0);
+        assert_eq!(client.get_lp_deposit(&early), 0);
+        assert_eq!(client.get_lp_value(&late), 1050);
+        client.withdraw(&late, &1050);
+        assert_eq!(client.get_total_shares(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "Pool has no value")]
+    fn test_share_math_rejects_deposit_into_worthless_pool() {
+        crate::shares::shares_for_deposit(1000, 500, 0);
+    }
+
+    #[test]
+    fn test_get_pledged_invoices() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+        let invoice_contract = env.register_contract(None, InvoiceContract);
+        let invoice_client = InvoiceContractClient::new(&env, &invoice_contract);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+        invoice_client.init(&admin, &token_address);
+        client.set_invoice_contract(&invoice_contract);
+        invoice_client.set_lending_pool(&contract_id);
+        client.set_release_ratio(&2000);
+
+        let borrower = Address::generate(&env);
+        token::StellarAssetClient::new(&env, &token_address).mint(&borrower, &3000);
+        let due_date = env.ledger().timestamp() + 86400;
+        let mut loan_ids = std::vec::Vec::new();
+        for invoice_id in 1..=3u64 {
+            seed_invoice(&env, &invoice_contract, invoice_id, &borrower, 1000, due_date);
+            let loan_id = client.create_loan(&borrower, &invoice_id, &1000, &1000, &due_date, &None, &terms_hash(&env));
+            disburse_loan(&env, &client, &token_address, loan_id);
+            loan_ids.push(loan_id);
+        }
+        assert_eq!(client.get_pledged_invoices(&0, &10), vec![&env, 1, 2, 3]);
+        assert_eq!(client.get_pledged_invoices(&1, &1), vec![&env, 2]);
+
+        // Repaying unpledges the invoice, as does releasing collateral early
+        client.repay_loan(&loan_ids[0], &None);
+        client.repay_partial(&loan_ids[2], &850);
+        client.release_collateral(&loan_ids[2]);
+        assert_eq!(client.get_pledged_invoices(&0, &10), vec![&env, 2]);
+        assert!(client.try_get_pledged_invoices(&0, &51).is_err());
+    }
+
+    // Defaults one loan into bad debt, then has a liquidator cover a second through liquidate or liquidate_partial,
+    // returning (bad debt, liquidator's spend)
+    fn liquidate_with_recovery(recovery_bps: u32, full: bool) -> (i128, i128) {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+        client.set_interest_recovery(&recovery_bps);
+
+        let liquidator = Address::generate(&env);
+        token::StellarAssetClient::new(&env, &token_address).mint(&liquidator, &2_000_000);
+        let due_date = env.ledger().timestamp() + 31_536_000;
+        let lost = client.create_loan(&Address::generate(&env), &1, &2_000_000, &1_000_000, &due_date, &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, lost);
+        let covered = client.create_loan(&Address::generate(&env), &2, &2_000_000, &1_000_000, &due_date, &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, covered);
+
+        env.ledger().with_mut(|li| li.timestamp = due_date + 1);
+        client.sweep_defaults(&vec![&env, lost]);
+        if full {
+            client.liquidate(&liquidator, &covered);
+        } else {
+            client.liquidate_partial(&liquidator, &covered, &1_000_000);
+        }
+        (client.get_bad_debt(), 2_000_000 - token::Client::new(&env, &token_address).balance(&liquidator))
+    }
+
+    #[test]
+    fn test_liquidation_interest_recovery_reduces_bad_debt() {
+        for full in [false, true] {
+            // Principal-only liquidation leaves the defaulted loan's loss untouched
+            let (bad_debt, spent) = liquidate_with_recovery(0, full);
+            assert_eq!(bad_debt, 1_000_000);
+            assert_eq!(spent, 1_000_000);
+
+            // Recovering half the covered loan's year of 5% interest writes the loss down by that much
+            let (bad_debt, spent) = liquidate_with_recovery(5000, full);
+            assert_eq!(spent, 1_025_000);
+            assert_eq!(bad_debt, 975_000);
+        }
+    }
+
+    #[test]
+    fn test_repaid_loan_is_archived_as_summary() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = testutils::register_token(&env, &admin);
+        client.init(&admin, &token_address);
+
+        let borrower = Address::generate(&env);
+        testutils::mint(&env, &token_address, &borrower, 2000);
+        let year = 31_536_000;
+        let loan_id = client.create_loan(&borrower, &1, &1000, &1000, &(2 * year), &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, loan_id);
+        assert!(client.get_loan_summary(&loan_id).is_none());
+
+        // 25 interest paid along the way, then 22 more settled with the final partial payment
+        env.ledger().with_mut(|li| li.timestamp = year / 2);
+        client.repay_partial(&loan_id, &125);
+        env.ledger().with_mut(|li| li.timestamp = year);
+        client.repay_partial(&loan_id, &922);
+
+        assert!(client.get_loan(&loan_id).is_none());
+        assert_eq!(client.get_total_owed(&loan_id), 0);
+        let summary = client.get_loan_summary(&loan_id).unwrap();
+        assert_eq!(summary, LoanSummary {
+            id: loan_id,
+            borrower: borrower.clone(),
+            principal: 1000,
+            interest_paid: 47,
+            closed_at: year,
+        });
+        env.as_contract(&contract_id, || {
+            assert!(!env.storage().instance().has(&DataKey::InterestPaid(loan_id)));
+        });
+
+        // The archive still tells a repaid loan apart from one that never existed
+        assert!(client.try_repay_loan(&loan_id, &None).is_err());
+        assert!(client.try_repay_partial(&loan_id, &10).is_err());
+        assert!(client.get_loan_summary(&99).is_none());
+    }
+
+    #[test]
+    fn test_large_loans_need_admin_approval() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = testutils::register_token(&env, &admin);
+        client.init(&admin, &token_address);
+        testutils::mint(&env, &token_address, &contract_id, 10_000);
+        client.set_auto_approve_limit(&1000);
+        assert!(client.try_set_auto_approve_limit(&-1).is_err());
+
+        // A loan at the limit is active straight away
+        let borrower = Address::generate(&env);
+        let due_date = 86_400;
+        let small = client.create_loan(&borrower, &1, &2000, &1000, &due_date, &None, &terms_hash(&env));
+        assert!(!client.is_pending_approval(&small));
+        assert!(client.try_approve_loan(&small).is_err());
+        client.disburse(&small, &None);
+        assert_eq!(testutils::balance(&env, &token_address, &borrower), 1000);
+
+        // A larger one can't be drawn until the admin approves it
+        let large = client.create_loan(&borrower, &2, &4000, &3000, &due_date, &None, &terms_hash(&env));
+        assert!(client.is_pending_approval(&large));
+        assert!(client.try_disburse(&large, &None).is_err());
+
+        client.approve_loan(&large);
+        let (_, topics, _) = env.events().all().last().unwrap();
+        let (name, loan_id, party) = <(Symbol, u64, Address)>::try_from_val(&env, &topics.to_val()).unwrap();
+        assert_eq!((name, loan_id, party), (Symbol::new(&env, "loan_approved"), large, borrower.clone()));
+        assert!(!client.is_pending_approval(&large));
+        client.disburse(&large, &None);
+        assert_eq!(testutils::balance(&env, &token_address, &borrower), 4000);
+        assert!(client.try_approve_loan(&large).is_err());
+    }
+
+    #[test]
+    fn test_early_repay_discount() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = testutils::register_token(&env, &admin);
+        client.init(&admin, &token_address);
+        client.set_early_repay_discount(&2000);
+        assert!(client.try_set_early_repay_discount(&10_001).is_err());
+
+        let year = 31_536_000;
+        let early = Address::generate(&env);
+        let on_time = Address::generate(&env);
+        testutils::mint(&env, &token_address, &early, 1100);
+        testutils::mint(&env, &token_address, &on_time, 1100);
+        let early_loan = client.create_loan(&early, &1, &1000, &1000, &year, &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, early_loan);
+        let on_time_loan = client.create_loan(&on_time, &2, &1000, &1000, &(year / 2), &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, on_time_loan);
+
+        // Half a year at 5% on 1000 is 25 interest: 20% is waived before the due date, none of it on the day
+        env.ledger().with_mut(|li| li.timestamp = year / 2);
+        let receipt = client.repay_loan(&early_loan, &None);
+        assert_eq!(receipt.interest_paid, 20);
+        assert_eq!(receipt.total_paid, 1020);
+        assert_eq!(client.get_loan_summary(&early_loan).unwrap().interest_paid, 20);
+
+        let receipt = client.repay_loan(&on_time_loan, &None);
+        assert_eq!(receipt.interest_paid, 25);
+        assert_eq!(receipt.total_paid, 1025);
+
+        // Even a full discount leaves the principal to pay
+        client.set_early_repay_discount(&10_000);
+        let borrower = Address::generate(&env);
+        testutils::mint(&env, &token_address, &borrower, 1000);
+        let loan_id = client.create_loan(&borrower, &3, &1000, &1000, &(2 * year), &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, loan_id);
+        env.ledger().with_mut(|li| li.timestamp = year);
+        let receipt = client.repay_loan(&loan_id, &None);
+        assert_eq!((receipt.principal_paid, receipt.interest_paid, receipt.total_paid), (1000, 0, 1000));
+    }
+
+    #[test]
+    fn test_unique_borrower_count() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = testutils::register_token(&env, &admin);
+        client.init(&admin, &token_address);
+        assert_eq!(client.get_unique_borrower_count(), 0);
+
+        let due_date = 86_400;
+        let repeat = Address::generate(&env);
+        let first = client.create_loan(&repeat, &1, &1000, &500, &due_date, &None, &terms_hash(&env));
+        client.create_loan(&repeat, &2, &1000, &500, &due_date, &None, &terms_hash(&env));
+        assert_eq!(client.get_unique_borrower_count(), 1);
+
+        // Cancelling doesn't make a returning borrower count as new
+        client.cancel_loan(&repeat, &first);
+        client.create_loan(&repeat, &3, &1000, &500, &due_date, &None, &terms_hash(&env));
+        assert_eq!(client.get_unique_borrower_count(), 1);
+
+        for invoice_id in 4..7u64 {
+            client.create_loan(&Address::generate(&env), &invoice_id, &1000, &500, &due_date, &None, &terms_hash(&env));
+        }
+        assert_eq!(client.get_unique_borrower_count(), 4);
+    }
+
+    #[test]
+    fn test_reserve_withdrawal_never_touches_lp_principal() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = testutils::register_token(&env, &admin);
+        client.init(&admin, &token_address);
+        client.set_protocol_share(&2000);
+
+        let lp = Address::generate(&env);
+        testutils::mint(&env, &token_address, &lp, 5000);
+        client.deposit(&lp, &5000);
+
+        // A one-year loan pays 50 interest, 10 of it to the treasury
+        let year = 31_536_000;
+        let borrower = Address::generate(&env);
+        testutils::mint(&env, &token_address, &borrower, 50);
+        let loan_id = client.create_loan(&borrower, &1, &2000, &1000, &year, &None, &terms_hash(&env));
+        client.disburse(&loan_id, &None);
+        env.ledger().with_mut(|li| li.timestamp = year);
+        client.repay_loan(&loan_id, &None);
+        assert_eq!(client.get_treasury_balance(), 10);
+
+        let operations = Address::generate(&env);
+        client.withdraw_reserve(&operations, &6);
+        let (_, topics, data) = env.events().all().last().unwrap();
+        let (name, to) = <(Symbol, Address)>::try_from_val(&env, &topics.to_val()).unwrap();
+        assert_eq!((name, to), (Symbol::new(&env, "reserve_withdrawn"), operations.clone()));
+        assert_eq!(i128::try_from_val(&env, &data).unwrap(), 6);
+
+        // The pool holds thousands, but only the 4 left in the treasury may be drawn
+        assert!(client.try_withdraw_reserve(&operations, &5).is_err());
+        assert!(client.try_withdraw_reserve(&operations, &0).is_err());
+        client.withdraw_reserve(&operations, &4);
+        assert_eq!(testutils::balance(&env, &token_address, &operations), 10);
+
+        // LPs keep their principal
+        client.withdraw(&lp, &5000);
+        assert_eq!(testutils::balance(&env, &token_address, &lp), 5000);
+    }
+
+    #[test]
+    fn test_error_message_codes() {
+        let env = Env::default();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        // Codes are part of the public interface, so check the raw numbers clients see
+        assert_eq!(client.error_message(&1), Symbol::new(&env, "not_initialized"));
+        assert_eq!(client.error_message(&4), Symbol::new(&env, "loan_not_found"));
+        assert_eq!(client.error_message(&8), Symbol::new(&env, "loan_healthy"));
+        assert_eq!(client.error_message(&9), Symbol::new(&env, "unauthorized"));
+        assert_eq!(client.error_message(&0), Symbol::new(&env, "unknown_error"));
+        assert_eq!(client.error_message(&10), Symbol::new(&env, "unknown_error"));
+    }
+
+    #[test]
+    fn test_loan_must_not_outlive_its_invoice() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+        let invoice_contract = env.register_contract(None, InvoiceContract);
+        let invoice_client = InvoiceContractClient::new(&env, &invoice_contract);
+
+        let admin = Address::generate(&env);
+        let token_address = testutils::register_token(&env, &admin);
+        client.init(&admin, &token_address);
+        invoice_client.init(&admin, &token_address);
+        client.set_invoice_contract(&invoice_contract);
+        invoice_client.set_lending_pool(&contract_id);
+
+        let day = 86_400;
+        let borrower = Address::generate(&env);
+        testutils::mint(&env, &token_address, &borrower, 1000);
+        seed_invoice(&env, &invoice_contract, 1, &borrower, 1000, 30 * day);
+        seed_invoice(&env, &invoice_contract, 2, &borrower, 1000, day);
+
+        // A loan may run up to the invoice's maturity but not past it
+        assert!(client.try_create_loan(&borrower, &1, &1000, &800, &(30 * day + 1), &None, &terms_hash(&env)).is_err());
+        let loan_id = client.create_loan(&borrower, &1, &1000, &800, &(20 * day), &None, &terms_hash(&env));
+
+        // Only a disbursed loan can be extended, as an undisbursed one may still be cancelled
+        assert!(client.try_extend_loan(&loan_id, &day).is_err());
+        disburse_loan(&env, &client, &token_address, loan_id);
+        assert!(client.try_extend_loan(&loan_id, &(10 * day + 1)).is_err());
+        client.extend_loan(&loan_id, &(10 * day));
+        assert_eq!(client.get_loan(&loan_id).unwrap().due_date, 30 * day);
+
+        // An invoice past its due date can't back a new loan
+        env.ledger().with_mut(|li| li.timestamp = day + 1);
+        assert!(client.try_create_loan(&borrower, &2, &1000, &800, &(2 * day), &None, &terms_hash(&env)).is_err());
+        assert_eq!(invoice_client.get_invoice_status(&2), InvoiceStatus::Expired);
+    }
+
+    #[test]
+    fn test_min_liquidation_amount() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = testutils::register_token(&env, &admin);
+        client.init(&admin, &token_address);
+        client.set_min_liquidation_amount(&100);
+        assert_eq!(client.get_min_liquidation_amount(), 100);
+        assert!(client.try_set_min_liquidation_amount(&-1).is_err());
+
+        let liquidator = Address::generate(&env);
+        testutils::mint(&env, &token_address, &liquidator, 1000);
+        let due_date = 86_400;
+        let loan_id = client.create_loan(&Address::generate(&env), &1, &2000, &1000, &due_date, &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, loan_id);
+        env.ledger().with_mut(|li| li.timestamp = due_date + 1);
+
+        // Dust is turned away, while the minimum itself is fine
+        assert!(client.try_liquidate_partial(&liquidator, &loan_id, &99).is_err());
+        client.liquidate_partial(&liquidator, &loan_id, &100);
+        client.liquidate_partial(&liquidator, &loan_id, &850);
+        assert_eq!(client.get_loan(&loan_id).unwrap().remaining_principal, 50);
+
+        // A remainder below the minimum can still be cleared in one go
+        client.liquidate_partial(&liquidator, &loan_id, &50);
+        assert!(client.get_loan(&loan_id).unwrap().is_defaulted);
+    }
+
+    #[test]
+    fn test_total_accrued_interest() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = testutils::register_token(&env, &admin);
+        client.init(&admin, &token_address);
+
+        let year = 31_536_000;
+        let borrower = Address::generate(&env);
+        let mut loan_ids = std::vec::Vec::new();
+        for (invoice_id, principal) in [(1u64, 1000i128), (2, 2500), (3, 4000)] {
+            loan_ids.push(client.create_loan(&borrower, &invoice_id, &(2 * principal), &principal, &(2 * year), &None, &terms_hash(&env)));
+            env.ledger().with_mut(|li| li.timestamp += year / 12);
+        }
+        let expected = || -> i128 {
+            loan_ids.iter()
+                .map(|loan_id| client.get_total_owed(loan_id) - client.get_loan(loan_id).unwrap().remaining_principal)
+                .sum()
+        };
+        assert!(expected() > 0);
+        assert_eq!(client.total_accrued_interest(&0, &10), expected());
+
+        // Later on, paging splits the same larger total
+        env.ledger().with_mut(|li| li.timestamp = year);
+        assert_eq!(client.total_accrued_interest(&0, &2) + client.total_accrued_interest(&2, &2), expected());
+        assert_eq!(client.total_accrued_interest(&0, &10), expected());
+        assert_eq!(client.total_accrued_interest(&3, &10), 0);
+    }
+
+    #[test]
+    fn test_overdue_repayment_accrues_penalty_interest() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = testutils::register_token(&env, &admin);
+        client.init(&admin, &token_address);
+        let year = 31_536_000;
+        client.set_penalty_rate(&2000);
+        // A long grace period keeps the overdue loan out of liquidation, so it is still repaid normally
+        client.set_grace_period(&year);
+
+        let on_time = Address::generate(&env);
+        let late = Address::generate(&env);
+        testutils::mint(&env, &token_address, &on_time, 2000);
+        testutils::mint(&env, &token_address, &late, 2000);
+        let on_time_loan = client.create_loan(&on_time, &1, &2000, &1000, &(year / 2), &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, on_time_loan);
+        let late_loan = client.create_loan(&late, &2, &2000, &1000, &(year / 2), &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, late_loan);
+
+        // Half a year at 5% on 1000 is 25 interest when paid on the due date
+        env.ledger().with_mut(|li| li.timestamp = year / 2);
+        assert_eq!(client.repay_loan(&on_time_loan, &None).total_paid, 1025);
+
+        // Half a year overdue adds 20% a year on 1000 for that half, instead of the 5% base rate
+        env.ledger().with_mut(|li| li.timestamp = year);
+        assert_eq!(client.interest_per_second(&late_loan), 1000 * 2000 * 1_000_000_000_000 / (10_000 * year as i128));
+        assert_eq!(client.get_total_owed(&late_loan), 1125);
+        let receipt = client.repay_loan(&late_loan, &None);
+        assert_eq!((receipt.interest_paid, receipt.total_paid), (125, 1125));
+        assert_eq!(testutils::balance(&env, &token_address, &late), 875);
+    }
+
+    #[test]
+    fn test_full_liquidation_respects_liquidator_whitelist() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = testutils::register_token(&env, &admin);
+        client.init(&admin, &token_address);
+        client.set_liquidator_whitelist(&true);
+
+        let listed = Address::generate(&env);
+        let outsider = Address::generate(&env);
+        testutils::mint(&env, &token_address, &listed, 1000);
+        testutils::mint(&env, &token_address, &outsider, 1000);
+        client.add_liquidator(&listed);
+
+        let due_date = 86_400;
+        let loan_id = client.create_loan(&Address::generate(&env), &1, &2000, &1000, &due_date, &None, &terms_hash(&env));
+        disburse_loan(&env, &client, &token_address, loan_id);
+        env.ledger().with_mut(|li| li.timestamp = due_date + 1);
+
+        // Unlisted and blocked callers are turned away, and only the listed liquidator pays in
+        assert!(client.try_liquidate(&outsider, &loan_id).is_err());
+        client.set_blocked(&listed, &true);
+        assert!(client.try_liquidate(&listed, &loan_id).is_err());
+        client.set_blocked(&listed, &false);
+        client.liquidate(&listed, &loan_id);
+        assert!(client.get_loan(&loan_id).unwrap().is_defaulted);
+        assert_eq!(testutils::balance(&env, &token_address, &listed), 0);
+        assert_eq!(testutils::balance(&env, &token_address, &outsider), 1000);
+    }
+
+    #[test]
+    fn test_loan_rejects_invoice_pledged_by_non_owner() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+        let invoice_contract = env.register_contract(None, InvoiceContract);
+        let invoice_client = InvoiceContractClient::new(&env, &invoice_contract);
+
+        let admin = Address::generate(&env);
+        let token_address = env.register_stellar_asset_contract_v2(admin.clone()).address();
+        client.init(&admin, &token_address);
+        invoice_client.init(&admin, &token_address);
+        client.set_invoice_contract(&invoice_contract);
+        invoice_client.set_lending_pool(&contract_id);
+
+        let owner = Address::generate(&env);
+        let due_date = env.ledger().timestamp() + 86400;
+        seed_invoice(&env, &invoice_contract, 1, &owner, 1000, due_date);
+
+        // Someone else can't borrow against the owner's invoice, and it stays unlocked
+        let stranger = Address::generate(&env);
+        assert!(client.try_create_loan(&stranger, &1, &1000, &500, &due_date, &None, &terms_hash(&env)).is_err());
+        assert_eq!(invoice_client.get_invoice_status(&1), InvoiceStatus::Active);
+
+        client.create_loan(&owner, &1, &1000, &500, &due_date, &None, &terms_hash(&env));
+        assert_eq!(invoice_client.get_invoice_status(&1), InvoiceStatus::Locked);
+    }
+
+    #[test]
+    fn test_undisbursed_loan_cannot_be_repaid_or_defaulted() {
+        let env = Env::default();
+        env.mock_all_auths();
+        let contract_id = env.register_contract(None, LendingPool);
+        let client = LendingPoolClient::new(&env, &contract_id);
+
+        let admin = Address::generate(&env);
+        let token_address = testutils::register_token(&env, &admin);
+        client.init(&admin, &token_address);
+
+        let lp = Address::generate(&env);
+        let borrower = Address::generate(&env);
+        let liquidator = Address::generate(&env);
+        testutils::mint(&env, &token_address, &lp, 1000);
+        testutils::mint(&env, &token_address, &borrower, 2000);
+        testutils::mint(&env, &token_address, &liquidator, 2000);
+        client.deposit(&lp, &1000);
+
+        // The principal is still idle in the pool, so it isn't counted a second time as lent out
+        let due_date = env.ledger().timestamp() + 86400;
+        let loan_id = client.create_loan(&borrower, &1, &2000, &1000, &due_date, &None, &terms_hash(&env));
+        assert_eq!(client.get_solvency_ratio(), 10_000);
+
+        assert!(client.try_repay_loan(&loan_id, &None).is_err());
+        assert!(client.try_repay_partial(&loan_id, &500).is_err());
+
+        env.ledger().with_mut(|li| li.timestamp = due_date + 1);
+        assert!(client.try_liquidate(&liquidator, &loan_id).is_err());
+        assert!(client.try_liquidate_partial(&liquidator, &loan_id, &500).is_err());
+        assert_eq!(client.sweep_defaults(&vec![&env, loan_id]).len(), 0);
+
+        let loan = client.get_loan(&loan_id).unwrap();
+        assert!(!loan.is_repaid && !loan.is_defaulted);
+        assert_eq!(client.get_bad_debt(), 0);
+        assert_eq!(testutils::balance(&env, &token_address, &borrower), 2000);
+    }
 }
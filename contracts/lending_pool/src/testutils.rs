@@ -0,0 +1,22 @@
+// Deterministic helpers for exercising the pool in tests: a mock token, balances and ledger time
+use soroban_sdk::{testutils::Ledger, token, Address, Env};
+
+// Registers a Stellar Asset Contract to stand in for the pool token
+pub fn register_token(env: &Env, admin: &Address) -> Address {
+    env.register_stellar_asset_contract_v2(admin.clone()).address()
+}
+
+// Mints token to an address (the token admin's auth must be mocked)
+pub fn mint(env: &Env, token: &Address, to: &Address, amount: i128) {
+    token::StellarAssetClient::new(env, token).mint(to, &amount);
+}
+
+// Reads an address's token balance
+pub fn balance(env: &Env, token: &Address, owner: &Address) -> i128 {
+    token::Client::new(env, token).balance(owner)
+}
+
+// Moves the ledger clock forward by a number of seconds
+pub fn advance_time(env: &Env, seconds: u64) {
+    env.ledger().with_mut(|li| li.timestamp += seconds);
+}